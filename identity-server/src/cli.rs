@@ -1,7 +1,19 @@
+use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::crypto::{generate_activation_code, generate_key};
-use crate::db::DbPool;
+use rusqlite::types::Value;
+use rusqlite::{Connection, ErrorCode, params_from_iter};
+use serde::Deserialize;
+use serde_json::json;
+use support_common::{FULL_ACCESS_SCOPE, parse_scopes};
+
+use crate::crypto::{
+    GeneratedActivationCode, KeyConfig, generate_activation_code, generate_key,
+    generate_totp_secret, totp_uri,
+};
+use crate::db::{self, DbPool};
+use crate::email::{EmailService, Templates};
+use crate::handlers;
 
 fn now_timestamp() -> i64 {
     SystemTime::now()
@@ -10,37 +22,271 @@ fn now_timestamp() -> i64 {
         .as_secs() as i64
 }
 
-pub fn create_user(db: &DbPool, email: &str, role: &str) -> Result<i64, String> {
-    let conn = db.lock().unwrap();
-    let now = now_timestamp();
+/// Which rows `list-keys` and `list-activation-codes` print.
+pub struct ListFilter<S> {
+    pub user_id: Option<i64>,
+    pub status: Option<S>,
+    pub limit: Option<i64>,
+    pub offset: i64,
+}
 
-    conn.execute(
-        "INSERT INTO users (email, role, subscription_status, created_at) VALUES (?, ?, 'active', ?)",
-        rusqlite::params![email, role, now],
-    )
-    .map_err(|e| e.to_string())?;
+impl<S> ListFilter<S> {
+    /// Completes `select` with the `conditions` and the page, ordered by
+    /// `order_by`, and appends the page's parameters to `params`.
+    fn query(
+        &self,
+        select: &str,
+        conditions: &[&str],
+        order_by: &str,
+        params: &mut Vec<Value>,
+    ) -> String {
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+        // SQLite reads a negative LIMIT as no limit
+        params.push(Value::Integer(self.limit.unwrap_or(-1)));
+        params.push(Value::Integer(self.offset));
+        format!(
+            "{} {} ORDER BY {} LIMIT ? OFFSET ?",
+            select, where_clause, order_by
+        )
+    }
+}
+
+/// How the list commands print their rows
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Fixed-width columns for reading
+    Table,
+    /// A JSON array of objects with every column, for scripts
+    Json,
+}
+
+fn print_json(rows: Vec<serde_json::Value>) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(&rows).map_err(|e| e.to_string())?;
+    println!("{}", json);
+    Ok(())
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum KeyStatus {
+    Active,
+    Revoked,
+    Expired,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum CodeStatus {
+    Available,
+    Used,
+    Expired,
+}
+
+/// Runs `f` in a transaction that is only committed without `dry_run`. A dry
+/// run prints the same output, including generated keys and codes, but rolls
+/// everything back.
+fn in_transaction<T>(
+    db: &DbPool,
+    dry_run: bool,
+    f: impl FnOnce(&Connection) -> Result<T, String>,
+) -> Result<T, String> {
+    let mut conn = db.get().map_err(|e| e.to_string())?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    if dry_run {
+        println!("DRY RUN - nothing below is saved\n");
+    }
+
+    let result = f(&tx)?;
+
+    if dry_run {
+        tx.rollback().map_err(|e| e.to_string())?;
+        println!("\nDRY RUN - rolled back, the database is unchanged");
+    } else {
+        tx.commit().map_err(|e| e.to_string())?;
+    }
+    Ok(result)
+}
 
-    let id = conn.last_insert_rowid();
+pub fn create_user(db: &DbPool, email: &str, role: &str, dry_run: bool) -> Result<i64, String> {
+    in_transaction(db, dry_run, |conn| insert_user(conn, email, role))
+}
+
+fn insert_user(conn: &Connection, email: &str, role: &str) -> Result<i64, String> {
+    let id = store_user(conn, email, role).map_err(|e| e.to_string())?;
     println!("Created user '{}' with id {}", email, id);
     Ok(id)
 }
 
-pub fn create_key(db: &DbPool, user_id: i64) -> Result<String, String> {
-    let conn = db.lock().unwrap();
+fn store_user(conn: &Connection, email: &str, role: &str) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO users (email, role, subscription_status, created_at) VALUES (?, ?, 'active', ?)",
+        rusqlite::params![email, role, now_timestamp()],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Roles accepted by `create-user` and `import-users`
+const ROLES: &[&str] = &["admin", "support", "customer"];
+
+#[derive(Deserialize)]
+struct ImportRow {
+    email: String,
+    role: String,
+}
+
+/// Creates a user for every row of a CSV file with an `email,role` header.
+/// Each row is its own transaction, so bad rows are reported and skipped
+/// without affecting the others. With `with_codes`, each new user also gets an
+/// activation code, printed next to the row.
+pub fn import_users(
+    db: &DbPool,
+    path: &Path,
+    with_codes: bool,
+    code_ttl_secs: i64,
+) -> Result<(), String> {
+    let mut reader = csv::Reader::from_path(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut conn = db.get().map_err(|e| e.to_string())?;
+
+    let (mut imported, mut failed) = (0, 0);
+    for (i, row) in reader.deserialize::<ImportRow>().enumerate() {
+        // Line 1 is the header
+        let line = i + 2;
+        let result = row
+            .map_err(|e| e.to_string())
+            .and_then(|row| import_user(&mut conn, &row, with_codes, code_ttl_secs));
+        match result {
+            Ok(message) => {
+                imported += 1;
+                println!("Line {}: {}", line, message);
+            }
+            Err(e) => {
+                failed += 1;
+                println!("Line {}: FAILED - {}", line, e);
+            }
+        }
+    }
 
+    println!("\nImported {} user(s), {} failed", imported, failed);
+    Ok(())
+}
+
+fn import_user(
+    conn: &mut Connection,
+    row: &ImportRow,
+    with_codes: bool,
+    code_ttl_secs: i64,
+) -> Result<String, String> {
+    let email = row.email.trim().to_lowercase();
+    let role = row.role.trim();
+    if !handlers::is_valid_email(&email) {
+        return Err(format!("invalid email '{}'", row.email));
+    }
+    if !ROLES.contains(&role) {
+        return Err(format!(
+            "unknown role '{}', expected one of {}",
+            role,
+            ROLES.join(", ")
+        ));
+    }
+
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let id = store_user(&tx, &email, role).map_err(|e| match e {
+        rusqlite::Error::SqliteFailure(err, _) if err.code == ErrorCode::ConstraintViolation => {
+            format!("{} already exists", email)
+        }
+        e => e.to_string(),
+    })?;
+    let mut message = format!("created {} ({}) with id {}", email, role, id);
+    if with_codes {
+        let (code, _) = store_activation_code(&tx, id, code_ttl_secs)?;
+        message.push_str(&format!(", activation code {}", code.full_code));
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+
+    Ok(message)
+}
+
+pub fn set_subscription(db: &DbPool, user_id: i64, status: &str) -> Result<(), String> {
+    let conn = db.get().map_err(|e| e.to_string())?;
+
+    let rows = conn
+        .execute(
+            "UPDATE users SET subscription_status = ? WHERE id = ? AND deleted_at IS NULL",
+            rusqlite::params![status, user_id],
+        )
+        .map_err(|e| e.to_string())?;
+
+    if rows == 0 {
+        Err(format!("User {} not found", user_id))
+    } else {
+        println!("Set subscription of user {} to '{}'", user_id, status);
+        Ok(())
+    }
+}
+
+/// Creates a key for the user, limited to `scopes` or with full access if there
+/// are none; with `ttl_days` it stops validating after that many days.
+pub fn create_key(
+    db: &DbPool,
+    key_config: &KeyConfig,
+    user_id: i64,
+    ttl_days: Option<i64>,
+    scopes: &[String],
+    dry_run: bool,
+) -> Result<String, String> {
+    let scopes = if scopes.is_empty() {
+        FULL_ACCESS_SCOPE.to_string()
+    } else {
+        scopes.join(",")
+    };
+    in_transaction(db, dry_run, |conn| {
+        insert_key(conn, key_config, user_id, ttl_days, &scopes)
+    })
+}
+
+fn insert_key(
+    conn: &Connection,
+    key_config: &KeyConfig,
+    user_id: i64,
+    ttl_days: Option<i64>,
+    scopes: &str,
+) -> Result<String, String> {
     // Verify user exists
     let email: String = conn
-        .query_row("SELECT email FROM users WHERE id = ?", [user_id], |row| {
-            row.get(0)
-        })
+        .query_row(
+            "SELECT email FROM users WHERE id = ? AND deleted_at IS NULL",
+            [user_id],
+            |row| row.get(0),
+        )
         .map_err(|_| format!("User {} not found", user_id))?;
 
-    let key = generate_key();
+    let key = generate_key(key_config);
     let now = now_timestamp();
+    let expires_at = ttl_days.map(|days| now + days * 24 * 60 * 60);
 
     conn.execute(
-        "INSERT INTO api_keys (key_hash, key_prefix, user_id, created_at) VALUES (?, ?, ?, ?)",
-        rusqlite::params![key.hash, key.prefix, user_id, now],
+        "INSERT INTO api_keys (key_hash, key_salt, hash_version, key_prefix, user_id, created_at, expires_at, scopes)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        rusqlite::params![
+            key.hash,
+            key.salt,
+            key.hash_version as i64,
+            key.prefix,
+            user_id,
+            now,
+            expires_at,
+            scopes
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    db::audit(
+        conn,
+        "key.created",
+        user_id,
+        &format!("prefix={} via=cli", key.prefix),
     )
     .map_err(|e| e.to_string())?;
 
@@ -49,202 +295,672 @@ pub fn create_key(db: &DbPool, user_id: i64) -> Result<String, String> {
     println!("Key:    {}", key.full_key);
     println!("Prefix: {}", key.prefix);
     println!("User:   {} (id={})", email, user_id);
+    println!("Scopes: {}", scopes);
+    if let Some(expires_at) = expires_at {
+        println!("Expires: {}", expires_at);
+    }
     println!("==============================================");
 
     Ok(key.full_key)
 }
 
-pub fn revoke_key(db: &DbPool, prefix: &str) -> Result<(), String> {
-    let conn = db.lock().unwrap();
+pub fn revoke_key(db: &DbPool, prefix: &str, dry_run: bool) -> Result<(), String> {
+    in_transaction(db, dry_run, |conn| {
+        let revoked = db::revoke_key(conn, prefix, None, "cli").map_err(|e| e.to_string())?;
+        if revoked == 0 {
+            return Err(format!("No active key found with prefix {}", prefix));
+        }
+
+        println!("Revoked key with prefix {}", prefix);
+        Ok(())
+    })
+}
+
+/// Revokes all of the user's active keys and issues one fresh key in the same
+/// transaction, e.g. when a shared device is offboarded. The new key gets
+/// `scopes`, or if there are none the scopes of the keys it replaces.
+pub fn rotate_keys(
+    db: &DbPool,
+    key_config: &KeyConfig,
+    user_id: i64,
+    scopes: &[String],
+    dry_run: bool,
+) -> Result<String, String> {
+    in_transaction(db, dry_run, |conn| {
+        // Verify user exists
+        conn.query_row(
+            "SELECT 1 FROM users WHERE id = ? AND deleted_at IS NULL",
+            [user_id],
+            |_| Ok(()),
+        )
+        .map_err(|_| format!("User {} not found", user_id))?;
+
+        let keys: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM api_keys WHERE user_id = ?",
+                [user_id],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        if keys == 0 {
+            return Err(format!("User {} has no keys to rotate", user_id));
+        }
+
+        let scopes = if scopes.is_empty() {
+            rotated_scopes(conn, user_id).map_err(|e| e.to_string())?
+        } else {
+            scopes.join(",")
+        };
+
+        let prefixes: Vec<String> = conn
+            .prepare("SELECT key_prefix FROM api_keys WHERE user_id = ? AND revoked_at IS NULL")
+            .and_then(|mut stmt| stmt.query_map([user_id], |row| row.get(0))?.collect())
+            .map_err(|e| e.to_string())?;
+        for prefix in &prefixes {
+            db::revoke_key(conn, prefix, Some(user_id), "rotate").map_err(|e| e.to_string())?;
+        }
+        println!(
+            "Revoked {} active key(s) of user {}",
+            prefixes.len(),
+            user_id
+        );
+
+        insert_key(conn, key_config, user_id, None, &scopes)
+    })
+}
+
+/// Scopes of the user's active keys combined, or of their newest key if none
+/// is active, so rotating never grants more than the user had. Full access
+/// only carries over from a key that had it.
+fn rotated_scopes(conn: &Connection, user_id: i64) -> rusqlite::Result<String> {
+    let keys: Vec<(String, bool)> = conn
+        .prepare(
+            "SELECT scopes, revoked_at IS NULL FROM api_keys WHERE user_id = ? ORDER BY id DESC",
+        )?
+        .query_map([user_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let active: Vec<&str> = keys
+        .iter()
+        .filter(|(_, active)| *active)
+        .map(|(scopes, _)| scopes.as_str())
+        .collect();
+    let sources = if active.is_empty() {
+        keys.first()
+            .map(|(scopes, _)| scopes.as_str())
+            .into_iter()
+            .collect()
+    } else {
+        active
+    };
+
+    let mut scopes: Vec<String> = sources.into_iter().flat_map(parse_scopes).collect();
+    if scopes.iter().any(|scope| scope == FULL_ACCESS_SCOPE) {
+        return Ok(FULL_ACCESS_SCOPE.to_string());
+    }
+    scopes.sort();
+    scopes.dedup();
+    Ok(scopes.join(","))
+}
+
+/// Marks the user as deleted. Their tickets and keys stay in the database, but
+/// the keys no longer validate and unused activation codes are invalidated.
+pub fn delete_user(db: &DbPool, user_id: i64) -> Result<(), String> {
+    let mut conn = db.get().map_err(|e| e.to_string())?;
     let now = now_timestamp();
 
-    let rows = conn
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let rows = tx
         .execute(
-            "UPDATE api_keys SET revoked_at = ? WHERE key_prefix = ? AND revoked_at IS NULL",
-            rusqlite::params![now, prefix],
+            "UPDATE users SET deleted_at = ? WHERE id = ? AND deleted_at IS NULL",
+            rusqlite::params![now, user_id],
         )
         .map_err(|e| e.to_string())?;
-
     if rows == 0 {
-        Err(format!("No active key found with prefix {}", prefix))
-    } else {
-        println!("Revoked key with prefix {}", prefix);
-        Ok(())
+        return Err(format!("User {} not found", user_id));
     }
+
+    tx.execute(
+        "UPDATE activation_codes SET used_at = ? WHERE user_id = ? AND used_at IS NULL",
+        rusqlite::params![now, user_id],
+    )
+    .map_err(|e| e.to_string())?;
+    db::audit(&tx, "user.deleted", user_id, "").map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    println!("Deleted user {}", user_id);
+    Ok(())
 }
 
-pub fn list_users(db: &DbPool) -> Result<(), String> {
-    let conn = db.lock().unwrap();
+/// Lists users; deleted ones only with `include_deleted`, marked as such.
+pub fn list_users(db: &DbPool, include_deleted: bool, output: OutputFormat) -> Result<(), String> {
+    let conn = db.get().map_err(|e| e.to_string())?;
     let mut stmt = conn
-        .prepare("SELECT id, email, role, subscription_status, created_at FROM users ORDER BY id")
+        .prepare(
+            "SELECT id, email, role, subscription_status, created_at, deleted_at FROM users
+             WHERE ?1 OR deleted_at IS NULL
+             ORDER BY id",
+        )
         .map_err(|e| e.to_string())?;
 
     let users = stmt
-        .query_map([], |row| {
+        .query_map([include_deleted], |row| {
             Ok((
                 row.get::<_, i64>(0)?,
                 row.get::<_, String>(1)?,
                 row.get::<_, String>(2)?,
                 row.get::<_, String>(3)?,
                 row.get::<_, i64>(4)?,
+                row.get::<_, Option<i64>>(5)?,
             ))
         })
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
         .map_err(|e| e.to_string())?;
 
+    if output == OutputFormat::Json {
+        return print_json(
+            users
+                .into_iter()
+                .map(|(id, email, role, status, created, deleted)| {
+                    json!({
+                        "id": id,
+                        "email": email,
+                        "role": role,
+                        "subscription_status": status,
+                        "created_at": created,
+                        "deleted_at": deleted,
+                    })
+                })
+                .collect(),
+        );
+    }
+
     println!(
         "{:<5} {:<30} {:<10} {:<12} Created",
         "ID", "Email", "Role", "Status"
     );
     println!("{}", "-".repeat(75));
 
-    for user in users {
-        let (id, email, role, status, created) = user.map_err(|e| e.to_string())?;
+    for (id, email, role, status, created, deleted) in users {
+        let deleted = deleted.map_or(String::new(), |d| format!(" (deleted {})", d));
         println!(
-            "{:<5} {:<30} {:<10} {:<12} {}",
-            id, email, role, status, created
+            "{:<5} {:<30} {:<10} {:<12} {}{}",
+            id, email, role, status, created, deleted
         );
     }
 
     Ok(())
 }
 
-pub fn list_keys(db: &DbPool) -> Result<(), String> {
-    let conn = db.lock().unwrap();
+fn key_status(revoked: Option<i64>, expires: Option<i64>, now: i64) -> &'static str {
+    if revoked.is_some() {
+        "revoked"
+    } else if expires.is_some_and(|e| e <= now) {
+        "expired"
+    } else {
+        "active"
+    }
+}
+
+pub fn list_keys(
+    db: &DbPool,
+    filter: &ListFilter<KeyStatus>,
+    idle_days: Option<i64>,
+    output: OutputFormat,
+) -> Result<(), String> {
+    let now = now_timestamp();
+    let mut conditions = Vec::new();
+    let mut params = Vec::new();
+    if let Some(user_id) = filter.user_id {
+        conditions.push("k.user_id = ?");
+        params.push(Value::Integer(user_id));
+    }
+    match filter.status {
+        Some(KeyStatus::Active) => {
+            conditions.push("k.revoked_at IS NULL AND (k.expires_at IS NULL OR k.expires_at > ?)");
+            params.push(Value::Integer(now));
+        }
+        Some(KeyStatus::Revoked) => conditions.push("k.revoked_at IS NOT NULL"),
+        Some(KeyStatus::Expired) => {
+            conditions.push("k.revoked_at IS NULL AND k.expires_at <= ?");
+            params.push(Value::Integer(now));
+        }
+        None => {}
+    }
+    if let Some(days) = idle_days {
+        // Keys that were never used count as idle since their creation
+        conditions.push("COALESCE(k.last_used_at, k.created_at) <= ?");
+        params.push(Value::Integer(now - days * 24 * 60 * 60));
+    }
+
+    let conn = db.get().map_err(|e| e.to_string())?;
     let mut stmt = conn
-        .prepare(
-            "SELECT k.id, k.key_prefix, u.email, k.created_at, k.revoked_at
+        .prepare(&filter.query(
+            "SELECT k.id, k.key_prefix, k.user_id, u.email, k.created_at, k.revoked_at, k.expires_at,
+                    k.scopes, k.last_used_at
              FROM api_keys k
-             JOIN users u ON k.user_id = u.id
-             ORDER BY k.id",
-        )
+             JOIN users u ON k.user_id = u.id",
+            &conditions,
+            "k.id",
+            &mut params,
+        ))
         .map_err(|e| e.to_string())?;
 
     let keys = stmt
-        .query_map([], |row| {
+        .query_map(params_from_iter(&params), |row| {
             Ok((
                 row.get::<_, i64>(0)?,
                 row.get::<_, String>(1)?,
-                row.get::<_, String>(2)?,
-                row.get::<_, i64>(3)?,
-                row.get::<_, Option<i64>>(4)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, Option<i64>>(5)?,
+                row.get::<_, Option<i64>>(6)?,
+                row.get::<_, String>(7)?,
+                row.get::<_, Option<i64>>(8)?,
             ))
         })
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
         .map_err(|e| e.to_string())?;
 
+    if output == OutputFormat::Json {
+        return print_json(
+            keys.into_iter()
+                .map(
+                    |(id, prefix, user_id, email, created, revoked, expires, scopes, last_used)| {
+                        json!({
+                            "id": id,
+                            "prefix": prefix,
+                            "user_id": user_id,
+                            "email": email,
+                            "created_at": created,
+                            "revoked_at": revoked,
+                            "expires_at": expires,
+                            "last_used_at": last_used,
+                            "status": key_status(revoked, expires, now),
+                            "scopes": parse_scopes(&scopes),
+                        })
+                    },
+                )
+                .collect(),
+        );
+    }
+
     println!(
-        "{:<5} {:<15} {:<30} {:<12} Status",
-        "ID", "Prefix", "User", "Created"
+        "{:<5} {:<15} {:<30} {:<12} {:<12} {:<12} {:<8} Scopes",
+        "ID", "Prefix", "User", "Created", "Expires", "Last used", "Status"
     );
-    println!("{}", "-".repeat(75));
+    println!("{}", "-".repeat(113));
 
-    for key in keys {
-        let (id, prefix, email, created, revoked) = key.map_err(|e| e.to_string())?;
-        let status = if revoked.is_some() {
-            "revoked"
-        } else {
-            "active"
-        };
+    for (id, prefix, _, email, created, revoked, expires, scopes, last_used) in keys {
+        let status = key_status(revoked, expires, now);
+        let expires = expires.map_or("-".to_string(), |e| e.to_string());
+        let last_used = last_used.map_or("never".to_string(), |t| t.to_string());
         println!(
-            "{:<5} {:<15} {:<30} {:<12} {}",
-            id, prefix, email, created, status
+            "{:<5} {:<15} {:<30} {:<12} {:<12} {:<12} {:<8} {}",
+            id, prefix, email, created, expires, last_used, status, scopes
         );
     }
 
     Ok(())
 }
 
-pub fn create_activation_code(db: &DbPool, user_id: i64) -> Result<String, String> {
-    let conn = db.lock().unwrap();
+pub fn create_activation_code(
+    db: &DbPool,
+    user_id: i64,
+    ttl_secs: i64,
+    dry_run: bool,
+) -> Result<String, String> {
+    in_transaction(db, dry_run, |conn| {
+        insert_activation_code(conn, user_id, ttl_secs)
+    })
+}
 
+fn insert_activation_code(
+    conn: &Connection,
+    user_id: i64,
+    ttl_secs: i64,
+) -> Result<String, String> {
     // Verify user exists
     let email: String = conn
-        .query_row("SELECT email FROM users WHERE id = ?", [user_id], |row| {
-            row.get(0)
-        })
+        .query_row(
+            "SELECT email FROM users WHERE id = ? AND deleted_at IS NULL",
+            [user_id],
+            |row| row.get(0),
+        )
         .map_err(|_| format!("User {} not found", user_id))?;
 
+    let (code, expires_at) = store_activation_code(conn, user_id, ttl_secs)?;
+
+    println!("==============================================");
+    println!("ACTIVATION CODE CREATED (shown only once!)");
+    println!("Code:    {}", code.full_code);
+    println!("Prefix:  {}", code.prefix);
+    println!("User:    {} (id={})", email, user_id);
+    println!("Expires: {}", expires_at);
+    println!("==============================================");
+
+    Ok(code.full_code)
+}
+
+/// Inserts and audits a new code for the user; returns it with its expiry.
+fn store_activation_code(
+    conn: &Connection,
+    user_id: i64,
+    ttl_secs: i64,
+) -> Result<(GeneratedActivationCode, i64), String> {
     let code = generate_activation_code();
     let now = now_timestamp();
+    let expires_at = now + ttl_secs;
 
     conn.execute(
-        "INSERT INTO activation_codes (code_hash, code_prefix, user_id, created_at) VALUES (?, ?, ?, ?)",
-        rusqlite::params![code.hash, code.prefix, user_id, now],
+        "INSERT INTO activation_codes (code_hash, code_prefix, user_id, created_at, expires_at) VALUES (?, ?, ?, ?, ?)",
+        rusqlite::params![code.hash, code.prefix, user_id, now, expires_at],
+    )
+    .map_err(|e| e.to_string())?;
+    db::audit(
+        conn,
+        "code.created",
+        user_id,
+        &format!("prefix={} via=cli", code.prefix),
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok((code, expires_at))
+}
+
+/// Gives the user a new TOTP secret, replacing any previous one. From then on
+/// activation requires a code from the authenticator app.
+pub fn enroll_totp(db: &DbPool, user_id: i64) -> Result<(), String> {
+    let conn = db.get().map_err(|e| e.to_string())?;
+
+    let email: String = conn
+        .query_row(
+            "SELECT email FROM users WHERE id = ? AND deleted_at IS NULL",
+            [user_id],
+            |row| row.get(0),
+        )
+        .map_err(|_| format!("User {} not found", user_id))?;
+
+    let secret = generate_totp_secret();
+    conn.execute(
+        "UPDATE users SET totp_secret = ? WHERE id = ?",
+        rusqlite::params![secret, user_id],
     )
     .map_err(|e| e.to_string())?;
 
     println!("==============================================");
-    println!("ACTIVATION CODE CREATED (shown only once!)");
-    println!("Code:   {}", code.full_code);
-    println!("Prefix: {}", code.prefix);
+    println!("TOTP ENROLLED (shown only once!)");
     println!("User:   {} (id={})", email, user_id);
+    println!("Secret: {}", secret);
+    println!("URI:    {}", totp_uri(&secret, &email));
     println!("==============================================");
 
-    Ok(code.full_code)
+    Ok(())
 }
 
-pub fn list_activation_codes(db: &DbPool) -> Result<(), String> {
-    let conn = db.lock().unwrap();
+fn code_status(used: Option<i64>, expires: Option<i64>, now: i64) -> &'static str {
+    if used.is_some() {
+        "used"
+    } else if expires.is_some_and(|e| e <= now) {
+        "expired"
+    } else {
+        "available"
+    }
+}
+
+pub fn list_activation_codes(
+    db: &DbPool,
+    filter: &ListFilter<CodeStatus>,
+    output: OutputFormat,
+) -> Result<(), String> {
+    let now = now_timestamp();
+    let mut conditions = Vec::new();
+    let mut params = Vec::new();
+    if let Some(user_id) = filter.user_id {
+        conditions.push("a.user_id = ?");
+        params.push(Value::Integer(user_id));
+    }
+    match filter.status {
+        Some(CodeStatus::Available) => {
+            conditions.push("a.used_at IS NULL AND (a.expires_at IS NULL OR a.expires_at > ?)");
+            params.push(Value::Integer(now));
+        }
+        Some(CodeStatus::Used) => conditions.push("a.used_at IS NOT NULL"),
+        Some(CodeStatus::Expired) => {
+            conditions.push("a.used_at IS NULL AND a.expires_at <= ?");
+            params.push(Value::Integer(now));
+        }
+        None => {}
+    }
+
+    let conn = db.get().map_err(|e| e.to_string())?;
     let mut stmt = conn
-        .prepare(
-            "SELECT a.id, a.code_prefix, u.email, a.created_at, a.used_at
+        .prepare(&filter.query(
+            "SELECT a.id, a.code_prefix, a.user_id, u.email, a.created_at, a.used_at, a.expires_at
              FROM activation_codes a
-             JOIN users u ON a.user_id = u.id
-             ORDER BY a.id",
-        )
+             JOIN users u ON a.user_id = u.id",
+            &conditions,
+            "a.id",
+            &mut params,
+        ))
         .map_err(|e| e.to_string())?;
 
     let codes = stmt
-        .query_map([], |row| {
+        .query_map(params_from_iter(&params), |row| {
             Ok((
                 row.get::<_, i64>(0)?,
                 row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, Option<i64>>(5)?,
+                row.get::<_, Option<i64>>(6)?,
+            ))
+        })
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .map_err(|e| e.to_string())?;
+
+    if output == OutputFormat::Json {
+        return print_json(
+            codes
+                .into_iter()
+                .map(|(id, prefix, user_id, email, created, used, expires)| {
+                    json!({
+                        "id": id,
+                        "prefix": prefix,
+                        "user_id": user_id,
+                        "email": email,
+                        "created_at": created,
+                        "used_at": used,
+                        "expires_at": expires,
+                        "status": code_status(used, expires, now),
+                    })
+                })
+                .collect(),
+        );
+    }
+
+    println!(
+        "{:<5} {:<15} {:<30} {:<12} {:<12} Status",
+        "ID", "Prefix", "User", "Created", "Expires"
+    );
+    println!("{}", "-".repeat(88));
+
+    for (id, prefix, _, email, created, used, expires) in codes {
+        let status = code_status(used, expires, now);
+        let expires = expires.map_or("-".to_string(), |e| e.to_string());
+        println!(
+            "{:<5} {:<15} {:<30} {:<12} {:<12} {}",
+            id, prefix, email, created, expires, status
+        );
+    }
+
+    Ok(())
+}
+
+/// Prints the newest `limit` audit log entries, newest first.
+pub fn audit_log(db: &DbPool, limit: i64, output: OutputFormat) -> Result<(), String> {
+    let conn = db.get().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, created_at, event_type, subject_id, detail FROM audit_log
+             ORDER BY id DESC LIMIT ?",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let entries = stmt
+        .query_map([limit], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
                 row.get::<_, String>(2)?,
                 row.get::<_, i64>(3)?,
-                row.get::<_, Option<i64>>(4)?,
+                row.get::<_, String>(4)?,
             ))
         })
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
         .map_err(|e| e.to_string())?;
 
+    if output == OutputFormat::Json {
+        return print_json(
+            entries
+                .into_iter()
+                .map(|(id, created, event, user_id, detail)| {
+                    json!({
+                        "id": id,
+                        "created_at": created,
+                        "event_type": event,
+                        "subject_id": user_id,
+                        "detail": detail,
+                    })
+                })
+                .collect(),
+        );
+    }
+
     println!(
-        "{:<5} {:<15} {:<30} {:<12} Status",
-        "ID", "Prefix", "User", "Created"
+        "{:<6} {:<12} {:<16} {:<8} Detail",
+        "ID", "Time", "Event", "User"
     );
     println!("{}", "-".repeat(75));
 
-    for code in codes {
-        let (id, prefix, email, created, used) = code.map_err(|e| e.to_string())?;
-        let status = if used.is_some() { "used" } else { "available" };
+    for (id, created, event, user_id, detail) in entries {
         println!(
-            "{:<5} {:<15} {:<30} {:<12} {}",
-            id, prefix, email, created, status
+            "{:<6} {:<12} {:<16} {:<8} {}",
+            id, created, event, user_id, detail
         );
     }
 
     Ok(())
 }
 
-pub fn seed_dev_data(db: &DbPool) -> Result<(), String> {
+pub fn seed_dev_data(
+    db: &DbPool,
+    key_config: &KeyConfig,
+    code_ttl_secs: i64,
+    dry_run: bool,
+) -> Result<(), String> {
+    in_transaction(db, dry_run, |conn| seed(conn, key_config, code_ttl_secs))
+}
+
+fn seed(conn: &Connection, key_config: &KeyConfig, code_ttl_secs: i64) -> Result<(), String> {
     println!("Seeding development data...\n");
 
     // Create dev users
-    let admin_id = create_user(db, "admin@curadesk.local", "admin")?;
-    let support_id = create_user(db, "support@curadesk.local", "support")?;
-    let customer_id = create_user(db, "customer@curadesk.local", "customer")?;
+    let admin_id = insert_user(conn, "admin@curadesk.local", "admin")?;
+    let support_id = insert_user(conn, "support@curadesk.local", "support")?;
+    let customer_id = insert_user(conn, "customer@curadesk.local", "customer")?;
 
     // Create keys for each
     println!("\n--- Admin Key ---");
-    create_key(db, admin_id)?;
+    insert_key(conn, key_config, admin_id, None, FULL_ACCESS_SCOPE)?;
 
     println!("\n--- Support Key ---");
-    create_key(db, support_id)?;
+    insert_key(conn, key_config, support_id, None, FULL_ACCESS_SCOPE)?;
 
     println!("\n--- Customer Key ---");
-    create_key(db, customer_id)?;
+    insert_key(conn, key_config, customer_id, None, FULL_ACCESS_SCOPE)?;
 
     // Create activation codes for testing
     println!("\n--- Customer Activation Code ---");
-    create_activation_code(db, customer_id)?;
+    insert_activation_code(conn, customer_id, code_ttl_secs)?;
 
     println!("\nSeed data created successfully.");
     Ok(())
 }
+
+pub async fn retry_failed_emails(
+    db: &DbPool,
+    email_service: &EmailService,
+    templates: &Templates,
+    max_age_days: i64,
+    code_ttl_secs: i64,
+) -> Result<(), String> {
+    let summary = handlers::retry_failed_emails(
+        db,
+        email_service,
+        templates,
+        max_age_days * 24 * 60 * 60,
+        code_ttl_secs,
+    )
+    .await?;
+
+    println!(
+        "Retried failed emails: {} sent, {} failed again, {} older than {} days skipped",
+        summary.sent, summary.failed, summary.expired, max_age_days
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn active_scopes(db: &DbPool, user_id: i64) -> Vec<String> {
+        db.get()
+            .unwrap()
+            .prepare("SELECT scopes FROM api_keys WHERE user_id = ? AND revoked_at IS NULL")
+            .unwrap()
+            .query_map([user_id], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    fn user_with_key(scopes: &[&str]) -> (DbPool, i64) {
+        let db = db::init_db(":memory:", "test").unwrap();
+        let user_id = create_user(&db, "customer@example.com", "customer", false).unwrap();
+        let scopes: Vec<String> = scopes.iter().map(|s| s.to_string()).collect();
+        create_key(&db, &KeyConfig::default(), user_id, None, &scopes, false).unwrap();
+        (db, user_id)
+    }
+
+    #[test]
+    fn rotation_keeps_limited_scopes() {
+        let (db, user_id) = user_with_key(&["tickets:read"]);
+
+        rotate_keys(&db, &KeyConfig::default(), user_id, &[], false).unwrap();
+
+        assert_eq!(active_scopes(&db, user_id), ["tickets:read"]);
+    }
+
+    #[test]
+    fn rotation_keeps_full_access() {
+        let (db, user_id) = user_with_key(&[]);
+
+        rotate_keys(&db, &KeyConfig::default(), user_id, &[], false).unwrap();
+
+        assert_eq!(active_scopes(&db, user_id), [FULL_ACCESS_SCOPE]);
+    }
+
+    #[test]
+    fn rotation_takes_explicit_scopes() {
+        let (db, user_id) = user_with_key(&["tickets:read"]);
+        let scopes = ["tickets:read".to_string(), "tickets:write".to_string()];
+
+        rotate_keys(&db, &KeyConfig::default(), user_id, &scopes, false).unwrap();
+
+        assert_eq!(active_scopes(&db, user_id), ["tickets:read,tickets:write"]);
+    }
+}
@@ -1,44 +1,167 @@
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use jsonwebtoken::{EncodingKey, Header};
 use rand::Rng;
+use sha1::Sha1;
 use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use support_common::TokenClaims;
 
 const KEY_CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
 
+/// Random characters after the label that identify a key in the database
+const KEY_ID_LEN: usize = 8;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How a key's stored hash was computed, kept in `api_keys.hash_version`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashVersion {
+    /// Plain SHA-256, for keys created before salting
+    Sha256 = 1,
+    /// HMAC-SHA256 keyed with the per-key salt
+    Salted = 2,
+    /// HMAC-SHA256 of salt and key, keyed with the server's pepper
+    Peppered = 3,
+}
+
+impl HashVersion {
+    pub fn from_db(version: i64) -> Option<Self> {
+        match version {
+            1 => Some(Self::Sha256),
+            2 => Some(Self::Salted),
+            3 => Some(Self::Peppered),
+            _ => None,
+        }
+    }
+}
+
 pub struct GeneratedKey {
     pub full_key: String,
     pub prefix: String,
     pub hash: String,
+    pub salt: String,
+    pub hash_version: HashVersion,
 }
 
-pub fn generate_key() -> GeneratedKey {
-    let mut rng = rand::rng();
+/// Shape of generated API keys, `<label>_<8 chars>_<random part>`, and how
+/// they are hashed.
+#[derive(Clone)]
+pub struct KeyConfig {
+    /// Tells keys of different environments apart, e.g. `sk_live` or `sk_test`
+    pub label: String,
+    pub random_len: usize,
+    /// Server secret mixed into the hashes of new keys, so a leaked database
+    /// alone isn't enough to check guessed keys
+    pub pepper: Option<String>,
+}
 
-    // Generate 8-char prefix
-    let prefix_chars: String = (0..8)
-        .map(|_| KEY_CHARS[rng.random_range(0..KEY_CHARS.len())] as char)
-        .collect();
+impl Default for KeyConfig {
+    fn default() -> Self {
+        Self {
+            label: "sk".to_string(),
+            random_len: 32,
+            pepper: None,
+        }
+    }
+}
 
-    // Generate 32-char random part
-    let random_part: String = (0..32)
-        .map(|_| KEY_CHARS[rng.random_range(0..KEY_CHARS.len())] as char)
-        .collect();
+/// Checks a `--key-prefix` label: letters, digits and inner underscores. A
+/// trailing `_` is accepted and dropped, so `sk_live_` and `sk_live` are equal.
+pub fn parse_key_label(input: &str) -> Result<String, String> {
+    let label = input.strip_suffix('_').unwrap_or(input);
+    let valid = !label.is_empty()
+        && !label.starts_with('_')
+        && !label.contains("__")
+        && label
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_');
+    if valid {
+        Ok(label.to_string())
+    } else {
+        Err("expected letters, digits and underscores, e.g. sk_live".to_string())
+    }
+}
 
-    let full_key = format!("sk_{}_{}", prefix_chars, random_part);
-    let prefix = format!("sk_{}", prefix_chars);
-    let hash = hash_key(&full_key);
+pub fn generate_key(config: &KeyConfig) -> GeneratedKey {
+    let mut rng = rand::rng();
+    let mut random_chars = |len: usize| -> String {
+        (0..len)
+            .map(|_| KEY_CHARS[rng.random_range(0..KEY_CHARS.len())] as char)
+            .collect()
+    };
+
+    let prefix = format!("{}_{}", config.label, random_chars(KEY_ID_LEN));
+    let full_key = format!("{}_{}", prefix, random_chars(config.random_len));
+    let salt = hex::encode(rng.random::<[u8; 16]>());
+    let (hash, hash_version) = match &config.pepper {
+        Some(pepper) => (
+            hash_key_peppered(&full_key, &salt, pepper),
+            HashVersion::Peppered,
+        ),
+        None => (hash_key_salted(&full_key, &salt), HashVersion::Salted),
+    };
 
     GeneratedKey {
         full_key,
         prefix,
         hash,
+        salt,
+        hash_version,
     }
 }
 
+/// The `<label>_XXXXXXXX` part of an API key, used to find candidate rows.
+/// Parsed from the right, so it works for keys of any label.
+pub fn key_prefix(key: &str) -> Option<&str> {
+    let (prefix, random_part) = key.rsplit_once('_')?;
+    let (label, id) = prefix.rsplit_once('_')?;
+    let alphanumeric = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric());
+    (!label.is_empty() && id.len() == KEY_ID_LEN && alphanumeric(id) && alphanumeric(random_part))
+        .then_some(prefix)
+}
+
+/// Unsalted SHA-256, used for activation codes and keys created before salting.
 pub fn hash_key(key: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(key.as_bytes());
     hex::encode(hasher.finalize())
 }
 
+pub fn hash_key_salted(key: &str, salt: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(salt.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(key.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn hash_key_peppered(key: &str, salt: &str, pepper: &str) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(pepper.as_bytes()).expect("HMAC accepts any key length");
+    // The salt is hex, so the separator keeps salt and key apart
+    mac.update(salt.as_bytes());
+    mac.update(b":");
+    mac.update(key.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Checks a presented key against a stored hash in constant time. Fails if the
+/// salt or pepper the hash version needs is missing.
+pub fn verify_key(
+    key: &str,
+    version: HashVersion,
+    salt: Option<&str>,
+    pepper: Option<&str>,
+    stored_hash: &str,
+) -> bool {
+    let hash = match (version, salt, pepper) {
+        (HashVersion::Sha256, _, _) => hash_key(key),
+        (HashVersion::Salted, Some(salt), _) => hash_key_salted(key, salt),
+        (HashVersion::Peppered, Some(salt), Some(pepper)) => hash_key_peppered(key, salt, pepper),
+        _ => return false,
+    };
+    hash.as_bytes().ct_eq(stored_hash.as_bytes()).into()
+}
+
 /// Generated activation code with its components
 pub struct GeneratedActivationCode {
     pub full_code: String,
@@ -70,3 +193,60 @@ pub fn generate_activation_code() -> GeneratedActivationCode {
         hash,
     }
 }
+
+/// Signs the claims as an HS256 JWT with the shared secret.
+pub fn issue_token(claims: &TokenClaims, secret: &str) -> Result<String, String> {
+    jsonwebtoken::encode(
+        &Header::default(),
+        claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// Seconds per TOTP step, as expected by authenticator apps (RFC 6238)
+const TOTP_STEP_SECS: i64 = 30;
+
+/// Random 160-bit TOTP secret, base32 encoded as authenticator apps expect.
+pub fn generate_totp_secret() -> String {
+    BASE32_NOPAD.encode(&rand::rng().random::<[u8; 20]>())
+}
+
+/// `otpauth://` URI for enrolling the secret in an authenticator app.
+pub fn totp_uri(secret: &str, account: &str) -> String {
+    let mut url = reqwest::Url::parse("otpauth://totp/").expect("static URL is valid");
+    url.set_path(&format!("CuraDesk:{}", account));
+    url.query_pairs_mut()
+        .append_pair("secret", secret)
+        .append_pair("issuer", "CuraDesk");
+    url.to_string()
+}
+
+/// Checks a 6-digit code against the current 30-second step. The neighbouring
+/// steps are accepted too, to allow for clock drift on the user's device.
+pub fn verify_totp(secret: &str, code: &str, now: i64) -> bool {
+    let Ok(key) = BASE32_NOPAD.decode(secret.as_bytes()) else {
+        return false;
+    };
+    let step = now / TOTP_STEP_SECS;
+    [step - 1, step, step + 1].iter().any(|&counter| {
+        let expected = format!("{:06}", totp_code(&key, counter as u64));
+        expected.as_bytes().ct_eq(code.trim().as_bytes()).into()
+    })
+}
+
+/// HOTP value (RFC 4226) for one counter; TOTP uses HMAC-SHA1 by convention.
+fn totp_code(key: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[19] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    value % 1_000_000
+}
@@ -1,54 +1,185 @@
-use rusqlite::{Connection, Result};
-use std::sync::{Arc, Mutex};
-
-pub type DbPool = Arc<Mutex<Connection>>;
-
-pub fn init_db(path: &str, encryption_key: &str) -> Result<DbPool> {
-    let conn = Connection::open(path)?;
-
-    // Set SQLCipher encryption key
-    conn.execute_batch(&format!("PRAGMA key = '{}';", encryption_key))?;
-
-    conn.execute_batch(
-        "
-        CREATE TABLE IF NOT EXISTS users (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            email TEXT NOT NULL UNIQUE,
-            role TEXT NOT NULL CHECK(role IN ('admin', 'support', 'customer')),
-            subscription_status TEXT NOT NULL DEFAULT 'active' CHECK(subscription_status IN ('active', 'inactive', 'trial')),
-            created_at INTEGER NOT NULL
-        );
-
-        CREATE TABLE IF NOT EXISTS api_keys (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            key_hash TEXT NOT NULL UNIQUE,
-            key_prefix TEXT NOT NULL,
-            user_id INTEGER NOT NULL,
-            created_at INTEGER NOT NULL,
-            revoked_at INTEGER,
-            FOREIGN KEY (user_id) REFERENCES users(id)
-        );
-
-        CREATE INDEX IF NOT EXISTS idx_api_keys_hash ON api_keys(key_hash);
-        CREATE INDEX IF NOT EXISTS idx_api_keys_prefix ON api_keys(key_prefix);
-        CREATE INDEX IF NOT EXISTS idx_api_keys_user_id ON api_keys(user_id);
-        CREATE INDEX IF NOT EXISTS idx_users_email ON users(email);
-
-        -- Activation codes: one-time use codes that can be exchanged for API keys
-        CREATE TABLE IF NOT EXISTS activation_codes (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            code_hash TEXT NOT NULL UNIQUE,
-            code_prefix TEXT NOT NULL,
-            user_id INTEGER NOT NULL,
-            created_at INTEGER NOT NULL,
-            used_at INTEGER,
-            FOREIGN KEY (user_id) REFERENCES users(id)
-        );
-
-        CREATE INDEX IF NOT EXISTS idx_activation_codes_hash ON activation_codes(code_hash);
-        CREATE INDEX IF NOT EXISTS idx_activation_codes_prefix ON activation_codes(code_prefix);
-        ",
+use r2d2::{ManageConnection, Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, ErrorCode, OpenFlags, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::migrations;
+
+pub type DbPool = Pool<SqliteConnectionManager>;
+pub type DbConn = PooledConnection<SqliteConnectionManager>;
+
+/// Opens (or creates) the database; pass `:memory:` as path for a throwaway DB.
+pub fn init_db(path: &str, encryption_key: &str) -> std::result::Result<DbPool, String> {
+    let manager = if path == ":memory:" {
+        SqliteConnectionManager::memory()
+    } else {
+        SqliteConnectionManager::file(path)
+    };
+    // SQLCipher needs the key on every connection before its first read
+    let key = encryption_key.to_string();
+    let manager =
+        manager.with_init(move |conn| conn.execute_batch(&format!("PRAGMA key = '{}';", key)));
+
+    // Migrate outside the pool first: with a wrong key the pool would keep
+    // retrying the failing connection until its timeout instead of erroring
+    let mut conn = manager.connect().map_err(|e| e.to_string())?;
+    migrations::migrate(&mut conn).map_err(|e| e.to_string())?;
+
+    Pool::new(manager).map_err(|e| e.to_string())
+}
+
+/// Appends an entry to the audit log. Pass the transaction that performs the
+/// action, so the entry is committed or rolled back together with it.
+pub fn audit(conn: &Connection, event_type: &str, subject_id: i64, detail: &str) -> Result<()> {
+    conn.execute(
+        "INSERT INTO audit_log (event_type, subject_id, detail, created_at) VALUES (?, ?, ?, ?)",
+        rusqlite::params![event_type, subject_id, detail, now_timestamp()],
     )?;
+    Ok(())
+}
+
+/// Records that the key just authenticated.
+pub fn touch_key(conn: &Connection, key_id: i64) -> Result<()> {
+    conn.execute(
+        "UPDATE api_keys SET last_used_at = ? WHERE id = ?",
+        rusqlite::params![now_timestamp(), key_id],
+    )?;
+    Ok(())
+}
+
+/// Revokes the active keys with `prefix`, only the ones owned by `owner` if
+/// given, and audits each. Returns how many keys were revoked. Like [`audit`],
+/// expects to run inside the caller's transaction.
+pub fn revoke_key(conn: &Connection, prefix: &str, owner: Option<i64>, via: &str) -> Result<usize> {
+    let owners: Vec<i64> = conn
+        .prepare(
+            "SELECT user_id FROM api_keys
+             WHERE key_prefix = ?1 AND revoked_at IS NULL AND (?2 IS NULL OR user_id = ?2)",
+        )?
+        .query_map(rusqlite::params![prefix, owner], |row| row.get(0))?
+        .collect::<Result<_>>()?;
+
+    conn.execute(
+        "UPDATE api_keys SET revoked_at = ?1
+         WHERE key_prefix = ?2 AND revoked_at IS NULL AND (?3 IS NULL OR user_id = ?3)",
+        rusqlite::params![now_timestamp(), prefix, owner],
+    )?;
+    for user_id in &owners {
+        audit(
+            conn,
+            "key.revoked",
+            *user_id,
+            &format!("prefix={} via={}", prefix, via),
+        )?;
+    }
+    Ok(owners.len())
+}
+
+fn now_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// Opens an existing database read-only and checks that the key decrypts it and
+/// that its pages are intact. Returns the row count of every table on success.
+pub fn verify_db(
+    path: &str,
+    encryption_key: &str,
+) -> std::result::Result<Vec<(String, i64)>, String> {
+    if !std::path::Path::new(path).exists() {
+        return Err(format!("Database file {} does not exist", path));
+    }
+
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| e.to_string())?;
+    conn.execute_batch(&format!("PRAGMA key = '{}';", encryption_key))
+        .map_err(|e| e.to_string())?;
+
+    // The first read fails with SQLITE_NOTADB if the key is wrong or the file is garbage
+    let tables: Vec<String> = conn
+        .prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<String>>>()
+        })
+        .map_err(|e| match e.sqlite_error_code() {
+            Some(ErrorCode::NotADatabase) => "Cannot decrypt database — wrong key?".to_string(),
+            _ => e.to_string(),
+        })?;
+
+    let cipher_errors: Vec<String> = conn
+        .prepare("PRAGMA cipher_integrity_check")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<String>>>()
+        })
+        .map_err(|e| e.to_string())?;
+    if !cipher_errors.is_empty() {
+        return Err(format!(
+            "Cipher integrity check failed:\n{}",
+            cipher_errors.join("\n")
+        ));
+    }
+
+    let integrity: String = conn
+        .query_row("PRAGMA integrity_check", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    if integrity != "ok" {
+        return Err(format!("Integrity check failed: {}", integrity));
+    }
+
+    tables
+        .into_iter()
+        .map(|table| {
+            let count: i64 = conn
+                .query_row(&format!("SELECT COUNT(*) FROM \"{}\"", table), [], |row| {
+                    row.get(0)
+                })
+                .map_err(|e| e.to_string())?;
+            Ok((table, count))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a migrated database file and returns its directory and path
+    fn fresh_db() -> (tempfile::TempDir, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.db").to_str().unwrap().to_string();
+        drop(init_db(&path, "test").unwrap());
+        (dir, path)
+    }
+
+    #[test]
+    fn verifies_a_good_file() {
+        let (_dir, path) = fresh_db();
+
+        let counts = verify_db(&path, "test").unwrap();
+
+        assert!(counts.contains(&("users".to_string(), 0)));
+    }
+
+    #[test]
+    fn rejects_a_truncated_file() {
+        let (_dir, path) = fresh_db();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        let len = file.metadata().unwrap().len();
+        file.set_len(len / 2).unwrap();
+
+        assert!(verify_db(&path, "test").is_err());
+    }
+
+    #[test]
+    fn rejects_a_wrong_key() {
+        let (_dir, path) = fresh_db();
+
+        let error = verify_db(&path, "other").unwrap_err();
 
-    Ok(Arc::new(Mutex::new(conn)))
+        assert_eq!(error, "Cannot decrypt database — wrong key?");
+    }
 }
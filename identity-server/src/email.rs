@@ -1,33 +1,370 @@
-use resend_rs::Resend;
-use resend_rs::types::CreateEmailBaseOptions;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Timelike, Utc};
+use chrono_tz::Tz;
+use tracing::warn;
+
+use crate::email_backend::EmailBackend;
+
+/// Language of users who registered without choosing one
+pub const DEFAULT_LANGUAGE: &str = "de";
+
+/// Subjects used when a template has no `<title>`
+const DEFAULT_ACTIVATION_SUBJECT: &str = "Ihr CuraDesk Aktivierungscode";
+const DEFAULT_TICKET_REPLY_SUBJECT: &str = "Neue Antwort auf Ihr Ticket {{ticket}}";
+
+/// Attempts per activation email, including the first
+const SEND_ATTEMPTS: u32 = 3;
+
+/// Wait before the first retry, doubled for each further one
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
 
 pub struct EmailService {
-    client: Resend,
-    from: String,
-    template: String,
+    backend: Box<dyn EmailBackend>,
 }
 
 impl EmailService {
-    pub fn new(api_key: &str, from: String, template: String) -> Self {
-        Self {
-            client: Resend::new(api_key),
-            from,
-            template,
-        }
+    pub fn new(backend: Box<dyn EmailBackend>) -> Self {
+        Self { backend }
     }
 
-    pub async fn send_activation_code(&self, to: &str, code: &str) -> Result<(), String> {
-        let content = self.template.replace("{{code}}", code);
-        let subject = "Ihr CuraDesk Aktivierungscode";
+    /// Sends `code` using `template`, usually picked via
+    /// [`Templates::activation`]. The plaintext part is for clients that don't
+    /// render HTML.
+    pub async fn send_activation_code(
+        &self,
+        to: &str,
+        code: &str,
+        template: &EmailTemplate,
+    ) -> Result<(), String> {
+        let subject = title(&template.html).unwrap_or(DEFAULT_ACTIVATION_SUBJECT);
+        let html = template.html.replace("{{code}}", code);
+        let text = template.text.replace("{{code}}", code);
+
+        let mut backoff = RETRY_BACKOFF;
+        for attempt in 1.. {
+            match self.backend.send(to, subject, &html, &text).await {
+                Ok(()) => break,
+                Err(e) if attempt < SEND_ATTEMPTS && e.transient => {
+                    warn!(error = %e.message, attempt, recipient = to, "Sending activation email failed, retrying");
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(e) => return Err(e.message),
+            }
+        }
+
+        Ok(())
+    }
 
-        let email = CreateEmailBaseOptions::new(&self.from, [to], subject).with_html(&content);
+    /// Tells `to` that support replied to `ticket`, using `template`, usually
+    /// picked via [`Templates::ticket_reply`].
+    pub async fn send_ticket_reply(
+        &self,
+        to: &str,
+        ticket: &str,
+        template: &EmailTemplate,
+    ) -> Result<(), String> {
+        let subject = title(&template.html)
+            .unwrap_or(DEFAULT_TICKET_REPLY_SUBJECT)
+            .replace("{{ticket}}", ticket);
+        let html = template.html.replace("{{ticket}}", ticket);
+        let text = template.text.replace("{{ticket}}", ticket);
 
-        self.client
-            .emails
-            .send(email)
+        self.backend
+            .send(to, &subject, &html, &text)
             .await
-            .map_err(|e| e.to_string())?;
+            .map_err(|e| e.message)
+    }
+}
+
+/// Contents of the `<title>` element, which doubles as the email subject so
+/// translated templates get a translated subject.
+fn title(html: &str) -> Option<&str> {
+    let start = html.find("<title>")? + "<title>".len();
+    let end = start + html[start..].find("</title>")?;
+    Some(html[start..end].trim()).filter(|t| !t.is_empty())
+}
+
+/// HTML and plaintext part of an email. Activation emails contain `{{code}}`,
+/// ticket reply emails `{{ticket}}`.
+pub struct EmailTemplate {
+    pub html: String,
+    pub text: String,
+}
 
+impl EmailTemplate {
+    /// Without a `.txt` template the plaintext part is derived from the HTML.
+    pub fn new(html: String, text: Option<String>) -> Self {
+        let text = text.unwrap_or_else(|| html_to_text(&html));
+        Self { html, text }
+    }
+}
+
+/// Email templates by language code.
+pub struct Templates {
+    activation: HashMap<String, EmailTemplate>,
+    ticket_reply: HashMap<String, EmailTemplate>,
+}
+
+impl Templates {
+    /// The German and English templates built into the binary.
+    pub fn embedded() -> Self {
+        let mut templates = Self {
+            activation: HashMap::new(),
+            ticket_reply: HashMap::new(),
+        };
+        templates.insert(
+            "de",
+            EmailTemplate::new(
+                include_str!("templates/activation_email.de.html").to_string(),
+                Some(include_str!("templates/activation_email.de.txt").to_string()),
+            ),
+        );
+        templates.insert(
+            "en",
+            EmailTemplate::new(
+                include_str!("templates/activation_email.en.html").to_string(),
+                Some(include_str!("templates/activation_email.en.txt").to_string()),
+            ),
+        );
+        templates.ticket_reply.insert(
+            "de".to_string(),
+            EmailTemplate::new(
+                include_str!("templates/ticket_reply_email.de.html").to_string(),
+                Some(include_str!("templates/ticket_reply_email.de.txt").to_string()),
+            ),
+        );
+        templates.ticket_reply.insert(
+            "en".to_string(),
+            EmailTemplate::new(
+                include_str!("templates/ticket_reply_email.en.html").to_string(),
+                Some(include_str!("templates/ticket_reply_email.en.txt").to_string()),
+            ),
+        );
+        templates
+    }
+
+    /// Adds or replaces the activation template for `language`.
+    pub fn insert(&mut self, language: &str, template: EmailTemplate) {
+        self.activation.insert(language.to_string(), template);
+    }
+
+    /// Adds every `activation_email.{lang}.html` and `ticket_reply_email.{lang}.html`
+    /// found in `dir`, replacing the embedded template of the same kind and
+    /// language. A `.txt` file of the same name next to it is used as the
+    /// plaintext part.
+    pub fn load_dir(&mut self, dir: &Path) -> Result<(), String> {
+        let entries = std::fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read templates dir {}: {}", dir.display(), e))?;
+        for entry in entries {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let (templates, rest) = if let Some(rest) = name.strip_prefix("activation_email.") {
+                (&mut self.activation, rest)
+            } else if let Some(rest) = name.strip_prefix("ticket_reply_email.") {
+                (&mut self.ticket_reply, rest)
+            } else {
+                continue;
+            };
+            let Some(language) = rest
+                .strip_suffix(".html")
+                .filter(|language| is_valid_language(language))
+            else {
+                continue;
+            };
+            let read = |path: &Path| {
+                std::fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read email template {}: {}", path.display(), e))
+            };
+            let html = read(&path)?;
+            let text_path = path.with_extension("txt");
+            let text = if text_path.exists() {
+                Some(read(&text_path)?)
+            } else {
+                None
+            };
+            templates.insert(language.to_string(), EmailTemplate::new(html, text));
+        }
         Ok(())
     }
+
+    /// Template for `language`, falling back to German, which is always present.
+    pub fn activation(&self, language: &str) -> &EmailTemplate {
+        self.activation
+            .get(language)
+            .unwrap_or(&self.activation[DEFAULT_LANGUAGE])
+    }
+
+    /// Like [`Templates::activation`], for ticket reply notifications.
+    pub fn ticket_reply(&self, language: &str) -> &EmailTemplate {
+        self.ticket_reply
+            .get(language)
+            .unwrap_or(&self.ticket_reply[DEFAULT_LANGUAGE])
+    }
+}
+
+/// Plaintext rendering of an HTML template: the `<body>` without tags, one
+/// paragraph per element, so the code ends up on a line of its own.
+fn html_to_text(html: &str) -> String {
+    let body = match (html.find("<body"), html.rfind("</body>")) {
+        (Some(start), Some(end)) if start < end => &html[start..end],
+        _ => html,
+    };
+
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in body.replace("<br/>", "\n").replace("<br>", "\n").chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Language codes like `de`, `en` or `pt-br`
+pub fn is_valid_language(language: &str) -> bool {
+    (2..=8).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_lowercase() || c == '-')
+}
+
+/// Builds the `From` header. `name` and `address` override the respective
+/// part of `default_from` (e.g. `CuraDesk <kontakt@curadesk.de>`).
+pub fn sender(default_from: &str, name: Option<&str>, address: Option<&str>) -> String {
+    let (default_name, default_address) = match default_from.split_once('<') {
+        Some((name, address)) => (name.trim(), address.trim_end_matches('>').trim()),
+        None => ("", default_from.trim()),
+    };
+
+    let name = name.unwrap_or(default_name);
+    let address = address.unwrap_or(default_address);
+    if name.is_empty() {
+        address.to_string()
+    } else {
+        format!("{} <{}>", name, address)
+    }
+}
+
+/// Daily window (local hours, end exclusive) during which emails are deferred.
+#[derive(Clone, Copy, Debug)]
+pub struct QuietHours {
+    start: u32,
+    end: u32,
+    tz: Tz,
+}
+
+impl QuietHours {
+    /// Parses a range like `22-7` in the given IANA timezone, e.g. `Europe/Berlin`.
+    pub fn parse(range: &str, tz: &str) -> Result<Self, String> {
+        let (start, end) = range
+            .split_once('-')
+            .ok_or_else(|| format!("Invalid quiet hours '{}', expected e.g. 22-7", range))?;
+        let parse_hour = |h: &str| {
+            h.trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|h| *h < 24)
+                .ok_or_else(|| format!("Invalid hour '{}' in quiet hours", h))
+        };
+        let tz = tz
+            .parse::<Tz>()
+            .map_err(|_| format!("Unknown timezone '{}'", tz))?;
+
+        Ok(Self {
+            start: parse_hour(start)?,
+            end: parse_hour(end)?,
+            tz,
+        })
+    }
+
+    pub fn contains(&self, at: DateTime<Utc>) -> bool {
+        let hour = at.with_timezone(&self.tz).hour();
+        if self.start <= self.end {
+            hour >= self.start && hour < self.end
+        } else {
+            // Window wraps around midnight
+            hour >= self.start || hour < self.end
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.contains(Utc::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email_backend::MockBackend;
+
+    fn at_hour(hour: u32) -> DateTime<Utc> {
+        DateTime::from_timestamp(i64::from(hour) * 3600, 0).unwrap()
+    }
+
+    #[test]
+    fn quiet_hours_wrap_around_midnight() {
+        let quiet = QuietHours::parse("22-7", "UTC").unwrap();
+        assert!(quiet.contains(at_hour(23)));
+        assert!(quiet.contains(at_hour(3)));
+        assert!(!quiet.contains(at_hour(7)));
+        assert!(!quiet.contains(at_hour(12)));
+
+        let quiet = QuietHours::parse("12-14", "Europe/Berlin").unwrap();
+        // 11:00 UTC is 12:00 in Berlin in January
+        assert!(quiet.contains(at_hour(11)));
+        assert!(!quiet.contains(at_hour(13)));
+    }
+
+    #[tokio::test]
+    async fn derived_plaintext_contains_the_raw_code() {
+        let backend = MockBackend::default();
+        let service = EmailService::new(Box::new(backend.clone()));
+        let template = EmailTemplate::new(
+            include_str!("templates/activation_email.en.html").to_string(),
+            None,
+        );
+
+        service
+            .send_activation_code("a@b.c", "AB12-CD34", &template)
+            .await
+            .unwrap();
+
+        let sent = backend.sent.lock().unwrap();
+        assert!(sent[0].text.lines().any(|line| line == "AB12-CD34"));
+        assert!(!sent[0].text.contains('<'));
+    }
+
+    #[tokio::test]
+    async fn ticket_reply_uses_the_recipients_language() {
+        let backend = MockBackend::default();
+        let service = EmailService::new(Box::new(backend.clone()));
+        let templates = Templates::embedded();
+
+        for language in ["en", "de", "fr"] {
+            service
+                .send_ticket_reply("a@b.c", "CD-000042", templates.ticket_reply(language))
+                .await
+                .unwrap();
+        }
+
+        let sent = backend.sent.lock().unwrap();
+        assert_eq!(sent[0].subject, "New reply to your ticket CD-000042");
+        assert!(sent[0].text.contains("replied to your ticket CD-000042"));
+        assert_eq!(sent[1].subject, "Neue Antwort auf Ihr Ticket CD-000042");
+        assert!(sent[1].text.contains("Ihr Ticket CD-000042 geantwortet"));
+        // No French template, so German is used
+        assert_eq!(sent[2], sent[1]);
+    }
 }
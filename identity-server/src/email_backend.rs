@@ -0,0 +1,263 @@
+//! Transports that deliver a finished email: Resend's HTTP API or any SMTP
+//! server, chosen with `--email-backend`.
+
+use async_trait::async_trait;
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use resend_rs::Resend;
+use resend_rs::types::CreateEmailBaseOptions;
+
+/// A failed delivery. Only `transient` failures are worth retrying.
+#[derive(Debug)]
+pub struct SendError {
+    pub message: String,
+    pub transient: bool,
+}
+
+impl SendError {
+    fn permanent(message: String) -> Self {
+        Self {
+            message,
+            transient: false,
+        }
+    }
+}
+
+#[async_trait]
+pub trait EmailBackend: Send + Sync {
+    /// Sends one email with an HTML and a plaintext part.
+    async fn send(&self, to: &str, subject: &str, html: &str, text: &str) -> Result<(), SendError>;
+}
+
+pub struct ResendBackend {
+    client: Resend,
+    from: String,
+    reply_to: Option<String>,
+}
+
+impl ResendBackend {
+    pub fn new(api_key: &str, from: String, reply_to: Option<String>) -> Self {
+        Self {
+            client: Resend::new(api_key),
+            from,
+            reply_to,
+        }
+    }
+
+    fn options(&self, to: &str, subject: &str, html: &str, text: &str) -> CreateEmailBaseOptions {
+        let email = CreateEmailBaseOptions::new(&self.from, [to], subject)
+            .with_html(html)
+            .with_text(text);
+        match &self.reply_to {
+            Some(reply_to) => email.with_reply(reply_to),
+            None => email,
+        }
+    }
+}
+
+#[async_trait]
+impl EmailBackend for ResendBackend {
+    async fn send(&self, to: &str, subject: &str, html: &str, text: &str) -> Result<(), SendError> {
+        let email = self.options(to, subject, html, text);
+
+        self.client
+            .emails
+            .send(email)
+            .await
+            .map(|_| ())
+            .map_err(|e| SendError {
+                // Network failures and 5xx responses; anything Resend rejects
+                // with a 4xx (including rate limits) would only fail again
+                transient: match &e {
+                    resend_rs::Error::Http(_) => true,
+                    resend_rs::Error::Resend(response) => response.status_code >= 500,
+                    _ => false,
+                },
+                message: e.to_string(),
+            })
+    }
+}
+
+/// How the SMTP connection is secured
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum SmtpTls {
+    /// Upgrade a plain connection with STARTTLS, usually port 587
+    Starttls,
+    /// TLS from the start, usually port 465
+    Tls,
+    /// Unencrypted, only for a relay on localhost
+    None,
+}
+
+pub struct SmtpConfig<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    pub tls: SmtpTls,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a str>,
+}
+
+pub struct SmtpBackend {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    reply_to: Option<Mailbox>,
+}
+
+impl SmtpBackend {
+    pub fn new(config: &SmtpConfig, from: &str, reply_to: Option<&str>) -> Result<Self, String> {
+        let builder = match config.tls {
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(config.host),
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(config.host),
+            SmtpTls::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+                config.host,
+            )),
+        }
+        .map_err(|e| format!("Invalid SMTP host {}: {}", config.host, e))?
+        .port(config.port);
+
+        let builder = match (config.username, config.password) {
+            (Some(username), Some(password)) => {
+                builder.credentials(Credentials::new(username.into(), password.into()))
+            }
+            (None, None) => builder,
+            _ => return Err("--smtp-username and --smtp-password must be set together".into()),
+        };
+
+        let parse = |address: &str| {
+            address
+                .parse::<Mailbox>()
+                .map_err(|e| format!("Invalid email address '{}': {}", address, e))
+        };
+
+        Ok(Self {
+            transport: builder.build(),
+            from: parse(from)?,
+            reply_to: reply_to.map(parse).transpose()?,
+        })
+    }
+
+    fn message(
+        &self,
+        to: &str,
+        subject: &str,
+        html: &str,
+        text: &str,
+    ) -> Result<Message, SendError> {
+        let to = to
+            .parse::<Mailbox>()
+            .map_err(|e| SendError::permanent(format!("Invalid recipient '{}': {}", to, e)))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(subject);
+        let message = match &self.reply_to {
+            Some(reply_to) => message.reply_to(reply_to.clone()),
+            None => message,
+        };
+        message
+            .multipart(MultiPart::alternative_plain_html(
+                text.to_string(),
+                html.to_string(),
+            ))
+            .map_err(|e| SendError::permanent(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl EmailBackend for SmtpBackend {
+    async fn send(&self, to: &str, subject: &str, html: &str, text: &str) -> Result<(), SendError> {
+        let message = self.message(to, subject, html, text)?;
+
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| SendError {
+                // 5xx replies are final; connection problems and 4xx replies
+                // (e.g. greylisting) may clear up
+                transient: !e.is_permanent(),
+                message: e.to_string(),
+            })
+    }
+}
+
+/// An email captured by [`MockBackend`]
+#[cfg(test)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SentEmail {
+    pub to: String,
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+/// Records emails instead of delivering them; every send fails while `fail` is set.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MockBackend {
+    pub sent: std::sync::Arc<std::sync::Mutex<Vec<SentEmail>>>,
+    pub fail: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl EmailBackend for MockBackend {
+    async fn send(&self, to: &str, subject: &str, html: &str, text: &str) -> Result<(), SendError> {
+        if self.fail.load(std::sync::atomic::Ordering::SeqCst) {
+            return Err(SendError::permanent("Mailbox unavailable".to_string()));
+        }
+        self.sent.lock().unwrap().push(SentEmail {
+            to: to.to_string(),
+            subject: subject.to_string(),
+            html: html.to_string(),
+            text: text.to_string(),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resend_options_carry_the_reply_to() {
+        let backend = ResendBackend::new(
+            "re_test",
+            "CuraDesk <kontakt@curadesk.de>".to_string(),
+            Some("support@curadesk.de".to_string()),
+        );
+
+        let options = backend.options("a@b.c", "Subject", "<p>Hi</p>", "Hi");
+
+        let json = serde_json::to_value(&options).unwrap();
+        assert_eq!(json["reply_to"], serde_json::json!(["support@curadesk.de"]));
+    }
+
+    #[test]
+    fn smtp_message_carries_the_reply_to() {
+        let config = SmtpConfig {
+            host: "localhost",
+            port: 25,
+            tls: SmtpTls::None,
+            username: None,
+            password: None,
+        };
+        let backend = SmtpBackend::new(
+            &config,
+            "CuraDesk <kontakt@curadesk.de>",
+            Some("support@curadesk.de"),
+        )
+        .unwrap();
+
+        let message = backend
+            .message("a@b.c", "Subject", "<p>Hi</p>", "Hi")
+            .unwrap();
+
+        assert_eq!(
+            message.headers().get_raw("Reply-To"),
+            Some("support@curadesk.de")
+        );
+    }
+}
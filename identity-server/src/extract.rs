@@ -0,0 +1,101 @@
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{FromRequest, Request},
+    http::{StatusCode, header},
+};
+use serde::de::DeserializeOwned;
+
+/// Like `axum::Json`, but rejects wrong content types and empty bodies with a
+/// plain explanation instead of axum's generic 415/400 rejections.
+pub struct JsonBody<T>(pub T);
+
+impl<S, T> FromRequest<S> for JsonBody<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_json = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"));
+        if !is_json {
+            return Err((
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Content-Type must be application/json".into(),
+            ));
+        }
+
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.body_text()))?;
+        if body.iter().all(u8::is_ascii_whitespace) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Request body is empty, expected a JSON object".into(),
+            ));
+        }
+
+        let Json(value) = Json::<T>::from_bytes(&body).map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid JSON body: {}", e.body_text()),
+            )
+        })?;
+        Ok(Self(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct Payload {
+        email: String,
+    }
+
+    async fn extract(content_type: &str, body: &str) -> Result<Payload, (StatusCode, String)> {
+        let req = Request::builder()
+            .method("POST")
+            .header(header::CONTENT_TYPE, content_type)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        JsonBody::<Payload>::from_request(req, &())
+            .await
+            .map(|JsonBody(payload)| payload)
+    }
+
+    #[tokio::test]
+    async fn accepts_json() {
+        let payload = extract("application/json; charset=utf-8", r#"{"email":"a@b.c"}"#)
+            .await
+            .unwrap();
+        assert_eq!(payload.email, "a@b.c");
+    }
+
+    #[tokio::test]
+    async fn rejects_form_bodies_with_415() {
+        let (status, message) = extract("application/x-www-form-urlencoded", "email=a%40b.c")
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(message, "Content-Type must be application/json");
+    }
+
+    #[tokio::test]
+    async fn rejects_empty_bodies_with_400() {
+        for body in ["", " \n"] {
+            let (status, message) = extract("application/json", body).await.unwrap_err();
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(message, "Request body is empty, expected a JSON object");
+        }
+    }
+}
@@ -1,10 +1,23 @@
-use axum::{Json, extract::State, http::StatusCode};
+use axum::{
+    Json,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+};
 use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
 
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::AppState;
-use crate::crypto::{generate_activation_code, generate_key, hash_key};
+use crate::crypto::{
+    HashVersion, generate_activation_code, generate_key, hash_key, issue_token, key_prefix,
+    verify_key, verify_totp,
+};
+use crate::db::{self, DbConn, DbPool};
+use crate::email::{self, EmailService, Templates};
+use crate::extract::JsonBody;
+use crate::rate_limit::RateLimitStatus;
+use support_common::{HealthStatus, TokenClaims, parse_scopes};
 
 #[derive(Deserialize)]
 pub struct ValidateRequest {
@@ -22,43 +35,98 @@ pub struct ValidateResponse {
 
 #[derive(Serialize)]
 pub struct UserInfo {
+    /// The key that was presented, for `last_used_at`
+    #[serde(skip)]
+    pub key_id: i64,
     pub id: i64,
     pub email: String,
     pub role: String,
     pub subscription_status: String,
+    /// Scopes of the key that was presented, not of the user
+    pub scopes: Vec<String>,
+}
+
+/// Looks up the owner of an active (non-revoked) API key. `pepper` is needed
+/// for keys created while `--key-pepper` was set.
+fn lookup_user(
+    conn: &rusqlite::Connection,
+    api_key: &str,
+    pepper: Option<&str>,
+) -> Option<UserInfo> {
+    let prefix = key_prefix(api_key)?;
+
+    // Hashes are salted per key, so look up candidates by prefix and verify each
+    let mut stmt = conn
+        .prepare(
+            "SELECT k.key_hash, k.key_salt, k.hash_version,
+                    u.id, u.email, u.role, u.subscription_status, k.scopes, k.id
+             FROM api_keys k
+             JOIN users u ON k.user_id = u.id
+             WHERE k.key_prefix = ?1 AND k.revoked_at IS NULL AND u.deleted_at IS NULL
+               AND (k.expires_at IS NULL OR k.expires_at > ?2)",
+        )
+        .ok()?;
+    let candidates = stmt
+        .query_map(rusqlite::params![prefix, now_timestamp()], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, i64>(2)?,
+                UserInfo {
+                    key_id: row.get(8)?,
+                    id: row.get(3)?,
+                    email: row.get(4)?,
+                    role: row.get(5)?,
+                    subscription_status: row.get(6)?,
+                    scopes: parse_scopes(&row.get::<_, String>(7)?),
+                },
+            ))
+        })
+        .ok()?;
+
+    candidates
+        .filter_map(Result::ok)
+        .find(|(hash, salt, version, _)| {
+            let Some(version) = HashVersion::from_db(*version) else {
+                return false;
+            };
+            if version == HashVersion::Peppered && pepper.is_none() {
+                warn!(
+                    prefix,
+                    "Key was hashed with a pepper, but none is configured"
+                );
+            }
+            verify_key(api_key, version, salt.as_deref(), pepper, hash)
+        })
+        .map(|(_, _, _, user)| user)
+}
+
+/// With `--enforce-subscription`, keys of inactive users are treated as invalid.
+fn subscription_blocked(state: &AppState, user: &UserInfo) -> bool {
+    state.enforce_subscription && user.subscription_status == "inactive"
 }
 
 pub async fn validate(
     State(state): State<AppState>,
-    Json(req): Json<ValidateRequest>,
+    JsonBody(req): JsonBody<ValidateRequest>,
 ) -> Result<Json<ValidateResponse>, (StatusCode, String)> {
-    let key_hash = hash_key(&req.api_key);
-
-    let conn = state.db.lock().unwrap();
-
-    let result = conn.query_row(
-        "SELECT u.id, u.email, u.role, u.subscription_status
-         FROM api_keys k
-         JOIN users u ON k.user_id = u.id
-         WHERE k.key_hash = ? AND k.revoked_at IS NULL",
-        [&key_hash],
-        |row| {
-            Ok(UserInfo {
-                id: row.get(0)?,
-                email: row.get(1)?,
-                role: row.get(2)?,
-                subscription_status: row.get(3)?,
-            })
-        },
-    );
+    let conn = db_conn(&state.db)?;
 
-    match result {
-        Ok(user) => Ok(Json(ValidateResponse {
-            valid: true,
-            user: Some(user),
-            error: None,
+    match lookup_user(&conn, &req.api_key, state.key_config.pepper.as_deref()) {
+        Some(user) if subscription_blocked(&state, &user) => Ok(Json(ValidateResponse {
+            valid: false,
+            user: None,
+            error: Some("Subscription inactive".into()),
         })),
-        Err(_) => Ok(Json(ValidateResponse {
+        Some(user) => {
+            record_key_use(&state, user.key_id);
+            Ok(Json(ValidateResponse {
+                valid: true,
+                user: Some(user),
+                error: None,
+            }))
+        }
+        None => Ok(Json(ValidateResponse {
             valid: false,
             user: None,
             error: Some("Invalid or revoked API key".into()),
@@ -66,11 +134,177 @@ pub async fn validate(
     }
 }
 
+/// Stamps the key's `last_used_at` off the request path. A lost update only
+/// makes the key look a little more dormant than it is, so failures are logged.
+fn record_key_use(state: &AppState, key_id: i64) {
+    let db = state.db.clone();
+    tokio::task::spawn_blocking(move || {
+        let result = db
+            .get()
+            .map_err(|e| e.to_string())
+            .and_then(|conn| db::touch_key(&conn, key_id).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            warn!(key_id, error = %e, "Failed to record key use");
+        }
+    });
+}
+
+// --- Who am I ---
+
+/// The user owning the `x-api-key` header, so clients can show who is logged in.
+pub async fn whoami(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<UserInfo>, (StatusCode, String)> {
+    require_user(&state, &headers).map(Json)
+}
+
+// --- Key listing ---
+
+#[derive(Serialize)]
+pub struct KeyInfo {
+    pub prefix: String,
+    pub created_at: i64,
+    pub revoked_at: Option<i64>,
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct KeyListResponse {
+    pub keys: Vec<KeyInfo>,
+}
+
+/// Lists the caller's own keys, identified by `x-api-key`. Only prefixes and
+/// timestamps are returned, never key material.
+pub async fn list_keys(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<KeyListResponse>, (StatusCode, String)> {
+    let user = require_user(&state, &headers)?;
+
+    let conn = db_conn(&state.db)?;
+    let keys = conn
+        .prepare(
+            "SELECT key_prefix, created_at, revoked_at, expires_at FROM api_keys
+             WHERE user_id = ? ORDER BY id",
+        )
+        .and_then(|mut stmt| {
+            stmt.query_map([user.id], |row| {
+                Ok(KeyInfo {
+                    prefix: row.get(0)?,
+                    created_at: row.get(1)?,
+                    revoked_at: row.get(2)?,
+                    expires_at: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()
+        })
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(KeyListResponse { keys }))
+}
+
+#[derive(Deserialize)]
+pub struct RevokeKeyRequest {
+    pub prefix: String,
+}
+
+/// Lets a user revoke one of their own keys, e.g. after it leaked. The caller
+/// authenticates with any of their valid keys, including the one revoked.
+pub async fn revoke_key(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    JsonBody(req): JsonBody<RevokeKeyRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let user = require_user(&state, &headers)?;
+
+    let mut conn = db_conn(&state.db)?;
+    let revoked = conn
+        .transaction()
+        .and_then(|tx| {
+            let revoked = db::revoke_key(&tx, &req.prefix, Some(user.id), "api")?;
+            tx.commit()?;
+            Ok(revoked)
+        })
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    // Someone else's prefix looks exactly like an unknown one
+    if revoked == 0 {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "No active key with this prefix".into(),
+        ));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// --- Token endpoint ---
+
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    pub api_key: String,
+}
+
+#[derive(Serialize)]
+pub struct TokenResponse {
+    pub token: String,
+    pub expires_at: i64,
+}
+
+/// Exchange a valid API key for a short-lived signed JWT that downstream
+/// services can verify locally without calling `/validate`.
+pub async fn token(
+    State(state): State<AppState>,
+    JsonBody(req): JsonBody<TokenRequest>,
+) -> Result<Json<TokenResponse>, (StatusCode, String)> {
+    let Some(jwt) = &state.jwt else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Token issuance not configured".into(),
+        ));
+    };
+
+    let user = {
+        let conn = db_conn(&state.db)?;
+        lookup_user(&conn, &req.api_key, state.key_config.pepper.as_deref())
+    }
+    .ok_or((
+        StatusCode::UNAUTHORIZED,
+        "Invalid or revoked API key".to_string(),
+    ))?;
+    if subscription_blocked(&state, &user) {
+        return Err((StatusCode::FORBIDDEN, "Subscription inactive".into()));
+    }
+    record_key_use(&state, user.key_id);
+
+    let now = now_timestamp();
+    let claims = TokenClaims {
+        sub: user.id,
+        email: user.email,
+        role: user.role,
+        subscription_status: user.subscription_status,
+        scopes: user.scopes,
+        iat: now,
+        exp: now + jwt.ttl_secs,
+    };
+
+    let token =
+        issue_token(&claims, &jwt.secret).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    Ok(Json(TokenResponse {
+        token,
+        expires_at: claims.exp,
+    }))
+}
+
 // --- Activation endpoint ---
 
 #[derive(Deserialize)]
 pub struct ActivateRequest {
     pub activation_code: String,
+    /// Required if the user has enrolled a TOTP secret
+    #[serde(default)]
+    pub totp_code: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -82,6 +316,17 @@ pub struct ActivateResponse {
     pub error: Option<String>,
 }
 
+/// Checks out a pooled connection, or 503 if none frees up in time.
+fn db_conn(pool: &DbPool) -> Result<DbConn, (StatusCode, String)> {
+    pool.get().map_err(|e| {
+        error!(error = %e, "Failed to get database connection");
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Database unavailable".into(),
+        )
+    })
+}
+
 fn now_timestamp() -> i64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -93,21 +338,29 @@ fn now_timestamp() -> i64 {
 /// The activation code is marked as used and cannot be reused.
 pub async fn activate(
     State(state): State<AppState>,
-    Json(req): Json<ActivateRequest>,
+    JsonBody(req): JsonBody<ActivateRequest>,
 ) -> Result<Json<ActivateResponse>, (StatusCode, String)> {
     let code_hash = hash_key(&req.activation_code);
     let now = now_timestamp();
 
-    let conn = state.db.lock().unwrap();
+    let mut conn = db_conn(&state.db)?;
 
     // Find unused activation code and get user_id
     let result = conn.query_row(
-        "SELECT id, user_id FROM activation_codes WHERE code_hash = ? AND used_at IS NULL",
+        "SELECT a.id, a.user_id, a.expires_at FROM activation_codes a
+         JOIN users u ON u.id = a.user_id
+         WHERE a.code_hash = ? AND a.used_at IS NULL AND u.deleted_at IS NULL",
         [&code_hash],
-        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+        |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, Option<i64>>(2)?,
+            ))
+        },
     );
 
-    let (code_id, user_id) = match result {
+    let (code_id, user_id, expires_at) = match result {
         Ok(data) => data,
         Err(_) => {
             return Ok(Json(ActivateResponse {
@@ -118,22 +371,86 @@ pub async fn activate(
         }
     };
 
-    // Mark activation code as used
-    if let Err(e) = conn.execute(
-        "UPDATE activation_codes SET used_at = ? WHERE id = ?",
-        rusqlite::params![now, code_id],
-    ) {
-        return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+    if expires_at.is_some_and(|e| e <= now) {
+        return Ok(Json(ActivateResponse {
+            success: false,
+            api_key: None,
+            error: Some("Activation code has expired, please request a new one".into()),
+        }));
     }
 
-    // Generate new API key for the user
-    let key = generate_key();
+    // Checked before the code is consumed, so a mistyped TOTP code can be retried
+    let totp_secret: Option<String> = conn
+        .query_row(
+            "SELECT totp_secret FROM users WHERE id = ?",
+            [user_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if let Some(secret) = totp_secret {
+        let error = match &req.totp_code {
+            None => Some("TOTP code required"),
+            Some(code) if !verify_totp(&secret, code, now) => Some("Invalid TOTP code"),
+            Some(_) => None,
+        };
+        if let Some(error) = error {
+            return Ok(Json(ActivateResponse {
+                success: false,
+                api_key: None,
+                error: Some(error.into()),
+            }));
+        }
+    }
 
-    if let Err(e) = conn.execute(
-        "INSERT INTO api_keys (key_hash, key_prefix, user_id, created_at) VALUES (?, ?, ?, ?)",
-        rusqlite::params![key.hash, key.prefix, user_id, now],
-    ) {
-        return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+    // Generate new API key for the user
+    let key = generate_key(&state.key_config);
+
+    // Consuming the code, creating the key and the audit entries succeed or fail together
+    let activated = (|| -> rusqlite::Result<bool> {
+        let tx = conn.transaction()?;
+        let consumed = tx.execute(
+            "UPDATE activation_codes SET used_at = ? WHERE id = ? AND used_at IS NULL",
+            rusqlite::params![now, code_id],
+        )?;
+        if consumed == 0 {
+            // A concurrent request used the code first
+            return Ok(false);
+        }
+        tx.execute(
+            "INSERT INTO api_keys (key_hash, key_salt, hash_version, key_prefix, user_id, created_at)
+             VALUES (?, ?, ?, ?, ?, ?)",
+            rusqlite::params![
+                key.hash,
+                key.salt,
+                key.hash_version as i64,
+                key.prefix,
+                user_id,
+                now
+            ],
+        )?;
+        let code_prefix: String = tx.query_row(
+            "SELECT code_prefix FROM activation_codes WHERE id = ?",
+            [code_id],
+            |row| row.get(0),
+        )?;
+        db::audit(&tx, "code.used", user_id, &format!("prefix={}", code_prefix))?;
+        db::audit(
+            &tx,
+            "key.created",
+            user_id,
+            &format!("prefix={} via=activation", key.prefix),
+        )?;
+        tx.commit()?;
+        Ok(true)
+    })()
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    if !activated {
+        return Ok(Json(ActivateResponse {
+            success: false,
+            api_key: None,
+            error: Some("Invalid or already used activation code".into()),
+        }));
     }
 
     Ok(Json(ActivateResponse {
@@ -148,6 +465,9 @@ pub async fn activate(
 #[derive(Deserialize)]
 pub struct RegisterRequest {
     pub email: String,
+    /// Language of the activation email, e.g. `en`; only set for new users
+    #[serde(default)]
+    pub language: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -157,7 +477,7 @@ pub struct RegisterResponse {
 }
 
 /// Basic email format validation
-fn is_valid_email(email: &str) -> bool {
+pub fn is_valid_email(email: &str) -> bool {
     let email = email.trim();
     if email.len() > 254 || email.is_empty() {
         return false;
@@ -174,7 +494,7 @@ fn is_valid_email(email: &str) -> bool {
 /// Always returns success to prevent email enumeration.
 pub async fn register(
     State(state): State<AppState>,
-    Json(req): Json<RegisterRequest>,
+    JsonBody(req): JsonBody<RegisterRequest>,
 ) -> Result<Json<RegisterResponse>, (StatusCode, String)> {
     let generic_response = RegisterResponse {
         success: true,
@@ -182,10 +502,10 @@ pub async fn register(
     };
 
     // Check if email service is configured
-    let email_service = match &state.email {
-        Some(svc) => svc.clone(),
+    let email_service = match state.email.clone() {
+        Some(svc) => svc,
         None => {
-            eprintln!("Email service not configured");
+            error!("Email service not configured");
             return Err((
                 StatusCode::SERVICE_UNAVAILABLE,
                 "Email service not configured".into(),
@@ -200,61 +520,432 @@ pub async fn register(
         return Ok(Json(generic_response));
     }
 
+    let language = req
+        .language
+        .map(|l| l.trim().to_lowercase())
+        .filter(|l| email::is_valid_language(l))
+        .unwrap_or_else(|| email::DEFAULT_LANGUAGE.to_string());
+
     let now = now_timestamp();
 
     // Find or create user
     let user_id: i64 = {
-        let conn = state.db.lock().unwrap();
+        let mut conn = db_conn(&state.db)?;
 
-        match conn.query_row("SELECT id FROM users WHERE email = ?", [&email], |row| {
-            row.get(0)
-        }) {
-            Ok(id) => {
+        match conn.query_row(
+            "SELECT id, deleted_at IS NOT NULL FROM users WHERE email = ?",
+            [&email],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, bool>(1)?)),
+        ) {
+            // Deleted accounts can't be revived by registering again
+            Ok((_, true)) => return Ok(Json(generic_response)),
+            Ok((id, false)) => {
                 // User exists - invalidate all unused activation codes
                 if let Err(e) = conn.execute(
                     "UPDATE activation_codes SET used_at = ? WHERE user_id = ? AND used_at IS NULL",
                     rusqlite::params![now, id],
                 ) {
-                    eprintln!("Failed to invalidate old codes: {}", e);
+                    warn!(error = %e, user_id = id, "Failed to invalidate old codes");
                 }
                 id
             }
             Err(_) => {
                 // Create new user with role=customer, subscription_status=trial
-                if let Err(e) = conn.execute(
-                    "INSERT INTO users (email, role, subscription_status, created_at) VALUES (?, 'customer', 'trial', ?)",
-                    rusqlite::params![email, now],
-                ) {
-                    eprintln!("Failed to create user: {}", e);
-                    return Ok(Json(generic_response));
+                let created = conn.transaction().and_then(|tx| {
+                    tx.execute(
+                        "INSERT INTO users (email, role, subscription_status, language, created_at) VALUES (?, 'customer', 'trial', ?, ?)",
+                        rusqlite::params![email, language, now],
+                    )?;
+                    let id = tx.last_insert_rowid();
+                    db::audit(&tx, "user.registered", id, "")?;
+                    tx.commit()?;
+                    Ok(id)
+                });
+                match created {
+                    Ok(id) => id,
+                    Err(e) => {
+                        error!(error = %e, "Failed to create user");
+                        return Ok(Json(generic_response));
+                    }
                 }
-                conn.last_insert_rowid()
             }
         }
     };
 
-    // Generate new activation code
-    let code = generate_activation_code();
-
-    {
-        let conn = state.db.lock().unwrap();
-        if let Err(e) = conn.execute(
-            "INSERT INTO activation_codes (code_hash, code_prefix, user_id, created_at) VALUES (?, ?, ?, ?)",
-            rusqlite::params![code.hash, code.prefix, user_id, now],
-        ) {
-            eprintln!("Failed to create activation code: {}", e);
-            return Ok(Json(generic_response));
+    // Defer the email until the quiet-hours window ends
+    if state.quiet_hours.is_some_and(|q| q.is_active()) {
+        let mut conn = db_conn(&state.db)?;
+        // One queued email per user, even if two registrations race
+        let queued = conn.transaction().and_then(|tx| {
+            tx.execute("DELETE FROM pending_emails WHERE user_id = ?", [user_id])?;
+            tx.execute(
+                "INSERT INTO pending_emails (recipient, user_id, created_at) VALUES (?, ?, ?)",
+                rusqlite::params![email, user_id, now],
+            )?;
+            tx.commit()
+        });
+        if let Err(e) = queued {
+            error!(error = %e, user_id, "Failed to queue activation email");
         }
+        return Ok(Json(generic_response));
     }
 
-    // Send email (async)
-    if let Err(e) = email_service
-        .send_activation_code(&email, &code.full_code)
-        .await
+    let templates = state.templates.load_full();
+    if let Err(e) = send_activation_email(
+        &state.db,
+        &email_service,
+        &templates,
+        user_id,
+        &email,
+        state.code_ttl_secs,
+    )
+    .await
     {
-        eprintln!("Failed to send email to {}: {}", email, e);
+        error!(error = %e.error, recipient = %email, "Failed to send activation email");
+        record_failed_email(&state.db, user_id, &email, &e);
         // Still return success to prevent enumeration
     }
 
     Ok(Json(generic_response))
 }
+
+/// An activation email that could not be delivered.
+pub struct EmailFailure {
+    /// Hash of the activation code that was created but never delivered
+    pub code_hash: Option<String>,
+    pub error: String,
+}
+
+/// Generates a fresh activation code for the user and emails it in the user's
+/// language.
+async fn send_activation_email(
+    db: &DbPool,
+    email_service: &EmailService,
+    templates: &Templates,
+    user_id: i64,
+    email: &str,
+    code_ttl_secs: i64,
+) -> Result<(), EmailFailure> {
+    let code = generate_activation_code();
+    let now = now_timestamp();
+
+    let language: String = {
+        let mut conn = db.get().map_err(|e| EmailFailure {
+            code_hash: None,
+            error: format!("Failed to get database connection: {}", e),
+        })?;
+        let language = conn
+            .query_row(
+                "SELECT language FROM users WHERE id = ?",
+                [user_id],
+                |row| row.get(0),
+            )
+            .map_err(|e| EmailFailure {
+                code_hash: None,
+                error: format!("Failed to look up user language: {}", e),
+            })?;
+        conn.transaction()
+            .and_then(|tx| {
+                tx.execute(
+                    "INSERT INTO activation_codes (code_hash, code_prefix, user_id, created_at, expires_at) VALUES (?, ?, ?, ?, ?)",
+                    rusqlite::params![code.hash, code.prefix, user_id, now, now + code_ttl_secs],
+                )?;
+                db::audit(
+                    &tx,
+                    "code.created",
+                    user_id,
+                    &format!("prefix={} via=email", code.prefix),
+                )?;
+                tx.commit()
+            })
+            .map_err(|e| EmailFailure {
+            code_hash: None,
+            error: format!("Failed to create activation code: {}", e),
+        })?;
+        language
+    };
+
+    email_service
+        .send_activation_code(email, &code.full_code, templates.activation(&language))
+        .await
+        .map_err(|error| EmailFailure {
+            code_hash: Some(code.hash),
+            error,
+        })
+}
+
+/// Adds a failed send to the dead-letter log so it can be retried later.
+fn record_failed_email(db: &DbPool, user_id: i64, recipient: &str, failure: &EmailFailure) {
+    let now = now_timestamp();
+    let recorded = db.get().map_err(|e| e.to_string()).and_then(|conn| {
+        conn.execute(
+            "INSERT INTO failed_emails (recipient, user_id, code_hash, error, created_at, last_attempt_at)
+             VALUES (?, ?, ?, ?, ?, ?)",
+            rusqlite::params![recipient, user_id, failure.code_hash, failure.error, now, now],
+        )
+        .map_err(|e| e.to_string())
+    });
+    if let Err(e) = recorded {
+        error!(error = %e, recipient, "Failed to record failed email");
+    }
+}
+
+/// Sends activation emails that were queued during quiet hours.
+/// Entries that fail to send are moved to the dead-letter log.
+pub async fn flush_pending_emails(state: &AppState) {
+    let Some(email_service) = state.email.clone() else {
+        return;
+    };
+    if state.quiet_hours.is_some_and(|q| q.is_active()) {
+        return;
+    }
+
+    let pending: Vec<(i64, String, i64)> = {
+        let result = state.db.get().map_err(|e| e.to_string()).and_then(|conn| {
+            conn.prepare("SELECT id, recipient, user_id FROM pending_emails ORDER BY id")
+                .and_then(|mut stmt| {
+                    stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                        .collect()
+                })
+                .map_err(|e| e.to_string())
+        });
+        match result {
+            Ok(rows) => rows,
+            Err(e) => {
+                error!(error = %e, "Failed to read pending emails");
+                return;
+            }
+        }
+    };
+
+    let templates = state.templates.load_full();
+    for (id, recipient, user_id) in pending {
+        if let Err(e) = send_activation_email(
+            &state.db,
+            &email_service,
+            &templates,
+            user_id,
+            &recipient,
+            state.code_ttl_secs,
+        )
+        .await
+        {
+            warn!(error = %e.error, recipient = %recipient, "Failed to send queued email");
+            record_failed_email(&state.db, user_id, &recipient, &e);
+        }
+        let removed = state.db.get().map_err(|e| e.to_string()).and_then(|conn| {
+            conn.execute("DELETE FROM pending_emails WHERE id = ?", [id])
+                .map_err(|e| e.to_string())
+        });
+        if let Err(e) = removed {
+            error!(error = %e, "Failed to remove email from queue");
+        }
+    }
+}
+
+/// Outcome of a dead-letter retry run.
+pub struct RetrySummary {
+    pub sent: usize,
+    pub failed: usize,
+    /// Entries older than the retention window, left untouched
+    pub expired: usize,
+}
+
+/// A dead-letter entry: id, recipient, user id and undelivered code hash
+type FailedEmail = (i64, String, i64, Option<String>);
+
+/// Re-sends dead-lettered activation emails created within `max_age_secs`.
+/// The undelivered code is discarded and a fresh one is sent in its place;
+/// delivered entries are removed from the log.
+pub async fn retry_failed_emails(
+    db: &DbPool,
+    email_service: &EmailService,
+    templates: &Templates,
+    max_age_secs: i64,
+    code_ttl_secs: i64,
+) -> Result<RetrySummary, String> {
+    let cutoff = now_timestamp() - max_age_secs;
+
+    let (entries, expired): (Vec<FailedEmail>, usize) = {
+        let conn = db.get().map_err(|e| e.to_string())?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, recipient, user_id, code_hash FROM failed_emails
+                 WHERE created_at >= ? ORDER BY id",
+            )
+            .map_err(|e| e.to_string())?;
+        let entries = stmt
+            .query_map([cutoff], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .and_then(|rows| rows.collect())
+            .map_err(|e| e.to_string())?;
+        let expired: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM failed_emails WHERE created_at < ?",
+                [cutoff],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        (entries, expired as usize)
+    };
+
+    let mut summary = RetrySummary {
+        sent: 0,
+        failed: 0,
+        expired,
+    };
+
+    for (id, recipient, user_id, code_hash) in entries {
+        if let Some(hash) = code_hash {
+            let conn = db.get().map_err(|e| e.to_string())?;
+            conn.execute(
+                "DELETE FROM activation_codes WHERE code_hash = ? AND used_at IS NULL",
+                [hash],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+
+        let result = send_activation_email(
+            db,
+            email_service,
+            templates,
+            user_id,
+            &recipient,
+            code_ttl_secs,
+        )
+        .await;
+        let conn = db.get().map_err(|e| e.to_string())?;
+        match result {
+            Ok(()) => {
+                conn.execute("DELETE FROM failed_emails WHERE id = ?", [id])
+                    .map_err(|e| e.to_string())?;
+                summary.sent += 1;
+            }
+            Err(e) => {
+                warn!(error = %e.error, recipient = %recipient, "Retry failed");
+                conn.execute(
+                    "UPDATE failed_emails
+                     SET code_hash = ?, error = ?, attempts = attempts + 1, last_attempt_at = ?
+                     WHERE id = ?",
+                    rusqlite::params![e.code_hash, e.error, now_timestamp(), id],
+                )
+                .map_err(|e| e.to_string())?;
+                summary.failed += 1;
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+// --- Notifications ---
+
+#[derive(Deserialize)]
+pub struct TicketReplyRequest {
+    pub user_id: i64,
+    /// Ticket as shown to the customer, e.g. `CD-000042`
+    pub ticket: String,
+}
+
+/// Emails a customer that support replied to their ticket. Called by the
+/// support server, which only knows user ids, with an admin API key.
+pub async fn notify_ticket_reply(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    JsonBody(req): JsonBody<TicketReplyRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    require_admin(&state, &headers)?;
+
+    let Some(email_service) = state.email.clone() else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Email not configured".into(),
+        ));
+    };
+
+    let (recipient, language): (String, String) = {
+        let conn = db_conn(&state.db)?;
+        conn.query_row(
+            "SELECT email, language FROM users WHERE id = ? AND deleted_at IS NULL",
+            [req.user_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .map_err(|_| (StatusCode::NOT_FOUND, "User not found".to_string()))?
+    };
+
+    let templates = state.templates.load_full();
+    email_service
+        .send_ticket_reply(&recipient, &req.ticket, templates.ticket_reply(&language))
+        .await
+        .map_err(|e| {
+            error!(error = %e, recipient = %recipient, "Failed to send reply notification");
+            (StatusCode::BAD_GATEWAY, "Failed to send email".to_string())
+        })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Liveness/readiness probe; needs no API key. Returns 503 if the database
+/// does not answer a trivial query.
+pub async fn health(State(state): State<AppState>) -> (StatusCode, Json<HealthStatus>) {
+    let db = state
+        .db
+        .get()
+        .map(|conn| conn.query_row("SELECT 1", [], |_| Ok(())).is_ok())
+        .unwrap_or(false);
+
+    let status = if db {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(HealthStatus::from_db_check(db)))
+}
+
+// --- Debug endpoints ---
+
+/// Resolves the owner of the `x-api-key` header, applying the same checks as
+/// `/validate`.
+fn require_user(state: &AppState, headers: &HeaderMap) -> Result<UserInfo, (StatusCode, String)> {
+    let api_key = headers
+        .get("x-api-key")
+        .and_then(|v| v.to_str().ok())
+        .ok_or((
+            StatusCode::UNAUTHORIZED,
+            "Missing X-API-Key header".to_string(),
+        ))?;
+
+    let conn = db_conn(&state.db)?;
+    match lookup_user(&conn, api_key, state.key_config.pepper.as_deref()) {
+        Some(user) if !subscription_blocked(state, &user) => Ok(user),
+        _ => Err((StatusCode::UNAUTHORIZED, "Invalid API key".into())),
+    }
+}
+
+/// Rejects the request unless `x-api-key` belongs to an admin.
+fn require_admin(state: &AppState, headers: &HeaderMap) -> Result<(), (StatusCode, String)> {
+    match require_user(state, headers)? {
+        user if user.role == "admin" => Ok(()),
+        _ => Err((StatusCode::FORBIDDEN, "Admin access required".into())),
+    }
+}
+
+/// Reports the effective rate-limit parameters and how many IPs are tracked.
+pub async fn rate_limit_status(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<RateLimitStatus>, (StatusCode, String)> {
+    require_admin(&state, &headers)?;
+    Ok(Json(state.rate_limiter.status()))
+}
+
+/// Clears all per-IP limiter state without restarting the server.
+pub async fn reset_rate_limit(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<StatusCode, (StatusCode, String)> {
+    require_admin(&state, &headers)?;
+    state.rate_limiter.reset();
+    Ok(StatusCode::NO_CONTENT)
+}
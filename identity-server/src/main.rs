@@ -2,26 +2,66 @@ mod cli;
 mod crypto;
 mod db;
 mod email;
+mod email_backend;
+mod extract;
 mod handlers;
+mod migrations;
+mod rate_limit;
 
+use std::io::IsTerminal;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
-use axum::{Router, routing::post};
-use clap::{Parser, Subcommand};
-use tower_governor::GovernorLayer;
-use tower_governor::governor::GovernorConfigBuilder;
+use arc_swap::ArcSwap;
+use axum::{
+    BoxError, Router,
+    error_handling::HandleErrorLayer,
+    http::{HeaderValue, Method, StatusCode, header},
+    middleware,
+    routing::{get, post},
+};
+use clap::builder::PossibleValuesParser;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use tower::ServiceBuilder;
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower_http::cors::CorsLayer;
+use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
+use tracing::{Level, info, warn};
+use tracing_subscriber::EnvFilter;
 
+use crypto::KeyConfig;
 use db::DbPool;
-use email::EmailService;
+use email::{EmailService, EmailTemplate, QuietHours, Templates};
+use email_backend::{EmailBackend, ResendBackend, SmtpBackend, SmtpConfig, SmtpTls};
+use rate_limit::{IpRateLimiter, KeyRateLimiter, RateLimitConfig};
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: DbPool,
     pub email: Option<Arc<EmailService>>,
+    /// Swapped on SIGHUP when the email templates are reloaded
+    pub templates: Arc<ArcSwap<Templates>>,
+    pub jwt: Option<Arc<JwtConfig>>,
+    pub quiet_hours: Option<QuietHours>,
+    pub rate_limiter: Arc<IpRateLimiter>,
+    pub key_rate_limiter: Arc<KeyRateLimiter>,
+    /// Lifetime of newly issued activation codes
+    pub code_ttl_secs: i64,
+    /// Keys of users with an inactive subscription don't validate
+    pub enforce_subscription: bool,
+    /// Shape of keys issued on activation
+    pub key_config: KeyConfig,
 }
 
-#[derive(Parser)]
+/// Shared secret and lifetime for tokens issued by `/token`
+pub struct JwtConfig {
+    pub secret: String,
+    pub ttl_secs: i64,
+}
+
+#[derive(Parser, Clone)]
 #[command(name = "identity-server")]
 #[command(about = "Identity and API key management for Curadesk")]
 struct Args {
@@ -33,23 +73,149 @@ struct Args {
     #[arg(long, default_value = "identity.db")]
     db_path: String,
 
+    /// Hours until a newly issued activation code expires
+    #[arg(long, default_value = "24")]
+    code_ttl_hours: i64,
+
+    /// Label in front of newly generated API keys, e.g. sk_live or sk_test.
+    /// Keys with other labels keep working.
+    #[arg(long, default_value = "sk", value_parser = crypto::parse_key_label)]
+    key_prefix: String,
+
+    /// Secret mixed into the hashes of new API keys (or use IDENTITY_KEY_PEPPER
+    /// env var). Keys created with it stop validating if it changes or is unset.
+    #[arg(long, env = "IDENTITY_KEY_PEPPER")]
+    key_pepper: Option<String>,
+
+    /// Use a throwaway in-memory database instead of --db-path
+    #[arg(long)]
+    in_memory: bool,
+
+    /// Service that delivers emails
+    #[arg(long, value_enum, default_value_t = EmailBackendKind::Resend)]
+    email_backend: EmailBackendKind,
+
     /// Resend API key for sending emails (or use RESEND_API_KEY env var)
     #[arg(long, env = "RESEND_API_KEY")]
     resend_api_key: Option<String>,
 
+    /// SMTP server, required with --email-backend smtp
+    #[arg(long)]
+    smtp_host: Option<String>,
+
+    /// SMTP server port
+    #[arg(long, default_value = "587")]
+    smtp_port: u16,
+
+    /// How the SMTP connection is encrypted
+    #[arg(long, value_enum, default_value_t = SmtpTls::Starttls)]
+    smtp_tls: SmtpTls,
+
+    /// SMTP login user name
+    #[arg(long)]
+    smtp_username: Option<String>,
+
+    /// SMTP login password (or use SMTP_PASSWORD env var)
+    #[arg(long, env = "SMTP_PASSWORD")]
+    smtp_password: Option<String>,
+
     /// Email sender address
     #[arg(long, default_value = "CuraDesk <kontakt@curadesk.de>")]
     email_from: String,
 
-    /// Custom email template file path (optional, defaults to embedded template)
+    /// Sender display name, overrides the name in --email-from
+    #[arg(long)]
+    email_from_name: Option<String>,
+
+    /// Sender address, overrides the address in --email-from
+    #[arg(long)]
+    email_from_address: Option<String>,
+
+    /// Reply-To address for outgoing emails, e.g. the support inbox
+    #[arg(long)]
+    email_reply_to: Option<String>,
+
+    /// Custom German email template file path or http(s) URL (optional,
+    /// defaults to embedded template). Re-read on SIGHUP.
     #[arg(long)]
     email_template: Option<String>,
 
+    /// Directory with `activation_email.{lang}.html` and `ticket_reply_email.{lang}.html`
+    /// templates, used for users of that language. Missing languages fall back
+    /// to German. Re-read on SIGHUP.
+    #[arg(long)]
+    templates_dir: Option<PathBuf>,
+
+    /// Shared HS256 secret for issuing JWTs via /token (or use IDENTITY_JWT_SECRET env var)
+    #[arg(long, env = "IDENTITY_JWT_SECRET")]
+    jwt_secret: Option<String>,
+
+    /// Lifetime of issued JWTs in seconds
+    #[arg(long, default_value = "900")]
+    jwt_ttl_secs: i64,
+
+    /// Hours during which activation emails are queued instead of sent, e.g. 22-7
+    #[arg(long)]
+    quiet_hours: Option<String>,
+
+    /// IANA timezone the quiet hours are interpreted in
+    #[arg(long, default_value = "Europe/Berlin")]
+    quiet_hours_tz: String,
+
+    /// Per-IP rate limit: seconds until one more request is allowed
+    #[arg(long, default_value = "1")]
+    rate_limit_replenish_secs: u64,
+
+    /// Per-IP rate limit: requests allowed in a burst
+    #[arg(long, default_value = "5")]
+    rate_limit_burst: u32,
+
+    /// TOML file with `replenish_secs` and/or `burst`, overriding the flags
+    /// above. Re-read on SIGHUP.
+    #[arg(long)]
+    rate_limit_config: Option<String>,
+
+    /// Reject API keys of users whose subscription is inactive
+    #[arg(long)]
+    enforce_subscription: bool,
+
+    /// Comma-separated origins allowed to call the API from a browser, e.g.
+    /// https://app.curadesk.de. Without any, no CORS headers are sent.
+    #[arg(long, value_delimiter = ',')]
+    allowed_origins: Vec<String>,
+
+    /// Attempts per minute allowed for a single API key on /validate and
+    /// /token, on top of the per-IP limit
+    #[arg(long, default_value = "60")]
+    per_key_rate: u32,
+
+    /// Expose /debug/rate-limit endpoints (admin key required)
+    #[arg(long)]
+    debug_endpoints: bool,
+
+    /// Maximum number of requests handled concurrently; excess requests get 503
+    #[arg(long, default_value = "64")]
+    max_concurrent_requests: usize,
+
+    /// Log output format; levels can be tuned with RUST_LOG
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Print list-users, list-keys, list-activation-codes and audit-log as a
+    /// table or as JSON
+    #[arg(long, value_enum, global = true, default_value_t = cli::OutputFormat::Table)]
+    output: cli::OutputFormat,
+
+    /// Show what create-user, create-key, revoke-key, rotate-keys,
+    /// create-activation-code or seed would change, then roll it back
+    #[arg(long, global = true)]
+    dry_run: bool,
+
     #[command(subcommand)]
     command: Option<Command>,
 }
 
-#[derive(Subcommand)]
+#[derive(Subcommand, Clone)]
 enum Command {
     /// Start the HTTP server
     Serve {
@@ -67,16 +233,77 @@ enum Command {
     CreateKey {
         #[arg(long)]
         user_id: i64,
+        /// Let the key expire after this many days
+        #[arg(long)]
+        ttl_days: Option<i64>,
+        /// Comma-separated scopes to limit the key to, e.g. tickets:read;
+        /// full access without
+        #[arg(
+            long,
+            value_delimiter = ',',
+            value_parser = PossibleValuesParser::new(support_common::SCOPES)
+        )]
+        scopes: Vec<String>,
     },
     /// Revoke an API key by prefix
     RevokeKey {
         #[arg(long)]
         prefix: String,
     },
+    /// Create users from a CSV file with `email,role` columns
+    ImportUsers {
+        path: PathBuf,
+        /// Also create an activation code for every imported user
+        #[arg(long)]
+        with_codes: bool,
+    },
+    /// Revoke all of a user's keys and issue one new key
+    RotateKeys {
+        #[arg(long)]
+        user_id: i64,
+        /// Comma-separated scopes for the new key; without, it keeps the
+        /// scopes of the keys it replaces
+        #[arg(
+            long,
+            value_delimiter = ',',
+            value_parser = PossibleValuesParser::new(support_common::SCOPES)
+        )]
+        scopes: Vec<String>,
+    },
+    /// Change a user's subscription status
+    SetSubscription {
+        #[arg(long)]
+        user_id: i64,
+        #[arg(long, value_parser = ["trial", "active", "inactive"])]
+        status: String,
+    },
+    /// Soft-delete a user; their keys stop validating
+    DeleteUser {
+        #[arg(long)]
+        user_id: i64,
+    },
     /// List all users
-    ListUsers,
-    /// List all API keys
-    ListKeys,
+    ListUsers {
+        /// Also list deleted users
+        #[arg(long)]
+        include_deleted: bool,
+    },
+    /// List API keys, oldest first
+    ListKeys {
+        #[arg(long)]
+        user_id: Option<i64>,
+        #[arg(long, value_enum)]
+        status: Option<cli::KeyStatus>,
+        /// Only keys unused for at least this many days, to find ones to revoke
+        #[arg(long)]
+        idle_days: Option<i64>,
+        /// Show at most this many keys
+        #[arg(long)]
+        limit: Option<i64>,
+        /// Skip this many keys first
+        #[arg(long, default_value = "0")]
+        offset: i64,
+    },
     /// Seed development data
     Seed,
     /// Create an activation code for a user
@@ -84,125 +311,813 @@ enum Command {
         #[arg(long)]
         user_id: i64,
     },
-    /// List all activation codes
-    ListActivationCodes,
+    /// List activation codes, oldest first
+    ListActivationCodes {
+        #[arg(long)]
+        user_id: Option<i64>,
+        #[arg(long, value_enum)]
+        status: Option<cli::CodeStatus>,
+        /// Show at most this many codes
+        #[arg(long)]
+        limit: Option<i64>,
+        /// Skip this many codes first
+        #[arg(long, default_value = "0")]
+        offset: i64,
+    },
+    /// Require a TOTP code from this user's authenticator app on activation
+    EnrollTotp {
+        #[arg(long)]
+        user_id: i64,
+    },
+    /// Re-send activation emails that previously failed to deliver
+    RetryFailedEmails {
+        /// Only retry failures from the last N days
+        #[arg(long, default_value = "7")]
+        max_age_days: i64,
+    },
+    /// Show recent key, activation code and user lifecycle events
+    AuditLog {
+        /// Number of entries to show, newest first
+        #[arg(long, default_value = "50")]
+        limit: i64,
+    },
+    /// Verify database integrity and that the encryption key is correct
+    Verify,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum EmailBackendKind {
+    /// Resend's HTTP API, needs --resend-api-key
+    Resend,
+    /// Any SMTP server, configured with the --smtp-* flags
+    Smtp,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line, for log aggregation
+    Json,
+}
+
+/// Logs go to stderr so command output on stdout stays clean.
+fn init_logging(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal());
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
 }
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
+    init_logging(args.log_format);
 
-    let db = db::init_db(&args.db_path, &args.db_key).expect("Failed to initialize database");
-
-    match args.command {
-        Some(Command::Serve { port }) => {
-            // Load email template
-            let template = match &args.email_template {
-                Some(path) => std::fs::read_to_string(path).expect("Failed to read email template"),
-                None => include_str!("templates/activation_email.html").to_string(),
-            };
+    if let Some(Command::Verify) = args.command {
+        verify(&args.db_path, &args.db_key);
+        return;
+    }
 
-            // Create email service if API key is provided
-            let email_service = args
-                .resend_api_key
-                .as_ref()
-                .map(|key| Arc::new(EmailService::new(key, args.email_from.clone(), template)));
+    let dry_run_supported = matches!(
+        args.command,
+        Some(
+            Command::CreateUser { .. }
+                | Command::CreateKey { .. }
+                | Command::RevokeKey { .. }
+                | Command::RotateKeys { .. }
+                | Command::CreateActivationCode { .. }
+                | Command::Seed
+        )
+    );
+    if args.dry_run && !dry_run_supported {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--dry-run is only supported by create-user, create-key, revoke-key, \
+                 rotate-keys, create-activation-code and seed",
+            )
+            .exit();
+    }
 
-            let state = AppState {
-                db,
-                email: email_service,
-            };
+    let db_path = if args.in_memory {
+        ":memory:"
+    } else {
+        &args.db_path
+    };
+    let db = db::init_db(db_path, &args.db_key).expect("Failed to initialize database");
+    let code_ttl_secs = args.code_ttl_hours * 60 * 60;
 
-            // Rate limiting: 5 burst, replenish 1 per second
-            let governor_conf = GovernorConfigBuilder::default()
-                .per_second(1)
-                .burst_size(5)
-                .finish()
-                .unwrap();
-
-            let app = Router::new()
-                .route("/validate", post(handlers::validate))
-                .route("/activate", post(handlers::activate))
-                .route("/register", post(handlers::register))
-                .layer(GovernorLayer {
-                    config: Arc::new(governor_conf),
-                })
-                .with_state(state);
-
-            let addr = format!("0.0.0.0:{}", port);
-            println!("Identity server running on http://{}", addr);
-            let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-            axum::serve(
-                listener,
-                app.into_make_service_with_connect_info::<SocketAddr>(),
-            )
-            .await
-            .unwrap();
-        }
+    match args.command {
+        Some(Command::Serve { port }) => serve(&args, db, port).await,
         Some(Command::CreateUser { email, role }) => {
-            cli::create_user(&db, &email, &role).expect("Failed to create user");
+            cli::create_user(&db, &email, &role, args.dry_run).expect("Failed to create user");
         }
-        Some(Command::CreateKey { user_id }) => {
-            cli::create_key(&db, user_id).expect("Failed to create key");
+        Some(Command::CreateKey {
+            user_id,
+            ttl_days,
+            ref scopes,
+        }) => {
+            cli::create_key(
+                &db,
+                &key_config(&args),
+                user_id,
+                ttl_days,
+                scopes,
+                args.dry_run,
+            )
+            .expect("Failed to create key");
         }
         Some(Command::RevokeKey { prefix }) => {
-            cli::revoke_key(&db, &prefix).expect("Failed to revoke key");
+            cli::revoke_key(&db, &prefix, args.dry_run).expect("Failed to revoke key");
         }
-        Some(Command::ListUsers) => {
-            cli::list_users(&db).expect("Failed to list users");
+        Some(Command::ImportUsers { path, with_codes }) => {
+            cli::import_users(&db, &path, with_codes, code_ttl_secs)
+                .expect("Failed to import users");
         }
-        Some(Command::ListKeys) => {
-            cli::list_keys(&db).expect("Failed to list keys");
+        Some(Command::RotateKeys {
+            user_id,
+            ref scopes,
+        }) => {
+            cli::rotate_keys(&db, &key_config(&args), user_id, scopes, args.dry_run)
+                .expect("Failed to rotate keys");
+        }
+        Some(Command::SetSubscription { user_id, status }) => {
+            cli::set_subscription(&db, user_id, &status).expect("Failed to set subscription");
+        }
+        Some(Command::DeleteUser { user_id }) => {
+            cli::delete_user(&db, user_id).expect("Failed to delete user");
+        }
+        Some(Command::ListUsers { include_deleted }) => {
+            cli::list_users(&db, include_deleted, args.output).expect("Failed to list users");
+        }
+        Some(Command::ListKeys {
+            user_id,
+            status,
+            idle_days,
+            limit,
+            offset,
+        }) => {
+            let filter = cli::ListFilter {
+                user_id,
+                status,
+                limit,
+                offset,
+            };
+            cli::list_keys(&db, &filter, idle_days, args.output).expect("Failed to list keys");
         }
         Some(Command::Seed) => {
-            cli::seed_dev_data(&db).expect("Failed to seed data");
+            cli::seed_dev_data(&db, &key_config(&args), code_ttl_secs, args.dry_run)
+                .expect("Failed to seed data");
         }
         Some(Command::CreateActivationCode { user_id }) => {
-            cli::create_activation_code(&db, user_id).expect("Failed to create activation code");
-        }
-        Some(Command::ListActivationCodes) => {
-            cli::list_activation_codes(&db).expect("Failed to list activation codes");
+            cli::create_activation_code(&db, user_id, code_ttl_secs, args.dry_run)
+                .expect("Failed to create activation code");
         }
-        None => {
-            // Default to serve on port 3001
-            let template = match &args.email_template {
-                Some(path) => std::fs::read_to_string(path).expect("Failed to read email template"),
-                None => include_str!("templates/activation_email.html").to_string(),
+        Some(Command::ListActivationCodes {
+            user_id,
+            status,
+            limit,
+            offset,
+        }) => {
+            let filter = cli::ListFilter {
+                user_id,
+                status,
+                limit,
+                offset,
             };
+            cli::list_activation_codes(&db, &filter, args.output)
+                .expect("Failed to list activation codes");
+        }
+        Some(Command::EnrollTotp { user_id }) => {
+            cli::enroll_totp(&db, user_id).expect("Failed to enroll TOTP");
+        }
+        Some(Command::RetryFailedEmails { max_age_days }) => {
+            let email_service = email_service(&args)
+                .expect("Invalid email configuration")
+                .expect("RetryFailedEmails requires a configured email backend");
+            let templates = templates(&args)
+                .await
+                .expect("Failed to load email templates");
+            cli::retry_failed_emails(&db, &email_service, &templates, max_age_days, code_ttl_secs)
+                .await
+                .expect("Failed to retry emails");
+        }
+        Some(Command::AuditLog { limit }) => {
+            cli::audit_log(&db, limit, args.output).expect("Failed to read audit log");
+        }
+        Some(Command::Verify) => unreachable!("handled before opening the database"),
+        // Default to serve on port 3001
+        None => serve(&args, db, 3001).await,
+    }
+}
 
-            let email_service = args
-                .resend_api_key
-                .as_ref()
-                .map(|key| Arc::new(EmailService::new(key, args.email_from.clone(), template)));
+fn key_config(args: &Args) -> KeyConfig {
+    KeyConfig {
+        label: args.key_prefix.clone(),
+        pepper: args.key_pepper.clone().filter(|pepper| !pepper.is_empty()),
+        ..KeyConfig::default()
+    }
+}
 
-            let state = AppState {
-                db,
-                email: email_service,
+/// Creates the email service if the selected backend is configured: Resend
+/// needs an API key, SMTP a host.
+fn email_service(args: &Args) -> Result<Option<Arc<EmailService>>, String> {
+    let from = email::sender(
+        &args.email_from,
+        args.email_from_name.as_deref(),
+        args.email_from_address.as_deref(),
+    );
+
+    let backend: Box<dyn EmailBackend> = match args.email_backend {
+        EmailBackendKind::Resend => {
+            let Some(key) = args.resend_api_key.as_ref() else {
+                return Ok(None);
+            };
+            Box::new(ResendBackend::new(key, from, args.email_reply_to.clone()))
+        }
+        EmailBackendKind::Smtp => {
+            let host = args
+                .smtp_host
+                .as_deref()
+                .ok_or("--email-backend smtp requires --smtp-host")?;
+            let config = SmtpConfig {
+                host,
+                port: args.smtp_port,
+                tls: args.smtp_tls,
+                username: args.smtp_username.as_deref(),
+                password: args.smtp_password.as_deref(),
             };
+            Box::new(SmtpBackend::new(
+                &config,
+                &from,
+                args.email_reply_to.as_deref(),
+            )?)
+        }
+    };
 
-            let governor_conf = GovernorConfigBuilder::default()
-                .per_second(1)
-                .burst_size(5)
-                .finish()
-                .unwrap();
+    Ok(Some(Arc::new(EmailService::new(backend))))
+}
 
-            let app = Router::new()
-                .route("/validate", post(handlers::validate))
-                .route("/activate", post(handlers::activate))
-                .route("/register", post(handlers::register))
-                .layer(GovernorLayer {
-                    config: Arc::new(governor_conf),
-                })
-                .with_state(state);
-
-            println!("Identity server running on http://0.0.0.0:3001");
-            let listener = tokio::net::TcpListener::bind("0.0.0.0:3001").await.unwrap();
-            axum::serve(
-                listener,
-                app.into_make_service_with_connect_info::<SocketAddr>(),
-            )
+/// The embedded templates, extended by `--templates-dir`, with the German one
+/// replaced by `--email-template` if given.
+async fn templates(args: &Args) -> Result<Templates, String> {
+    let mut templates = Templates::embedded();
+    if let Some(dir) = &args.templates_dir {
+        templates.load_dir(dir)?;
+    }
+    if let Some(source) = &args.email_template {
+        let html = load_template(source).await?;
+        templates.insert(email::DEFAULT_LANGUAGE, EmailTemplate::new(html, None));
+    }
+    Ok(templates)
+}
+
+/// Reads the email template from a file, or downloads it if `source` is a URL.
+async fn load_template(source: &str) -> Result<String, String> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let response = reqwest::get(source)
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("Failed to fetch email template: {}", e))?;
+        response
+            .text()
             .await
+            .map_err(|e| format!("Failed to fetch email template: {}", e))
+    } else {
+        std::fs::read_to_string(source)
+            .map_err(|e| format!("Failed to read email template {}: {}", source, e))
+    }
+}
+
+/// Rate-limit parameters from the flags, overridden by `--rate-limit-config`.
+fn rate_limit_params(args: &Args) -> Result<(u64, u32), String> {
+    let mut params = (args.rate_limit_replenish_secs, args.rate_limit_burst);
+    if let Some(path) = &args.rate_limit_config {
+        let config = RateLimitConfig::load(path)?;
+        params.0 = config.replenish_secs.unwrap_or(params.0);
+        params.1 = config.burst.unwrap_or(params.1);
+    }
+    Ok(params)
+}
+
+/// Re-reads the email templates and rate-limit config. On failure the old
+/// settings stay in place so a bad edit never takes the server down.
+async fn reload(args: &Args, state: &AppState) {
+    match templates(args).await {
+        Ok(templates) => {
+            state.templates.store(Arc::new(templates));
+            info!("Reloaded email templates");
+        }
+        Err(e) => warn!(error = %e, "Reload: keeping previous email templates"),
+    }
+
+    match rate_limit_params(args)
+        .and_then(|(replenish, burst)| state.rate_limiter.reconfigure(replenish, burst))
+    {
+        Ok(()) => info!("Reloaded rate-limit configuration"),
+        Err(e) => warn!(error = %e, "Reload: keeping previous rate limits"),
+    }
+}
+
+async fn serve(args: &Args, db: DbPool, port: u16) {
+    let email_service = email_service(args).expect("Invalid email configuration");
+    let templates = templates(args)
+        .await
+        .expect("Failed to load email templates");
+
+    let jwt = args.jwt_secret.as_ref().map(|secret| {
+        Arc::new(JwtConfig {
+            secret: secret.clone(),
+            ttl_secs: args.jwt_ttl_secs,
+        })
+    });
+
+    let quiet_hours = args.quiet_hours.as_ref().map(|range| {
+        QuietHours::parse(range, &args.quiet_hours_tz).expect("Invalid quiet hours configuration")
+    });
+
+    let (replenish_secs, burst) =
+        rate_limit_params(args).expect("Invalid rate limit configuration");
+    let rate_limiter =
+        IpRateLimiter::new(replenish_secs, burst).expect("Invalid rate limit configuration");
+    let key_rate_limiter =
+        KeyRateLimiter::new(args.per_key_rate).expect("Invalid per-key rate limit");
+
+    let state = AppState {
+        db,
+        email: email_service,
+        templates: Arc::new(ArcSwap::from_pointee(templates)),
+        jwt,
+        quiet_hours,
+        rate_limiter: Arc::new(rate_limiter),
+        key_rate_limiter: Arc::new(key_rate_limiter),
+        code_ttl_secs: args.code_ttl_hours * 60 * 60,
+        enforce_subscription: args.enforce_subscription,
+        key_config: key_config(args),
+    };
+
+    // Keys that were seen once shouldn't be tracked forever
+    {
+        let key_rate_limiter = state.key_rate_limiter.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                key_rate_limiter.prune();
+            }
+        });
+    }
+
+    // Deliver emails queued during quiet hours once the window is over
+    if state.email.is_some() {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                handlers::flush_pending_emails(&state).await;
+            }
+        });
+    }
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut hangup = signal(SignalKind::hangup()).expect("Failed to install SIGHUP handler");
+        let args = args.clone();
+        let state = state.clone();
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                reload(&args, &state).await;
+            }
+        });
+    }
+
+    let cors = cors_layer(&args.allowed_origins).expect("Invalid --allowed-origins");
+    let app = app(
+        state,
+        args.max_concurrent_requests,
+        args.debug_endpoints,
+        cors,
+    );
+
+    let addr = format!("0.0.0.0:{}", port);
+    info!("Identity server running on http://{}", addr);
+    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await
+    .unwrap();
+    info!("Shut down cleanly");
+}
+
+/// Resolves on Ctrl-C or SIGTERM; the server then stops accepting connections
+/// and lets in-flight requests finish.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl-C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    info!("Shutting down, waiting for in-flight requests");
+}
+
+/// CORS for browser clients posting JSON, or `None` if no origins are allowed.
+fn cors_layer(origins: &[String]) -> Result<Option<CorsLayer>, String> {
+    if origins.is_empty() {
+        return Ok(None);
+    }
+    let origins = origins
+        .iter()
+        .map(|origin| {
+            HeaderValue::from_str(origin.trim()).map_err(|_| format!("Invalid origin '{}'", origin))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods([Method::POST])
+            .allow_headers([header::CONTENT_TYPE]),
+    ))
+}
+
+/// Builds the full router; also usable with `tower::ServiceExt::oneshot` in tests.
+fn app(
+    state: AppState,
+    max_concurrent_requests: usize,
+    debug_endpoints: bool,
+    cors: Option<CorsLayer>,
+) -> Router {
+    // Shed requests beyond the limit instead of queueing them on the DB pool.
+    // `Router::layer` wraps every route on its own, so the semaphore is
+    // created here to be shared by all of them.
+    let concurrency_limit = ServiceBuilder::new()
+        .layer(HandleErrorLayer::new(|_: BoxError| async {
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Server busy, try again later",
+            )
+        }))
+        .load_shed()
+        .layer(GlobalConcurrencyLimitLayer::new(max_concurrent_requests));
+
+    // Routes taking an `api_key` in the body are also limited per key
+    let keyed_routes = Router::new()
+        .route("/validate", post(handlers::validate))
+        .route("/token", post(handlers::token))
+        .route_layer(middleware::from_fn_with_state(
+            state.key_rate_limiter.clone(),
+            rate_limit::limit_by_key,
+        ));
+
+    let mut router = Router::new()
+        .merge(keyed_routes)
+        .route("/whoami", get(handlers::whoami))
+        .route("/keys", get(handlers::list_keys))
+        .route("/keys/revoke", post(handlers::revoke_key))
+        .route("/activate", post(handlers::activate))
+        .route("/register", post(handlers::register))
+        .route("/notify/ticket-reply", post(handlers::notify_ticket_reply));
+
+    if debug_endpoints {
+        router = router
+            .route("/debug/rate-limit", get(handlers::rate_limit_status))
+            .route("/debug/rate-limit/reset", post(handlers::reset_rate_limit));
+    }
+
+    router = router
+        .layer(concurrency_limit)
+        .layer(middleware::from_fn_with_state(
+            state.rate_limiter.clone(),
+            rate_limit::limit_by_ip,
+        ));
+
+    // Outside the rate limit: preflights are answered here without using up
+    // the client's quota, and 429s still carry CORS headers the browser needs
+    if let Some(cors) = cors {
+        router = router.layer(cors);
+    }
+
+    router
+        // One span per request, so log lines from a handler can be correlated
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+        // Added after the layers so probes are never rate limited, shed or traced
+        .route("/health", get(handlers::health))
+        .with_state(state)
+}
+
+fn verify(db_path: &str, db_key: &str) {
+    match db::verify_db(db_path, db_key) {
+        Ok(counts) => {
+            println!("{:<25} Rows", "Table");
+            println!("{}", "-".repeat(35));
+            for (table, count) in counts {
+                println!("{:<25} {}", table, count);
+            }
+            println!("\nDatabase {} OK", db_path);
+        }
+        Err(e) => {
+            eprintln!("Verification of {} failed: {}", db_path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{Body, Bytes, to_bytes};
+    use axum::extract::connect_info::MockConnectInfo;
+    use axum::http::Request;
+    use chrono::Timelike;
+    use serde_json::{Value, json};
+    use std::sync::atomic::Ordering;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// State on a fresh in-memory database, without email or JWTs.
+    fn test_state() -> AppState {
+        AppState {
+            db: db::init_db(":memory:", "test").unwrap(),
+            email: None,
+            templates: Arc::new(ArcSwap::from_pointee(Templates::embedded())),
+            jwt: None,
+            quiet_hours: None,
+            rate_limiter: Arc::new(IpRateLimiter::new(1, 1000).unwrap()),
+            key_rate_limiter: Arc::new(KeyRateLimiter::new(1000).unwrap()),
+            code_ttl_secs: 24 * 60 * 60,
+            enforce_subscription: false,
+            key_config: KeyConfig::default(),
+        }
+    }
+
+    /// Sends email from `state` through the returned mock backend.
+    fn mock_email(state: &mut AppState) -> email_backend::MockBackend {
+        let backend = email_backend::MockBackend::default();
+        state.email = Some(Arc::new(EmailService::new(Box::new(backend.clone()))));
+        backend
+    }
+
+    fn register_request(email: &str) -> Request<Body> {
+        Request::post("/register")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({ "email": email }).to_string()))
+            .unwrap()
+    }
+
+    fn count(db: &DbPool, table: &str) -> i64 {
+        db.get()
+            .unwrap()
+            .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    /// A request body that stays open until the sender is dropped
+    fn held_body() -> (tokio::sync::mpsc::Sender<Bytes>, Body) {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Bytes>(1);
+        let chunks = futures_util::stream::unfold(rx, |mut rx| async move {
+            let chunk = rx.recv().await?;
+            Some((Ok::<_, std::io::Error>(chunk), rx))
+        });
+        (tx, Body::from_stream(chunks))
+    }
+
+    fn router(state: AppState) -> Router {
+        // The IP rate limit needs the peer address `serve` would provide
+        app(state, 8, false, None).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))))
+    }
+
+    /// The full router on a fresh in-memory database, without email or JWTs.
+    fn test_app() -> (Router, DbPool) {
+        let state = test_state();
+        let db = state.db.clone();
+        (router(state), db)
+    }
+
+    async fn send(app: &Router, request: Request<Body>) -> (StatusCode, Value) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn validates_a_created_key() {
+        let (app, db) = test_app();
+        let user_id = cli::create_user(&db, "support@example.com", "support", false).unwrap();
+        let key = cli::create_key(&db, &KeyConfig::default(), user_id, None, &[], false).unwrap();
+
+        let request = Request::post("/validate")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({ "api_key": key }).to_string()))
+            .unwrap();
+        let (status, body) = send(&app, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["valid"], true);
+        assert_eq!(body["user"]["id"], user_id);
+        assert_eq!(body["user"]["email"], "support@example.com");
+        assert_eq!(body["user"]["role"], "support");
+
+        let request = Request::get("/whoami")
+            .header("x-api-key", &key)
+            .body(Body::empty())
             .unwrap();
+        let (status, body) = send(&app, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["id"], user_id);
+    }
+
+    #[tokio::test]
+    async fn rejects_an_unknown_key() {
+        let (app, _db) = test_app();
+
+        let request = Request::post("/validate")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({ "api_key": "sk_nope" }).to_string()))
+            .unwrap();
+        let (status, body) = send(&app, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["valid"], false);
+        assert_eq!(body["error"], "Invalid or revoked API key");
+    }
+
+    #[tokio::test]
+    async fn issues_a_token_for_a_key() {
+        let mut state = test_state();
+        state.jwt = Some(Arc::new(JwtConfig {
+            secret: "test-secret".to_string(),
+            ttl_secs: 900,
+        }));
+        let db = state.db.clone();
+        let app = router(state);
+        let user_id = cli::create_user(&db, "customer@example.com", "customer", false).unwrap();
+        let key = cli::create_key(&db, &KeyConfig::default(), user_id, None, &[], false).unwrap();
+
+        let request = Request::post("/token")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({ "api_key": key }).to_string()))
+            .unwrap();
+        let (status, body) = send(&app, request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let claims = jsonwebtoken::decode::<support_common::TokenClaims>(
+            body["token"].as_str().unwrap(),
+            &jsonwebtoken::DecodingKey::from_secret(b"test-secret"),
+            &jsonwebtoken::Validation::default(),
+        )
+        .unwrap()
+        .claims;
+        assert_eq!(claims.sub, user_id);
+        assert_eq!(claims.email, "customer@example.com");
+        assert_eq!(claims.role, "customer");
+        assert_eq!(claims.exp, claims.iat + 900);
+        assert_eq!(body["expires_at"], claims.exp);
+    }
+
+    #[tokio::test]
+    async fn failed_email_is_dead_lettered_and_retried() {
+        let mut state = test_state();
+        let backend = mock_email(&mut state);
+        let db = state.db.clone();
+        let email_service = state.email.clone().unwrap();
+        let app = router(state);
+
+        backend.fail.store(true, Ordering::SeqCst);
+        let (status, _) = send(&app, register_request("new@example.com")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(count(&db, "failed_emails"), 1);
+        assert!(backend.sent.lock().unwrap().is_empty());
+
+        backend.fail.store(false, Ordering::SeqCst);
+        let summary =
+            handlers::retry_failed_emails(&db, &email_service, &Templates::embedded(), 3600, 3600)
+                .await
+                .unwrap();
+        assert_eq!(summary.sent, 1);
+        assert_eq!(summary.failed, 0);
+        assert_eq!(count(&db, "failed_emails"), 0);
+        assert_eq!(backend.sent.lock().unwrap()[0].to, "new@example.com");
+    }
+
+    #[tokio::test]
+    async fn registration_in_quiet_hours_is_queued() {
+        let mut state = test_state();
+        let backend = mock_email(&mut state);
+        // Every hour but the previous one, so the window is active now
+        let hour = chrono::Utc::now().hour();
+        let range = format!("{}-{}", hour, (hour + 23) % 24);
+        state.quiet_hours = Some(QuietHours::parse(&range, "UTC").unwrap());
+        let db = state.db.clone();
+        let app = router(state);
+
+        for _ in 0..2 {
+            let (status, _) = send(&app, register_request("late@example.com")).await;
+            assert_eq!(status, StatusCode::OK);
         }
+
+        assert_eq!(count(&db, "pending_emails"), 1);
+        assert!(backend.sent.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reload_picks_up_an_edited_template() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("activation_email.de.html");
+        let args = Args::try_parse_from([
+            "identity-server",
+            "--db-key",
+            "test",
+            "--templates-dir",
+            dir.path().to_str().unwrap(),
+        ])
+        .unwrap();
+        let mut state = test_state();
+        let backend = mock_email(&mut state);
+        let app = router(state.clone());
+
+        std::fs::write(&path, "<title>Alt</title><body><p>{{code}}</p></body>").unwrap();
+        reload(&args, &state).await;
+        send(&app, register_request("first@example.com")).await;
+
+        std::fs::write(&path, "<title>Neu</title><body><p>{{code}}</p></body>").unwrap();
+        reload(&args, &state).await;
+        send(&app, register_request("second@example.com")).await;
+
+        let sent = backend.sent.lock().unwrap();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].subject, "Alt");
+        assert_eq!(sent[1].subject, "Neu");
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_concurrency_limit_are_shed() {
+        let (app, db) = {
+            let state = test_state();
+            let db = state.db.clone();
+            let app = app(state, 1, false, None)
+                .layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))));
+            (app, db)
+        };
+        let user_id = cli::create_user(&db, "support@example.com", "support", false).unwrap();
+        let key = cli::create_key(&db, &KeyConfig::default(), user_id, None, &[], false).unwrap();
+        let validate = || {
+            Request::post("/validate")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json!({ "api_key": key }).to_string()))
+                .unwrap()
+        };
+
+        // Holds the only slot while the handler waits for the rest of the body
+        let (tx, body) = held_body();
+        let request = Request::post("/register")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .unwrap();
+        let held = tokio::spawn(app.clone().oneshot(request));
+        tx.send(Bytes::from_static(b"{")).await.unwrap();
+        // Capacity is back once the handler has read the first chunk
+        drop(tx.reserve().await.unwrap());
+
+        let response = app.clone().oneshot(validate()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        drop(tx);
+        let response = held.await.unwrap().unwrap();
+        assert_ne!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let (status, _) = send(&app, validate()).await;
+        assert_eq!(status, StatusCode::OK);
     }
 }
@@ -0,0 +1,272 @@
+//! Schema migrations, tracked in `PRAGMA user_version`.
+//!
+//! Append new migrations to `MIGRATIONS`; never edit or reorder one that has
+//! shipped. Version N means the first N migrations have been applied.
+
+use rusqlite::{Connection, Result, ffi};
+
+type Migration = fn(&Connection) -> Result<()>;
+
+const MIGRATIONS: &[Migration] = &[
+    initial_schema,
+    add_totp_secret,
+    add_code_expiry,
+    add_user_deleted_at,
+    add_audit_log,
+    add_user_language,
+    add_key_hash_version,
+    add_key_scopes,
+    add_key_last_used,
+];
+
+/// Brings the database up to the latest schema version. Each migration runs in
+/// its own transaction together with the version bump.
+pub fn migrate(conn: &mut Connection) -> Result<()> {
+    let current: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    let current = current as usize;
+    if current > MIGRATIONS.len() {
+        return Err(rusqlite::Error::SqliteFailure(
+            ffi::Error::new(ffi::SQLITE_ERROR),
+            Some(format!(
+                "Database schema version {} is newer than this build supports ({})",
+                current,
+                MIGRATIONS.len()
+            )),
+        ));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+        let tx = conn.transaction()?;
+        migration(&tx)?;
+        tx.pragma_update(None, "user_version", (index + 1) as i64)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Schema as of the introduction of migrations. Databases created before then
+/// are at version 0 but may already have some or all of this, so it has to be
+/// idempotent; later migrations need not be.
+fn initial_schema(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS users (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            email TEXT NOT NULL UNIQUE,
+            role TEXT NOT NULL CHECK(role IN ('admin', 'support', 'customer')),
+            subscription_status TEXT NOT NULL DEFAULT 'active' CHECK(subscription_status IN ('active', 'inactive', 'trial')),
+            created_at INTEGER NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS api_keys (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            key_hash TEXT NOT NULL UNIQUE,
+            key_salt TEXT,
+            key_prefix TEXT NOT NULL,
+            user_id INTEGER NOT NULL,
+            created_at INTEGER NOT NULL,
+            revoked_at INTEGER,
+            expires_at INTEGER,
+            FOREIGN KEY (user_id) REFERENCES users(id)
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_api_keys_hash ON api_keys(key_hash);
+        CREATE INDEX IF NOT EXISTS idx_api_keys_prefix ON api_keys(key_prefix);
+        CREATE INDEX IF NOT EXISTS idx_api_keys_user_id ON api_keys(user_id);
+        CREATE INDEX IF NOT EXISTS idx_users_email ON users(email);
+
+        -- Activation codes: one-time use codes that can be exchanged for API keys
+        CREATE TABLE IF NOT EXISTS activation_codes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            code_hash TEXT NOT NULL UNIQUE,
+            code_prefix TEXT NOT NULL,
+            user_id INTEGER NOT NULL,
+            created_at INTEGER NOT NULL,
+            used_at INTEGER,
+            FOREIGN KEY (user_id) REFERENCES users(id)
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_activation_codes_hash ON activation_codes(code_hash);
+        CREATE INDEX IF NOT EXISTS idx_activation_codes_prefix ON activation_codes(code_prefix);
+
+        -- Activation emails deferred during quiet hours; the code is generated when sent
+        CREATE TABLE IF NOT EXISTS pending_emails (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            recipient TEXT NOT NULL,
+            user_id INTEGER NOT NULL,
+            created_at INTEGER NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id)
+        );
+
+        -- Dead-letter log of activation emails the provider rejected; code_hash
+        -- points at the undelivered activation code, if one was created
+        CREATE TABLE IF NOT EXISTS failed_emails (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            recipient TEXT NOT NULL,
+            user_id INTEGER NOT NULL,
+            code_hash TEXT,
+            error TEXT NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 1,
+            created_at INTEGER NOT NULL,
+            last_attempt_at INTEGER NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id)
+        );
+        ",
+    )?;
+
+    // Columns added before migrations existed, missing on the oldest databases
+    add_column_if_missing(conn, "api_keys", "expires_at", "INTEGER")?;
+    // NULL for keys created before salting; those are verified as plain SHA-256
+    add_column_if_missing(conn, "api_keys", "key_salt", "TEXT")
+}
+
+/// Base32 TOTP secret; when set, activation also requires a TOTP code
+fn add_totp_secret(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE users ADD COLUMN totp_secret TEXT;")
+}
+
+/// Activation codes stop working at `expires_at`. Codes issued before this
+/// get the default lifetime of 24 hours, so long-forgotten ones expire.
+fn add_code_expiry(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        ALTER TABLE activation_codes ADD COLUMN expires_at INTEGER;
+        UPDATE activation_codes SET expires_at = created_at + 86400;
+        ",
+    )
+}
+
+/// Soft delete: rows stay for tickets and the audit trail, but the user and
+/// their keys are treated as nonexistent
+fn add_user_deleted_at(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE users ADD COLUMN deleted_at INTEGER;")
+}
+
+/// Append-only trail of key, code and user lifecycle events; `subject_id` is
+/// the affected user
+fn add_audit_log(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE audit_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            event_type TEXT NOT NULL,
+            subject_id INTEGER NOT NULL,
+            detail TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        );
+        CREATE INDEX idx_audit_log_created_at ON audit_log(created_at);
+        ",
+    )
+}
+
+/// Picks the activation email template; existing users get German, which was
+/// the only language before
+fn add_user_language(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE users ADD COLUMN language TEXT NOT NULL DEFAULT 'de';")
+}
+
+/// How `key_hash` was computed, see `crypto::HashVersion`. Keys with a salt
+/// were hashed with HMAC, the rest with plain SHA-256
+fn add_key_hash_version(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        ALTER TABLE api_keys ADD COLUMN hash_version INTEGER NOT NULL DEFAULT 1;
+        UPDATE api_keys SET hash_version = 2 WHERE key_salt IS NOT NULL;
+        ",
+    )
+}
+
+/// Comma-separated scopes a key is limited to; existing keys keep full access
+fn add_key_scopes(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE api_keys ADD COLUMN scopes TEXT NOT NULL DEFAULT '*';")
+}
+
+/// When a key last authenticated, to find dormant keys. NULL for never used
+fn add_key_last_used(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE api_keys ADD COLUMN last_used_at INTEGER;")
+}
+
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<()> {
+    let exists = conn
+        .prepare(&format!(
+            "SELECT 1 FROM pragma_table_info('{}') WHERE name = ?",
+            table
+        ))?
+        .exists([column])?;
+    if !exists {
+        conn.execute_batch(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {};",
+            table, column, definition
+        ))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        conn.prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap()
+    }
+
+    fn user_version(conn: &Connection) -> i64 {
+        conn.pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn empty_database_reaches_latest_schema() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        migrate(&mut conn).unwrap();
+
+        assert_eq!(user_version(&conn), MIGRATIONS.len() as i64);
+        let expected = [
+            ("users", "totp_secret"),
+            ("users", "deleted_at"),
+            ("users", "language"),
+            ("api_keys", "expires_at"),
+            ("api_keys", "key_salt"),
+            ("api_keys", "hash_version"),
+            ("api_keys", "scopes"),
+            ("api_keys", "last_used_at"),
+            ("activation_codes", "expires_at"),
+            ("audit_log", "event_type"),
+            ("pending_emails", "recipient"),
+            ("failed_emails", "attempts"),
+        ];
+        for (table, column) in expected {
+            assert!(
+                columns(&conn, table).iter().any(|c| c == column),
+                "{}.{} missing",
+                table,
+                column
+            );
+        }
+
+        // Running again is a no-op
+        migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len() as i64);
+    }
+
+    #[test]
+    fn newer_schema_is_rejected() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() as i64 + 1)
+            .unwrap();
+
+        let error = migrate(&mut conn).unwrap_err();
+        assert!(error.to_string().contains("newer than this build supports"));
+    }
+}
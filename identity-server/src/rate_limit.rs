@@ -0,0 +1,255 @@
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use axum::{
+    body::{Body, to_bytes},
+    extract::{ConnectInfo, Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use governor::clock::{Clock, DefaultClock};
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Largest body `limit_by_key` buffers to find the API key; the requests it
+/// guards are tiny JSON objects
+const MAX_KEYED_BODY_BYTES: usize = 16 * 1024;
+
+/// Per-IP token bucket limiter. Unlike `tower_governor` we own the limiter, so
+/// its state can be inspected, reset and reconfigured while the server is running.
+pub struct IpRateLimiter {
+    current: ArcSwap<Limiter>,
+}
+
+struct Limiter {
+    replenish_secs: u64,
+    burst_size: u32,
+    limiter: DefaultKeyedRateLimiter<IpAddr>,
+}
+
+#[derive(Serialize)]
+pub struct RateLimitStatus {
+    pub replenish_secs: u64,
+    pub burst_size: u32,
+    /// Number of client IPs currently holding limiter state
+    pub tracked_ips: usize,
+}
+
+/// Optional overrides read from `--rate-limit-config`, e.g.
+///
+/// ```toml
+/// replenish_secs = 2
+/// burst = 10
+/// ```
+#[derive(Deserialize)]
+pub struct RateLimitConfig {
+    pub replenish_secs: Option<u64>,
+    pub burst: Option<u32>,
+}
+
+impl RateLimitConfig {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content =
+            std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        toml::from_str(&content).map_err(|e| format!("Invalid {}: {}", path, e))
+    }
+}
+
+impl IpRateLimiter {
+    /// Allows `burst_size` requests at once, then one more every `replenish_secs`.
+    pub fn new(replenish_secs: u64, burst_size: u32) -> Result<Self, String> {
+        Ok(Self {
+            current: ArcSwap::from_pointee(Limiter::new(replenish_secs, burst_size)?),
+        })
+    }
+
+    /// Returns how long the client has to wait if it is over its quota.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.current
+            .load()
+            .limiter
+            .check_key(&ip)
+            .map_err(|not_until| not_until.wait_time_from(DefaultClock::default().now()))
+    }
+
+    /// Forgets all per-IP state, giving every client a full burst again.
+    pub fn reset(&self) {
+        let current = self.current.load();
+        self.current.store(Arc::new(
+            Limiter::new(current.replenish_secs, current.burst_size)
+                .expect("current parameters are valid"),
+        ));
+    }
+
+    /// Swaps in new parameters; per-IP state is kept if they are unchanged.
+    pub fn reconfigure(&self, replenish_secs: u64, burst_size: u32) -> Result<(), String> {
+        let current = self.current.load();
+        if current.replenish_secs == replenish_secs && current.burst_size == burst_size {
+            return Ok(());
+        }
+        self.current
+            .store(Arc::new(Limiter::new(replenish_secs, burst_size)?));
+        Ok(())
+    }
+
+    pub fn status(&self) -> RateLimitStatus {
+        let current = self.current.load();
+        current.limiter.retain_recent();
+        RateLimitStatus {
+            replenish_secs: current.replenish_secs,
+            burst_size: current.burst_size,
+            tracked_ips: current.limiter.len(),
+        }
+    }
+}
+
+impl Limiter {
+    fn new(replenish_secs: u64, burst_size: u32) -> Result<Self, String> {
+        if replenish_secs == 0 || burst_size == 0 {
+            return Err("Rate limit period and burst size must be positive".into());
+        }
+        let quota = Quota::with_period(Duration::from_secs(replenish_secs))
+            .expect("period is non-zero")
+            .allow_burst(NonZeroU32::new(burst_size).expect("burst is non-zero"));
+        Ok(Self {
+            replenish_secs,
+            burst_size,
+            limiter: RateLimiter::keyed(quota),
+        })
+    }
+}
+
+/// Per-API-key limiter, so a single leaked key can't hammer `/validate` from
+/// many addresses. Keys are tracked by their SHA-256 digest, never in plain text.
+pub struct KeyRateLimiter {
+    limiter: DefaultKeyedRateLimiter<[u8; 32]>,
+}
+
+/// The part of a JSON body `limit_by_key` looks at
+#[derive(Deserialize)]
+struct KeyedBody {
+    api_key: String,
+}
+
+impl KeyRateLimiter {
+    /// Allows `per_minute` attempts per key, refilled evenly over the minute.
+    pub fn new(per_minute: u32) -> Result<Self, String> {
+        let per_minute =
+            NonZeroU32::new(per_minute).ok_or("Per-key rate limit must be positive")?;
+        Ok(Self {
+            limiter: RateLimiter::keyed(Quota::per_minute(per_minute)),
+        })
+    }
+
+    /// Returns how long the key has to wait if it is over its quota.
+    pub fn check(&self, api_key: &str) -> Result<(), Duration> {
+        let digest: [u8; 32] = Sha256::digest(api_key.as_bytes()).into();
+        self.limiter
+            .check_key(&digest)
+            .map_err(|not_until| not_until.wait_time_from(DefaultClock::default().now()))
+    }
+
+    /// Drops state for keys whose bucket has refilled completely.
+    pub fn prune(&self) {
+        self.limiter.retain_recent();
+    }
+}
+
+fn too_many_requests(wait: Duration) -> Response {
+    let wait_secs = wait.as_secs().max(1);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, wait_secs.to_string())],
+        format!("Too many requests, retry in {}s", wait_secs),
+    )
+        .into_response()
+}
+
+/// Middleware rejecting requests over the per-IP quota with `429`.
+pub async fn limit_by_ip(
+    State(limiter): State<Arc<IpRateLimiter>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    match limiter.check(addr.ip()) {
+        Ok(()) => next.run(req).await,
+        Err(wait) => too_many_requests(wait),
+    }
+}
+
+/// Middleware rejecting requests over the quota for the `api_key` in their
+/// JSON body with `429`. Bodies without one are passed on for the handler to
+/// reject.
+pub async fn limit_by_key(
+    State(limiter): State<Arc<KeyRateLimiter>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = req.into_parts();
+    let Ok(bytes) = to_bytes(body, MAX_KEYED_BODY_BYTES).await else {
+        return (StatusCode::PAYLOAD_TOO_LARGE, "Request body too large").into_response();
+    };
+
+    if let Ok(keyed) = serde_json::from_slice::<KeyedBody>(&bytes)
+        && let Err(wait) = limiter.check(&keyed.api_key)
+    {
+        return too_many_requests(wait);
+    }
+    next.run(Request::from_parts(parts, Body::from(bytes)))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+    #[test]
+    fn status_reports_the_configuration() {
+        let limiter = IpRateLimiter::new(30, 3).unwrap();
+        limiter.check(CLIENT).unwrap();
+
+        let status = limiter.status();
+        assert_eq!(status.replenish_secs, 30);
+        assert_eq!(status.burst_size, 3);
+        assert_eq!(status.tracked_ips, 1);
+    }
+
+    #[test]
+    fn reset_gives_a_full_burst_again() {
+        let limiter = IpRateLimiter::new(60, 2).unwrap();
+        limiter.check(CLIENT).unwrap();
+        limiter.check(CLIENT).unwrap();
+        assert!(limiter.check(CLIENT).is_err());
+
+        limiter.reset();
+
+        assert!(limiter.check(CLIENT).is_ok());
+        assert_eq!(limiter.status().burst_size, 2);
+    }
+
+    #[test]
+    fn reconfigure_swaps_parameters_and_rejects_invalid_ones() {
+        let limiter = IpRateLimiter::new(60, 1).unwrap();
+        limiter.check(CLIENT).unwrap();
+
+        // Unchanged parameters keep the client's state
+        limiter.reconfigure(60, 1).unwrap();
+        assert!(limiter.check(CLIENT).is_err());
+
+        limiter.reconfigure(10, 4).unwrap();
+        let status = limiter.status();
+        assert_eq!((status.replenish_secs, status.burst_size), (10, 4));
+        assert!(limiter.check(CLIENT).is_ok());
+
+        assert!(limiter.reconfigure(0, 4).is_err());
+        assert_eq!(limiter.status().replenish_secs, 10);
+    }
+}
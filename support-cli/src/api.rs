@@ -1,13 +1,36 @@
 use anyhow::{Context, Result};
 use reqwest::blocking::{Client, multipart};
-use support_common::{CreateCommentRequest, Ticket, TicketDetail, TicketState, UpdateStateRequest};
+use support_common::{
+    AddTagRequest, Comment, CommentPage, CreateCommentRequest, ErrorResponse, PaginatedTickets,
+    Ticket, TicketDetail, TicketState, UpdateCommentRequest, UpdateStateRequest,
+};
 
+/// Server-side filters for the admin ticket list; `None` means no filter.
+#[derive(Debug, Clone, Default)]
+pub struct TicketFilter {
+    pub state: Option<TicketState>,
+    pub user_id: Option<i64>,
+    /// Most recently active tickets first instead of the newest
+    pub by_activity: bool,
+}
+
+#[derive(Clone)]
 pub struct ApiClient {
     client: Client,
     base_url: String,
+    /// Only ever sent as a header; never rendered or logged
     api_key: String,
 }
 
+impl std::fmt::Debug for ApiClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ApiClient")
+            .field("base_url", &self.base_url)
+            .field("api_key", &"***")
+            .finish()
+    }
+}
+
 impl ApiClient {
     pub fn new(base_url: String, api_key: String) -> Self {
         Self {
@@ -17,10 +40,27 @@ impl ApiClient {
         }
     }
 
-    pub fn list_tickets(&self) -> Result<Vec<Ticket>> {
+    pub fn list_tickets(
+        &self,
+        limit: i64,
+        offset: i64,
+        filter: &TicketFilter,
+    ) -> Result<PaginatedTickets> {
+        let mut query = vec![("limit", limit.to_string()), ("offset", offset.to_string())];
+        if let Some(state) = filter.state {
+            query.push(("state", state.as_str().to_string()));
+        }
+        if let Some(user_id) = filter.user_id {
+            query.push(("user_id", user_id.to_string()));
+        }
+        if filter.by_activity {
+            query.push(("sort", "updated".to_string()));
+        }
+
         let resp = self
             .client
             .get(format!("{}/admin/tickets", self.base_url))
+            .query(&query)
             .header("x-api-key", &self.api_key)
             .send()
             .context("Konnte Server nicht erreichen")?;
@@ -32,10 +72,32 @@ impl ApiClient {
         resp.json().context("Ungültige Antwort vom Server")
     }
 
-    pub fn get_ticket(&self, id: i64) -> Result<TicketDetail> {
+    /// Fetches a ticket with only its newest `comments_limit` comments.
+    pub fn get_ticket(&self, id: i64, comments_limit: i64) -> Result<TicketDetail> {
         let resp = self
             .client
             .get(format!("{}/admin/tickets/{}", self.base_url, id))
+            .query(&[("comments_limit", comments_limit)])
+            .header("x-api-key", &self.api_key)
+            .send()
+            .context("Konnte Server nicht erreichen")?;
+
+        if !resp.status().is_success() {
+            anyhow::bail!("Server Fehler: {}", resp.status());
+        }
+
+        resp.json().context("Ungültige Antwort vom Server")
+    }
+
+    /// Fetches up to `limit` comments older than the comment `before`.
+    pub fn list_comments(&self, ticket_id: i64, before: i64, limit: i64) -> Result<CommentPage> {
+        let resp = self
+            .client
+            .get(format!(
+                "{}/admin/tickets/{}/comments",
+                self.base_url, ticket_id
+            ))
+            .query(&[("before", before), ("limit", limit)])
             .header("x-api-key", &self.api_key)
             .send()
             .context("Konnte Server nicht erreichen")?;
@@ -56,6 +118,9 @@ impl ApiClient {
             .send()
             .context("Konnte Server nicht erreichen")?;
 
+        if resp.status() == reqwest::StatusCode::CONFLICT {
+            anyhow::bail!("{}", error_message(resp));
+        }
         if !resp.status().is_success() {
             anyhow::bail!("Server Fehler: {}", resp.status());
         }
@@ -63,7 +128,30 @@ impl ApiClient {
         Ok(())
     }
 
-    pub fn add_comment(&self, ticket_id: i64, text: String) -> Result<()> {
+    pub fn add_tag(&self, ticket_id: i64, tag: String) -> Result<()> {
+        let resp = self
+            .client
+            .post(format!(
+                "{}/admin/tickets/{}/tags",
+                self.base_url, ticket_id
+            ))
+            .header("x-api-key", &self.api_key)
+            .json(&AddTagRequest { tag })
+            .send()
+            .context("Konnte Server nicht erreichen")?;
+
+        // The server explains which tags it accepts
+        if resp.status() == reqwest::StatusCode::BAD_REQUEST {
+            anyhow::bail!("{}", error_message(resp));
+        }
+        if !resp.status().is_success() {
+            anyhow::bail!("Server Fehler: {}", resp.status());
+        }
+
+        Ok(())
+    }
+
+    pub fn add_comment(&self, ticket_id: i64, text: String, internal: bool) -> Result<()> {
         let resp = self
             .client
             .post(format!(
@@ -71,7 +159,64 @@ impl ApiClient {
                 self.base_url, ticket_id
             ))
             .header("x-api-key", &self.api_key)
-            .json(&CreateCommentRequest { text })
+            .json(&CreateCommentRequest { text, internal })
+            .send()
+            .context("Konnte Server nicht erreichen")?;
+
+        if !resp.status().is_success() {
+            anyhow::bail!("Server Fehler: {}", resp.status());
+        }
+
+        Ok(())
+    }
+
+    /// The user id owning the API key, from the identity server's `/whoami`.
+    pub fn whoami(&self, identity_url: &str) -> Result<i64> {
+        #[derive(serde::Deserialize)]
+        struct WhoAmI {
+            id: i64,
+        }
+
+        let resp = self
+            .client
+            .get(format!("{}/whoami", identity_url))
+            .header("x-api-key", &self.api_key)
+            .send()
+            .context("Konnte Identity-Server nicht erreichen")?;
+
+        if !resp.status().is_success() {
+            anyhow::bail!("Identity-Server Fehler: {}", resp.status());
+        }
+
+        Ok(resp
+            .json::<WhoAmI>()
+            .context("Ungültige Antwort vom Identity-Server")?
+            .id)
+    }
+
+    pub fn delete_ticket(&self, id: i64) -> Result<()> {
+        let resp = self
+            .client
+            .delete(format!("{}/admin/tickets/{}", self.base_url, id))
+            .header("x-api-key", &self.api_key)
+            .send()
+            .context("Konnte Server nicht erreichen")?;
+
+        if !resp.status().is_success() {
+            anyhow::bail!("Server Fehler: {}", resp.status());
+        }
+
+        Ok(())
+    }
+
+    pub fn get_comment(&self, ticket_id: i64, comment_id: i64) -> Result<Comment> {
+        let resp = self
+            .client
+            .get(format!(
+                "{}/admin/tickets/{}/comments/{}",
+                self.base_url, ticket_id, comment_id
+            ))
+            .header("x-api-key", &self.api_key)
             .send()
             .context("Konnte Server nicht erreichen")?;
 
@@ -79,6 +224,49 @@ impl ApiClient {
             anyhow::bail!("Server Fehler: {}", resp.status());
         }
 
+        resp.json().context("Ungültige Antwort vom Server")
+    }
+
+    pub fn update_comment(&self, ticket_id: i64, comment_id: i64, text: String) -> Result<()> {
+        let resp = self
+            .client
+            .put(format!(
+                "{}/admin/tickets/{}/comments/{}",
+                self.base_url, ticket_id, comment_id
+            ))
+            .header("x-api-key", &self.api_key)
+            .json(&UpdateCommentRequest { text })
+            .send()
+            .context("Konnte Server nicht erreichen")?;
+
+        if resp.status() == reqwest::StatusCode::FORBIDDEN {
+            anyhow::bail!("{}", error_message(resp));
+        }
+        if !resp.status().is_success() {
+            anyhow::bail!("Server Fehler: {}", resp.status());
+        }
+
+        Ok(())
+    }
+
+    pub fn delete_comment(&self, ticket_id: i64, comment_id: i64) -> Result<()> {
+        let resp = self
+            .client
+            .delete(format!(
+                "{}/admin/tickets/{}/comments/{}",
+                self.base_url, ticket_id, comment_id
+            ))
+            .header("x-api-key", &self.api_key)
+            .send()
+            .context("Konnte Server nicht erreichen")?;
+
+        if resp.status() == reqwest::StatusCode::FORBIDDEN {
+            anyhow::bail!("{}", error_message(resp));
+        }
+        if !resp.status().is_success() {
+            anyhow::bail!("Server Fehler: {}", resp.status());
+        }
+
         Ok(())
     }
 
@@ -99,13 +287,18 @@ impl ApiClient {
             .context("Fehler beim Herunterladen")
     }
 
-    pub fn create_ticket(&self, description: String, zip_data: Vec<u8>) -> Result<Ticket> {
+    pub fn create_ticket(
+        &self,
+        description: String,
+        file_name: String,
+        zip_data: Vec<u8>,
+    ) -> Result<Ticket> {
         let form = multipart::Form::new()
             .text("description", description)
             .part(
                 "zip",
                 multipart::Part::bytes(zip_data)
-                    .file_name("report.zip")
+                    .file_name(file_name)
                     .mime_str("application/zip")?,
             );
 
@@ -124,3 +317,15 @@ impl ApiClient {
         resp.json().context("Ungültige Antwort vom Server")
     }
 }
+
+/// The server's `{"error": ...}` message with its request id, or the raw body
+/// if it sent something else.
+fn error_message(resp: reqwest::blocking::Response) -> String {
+    let body = resp.text().unwrap_or_default();
+    serde_json::from_str::<ErrorResponse>(&body)
+        .map(|e| match e.request_id {
+            Some(id) => format!("{} (Request-ID: {})", e.error, id),
+            None => e.error,
+        })
+        .unwrap_or(body)
+}
@@ -1,10 +1,128 @@
-use std::io::{Cursor, Write};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::OpenOptions;
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
-use support_common::{Ticket, TicketDetail, TicketState};
+use anyhow::{Context, Result};
+use directories::BaseDirs;
+use ratatui::text::Line;
+use support_common::{Comment, CommentPage, PaginatedTickets, Ticket, TicketDetail, TicketState};
+use unicode_segmentation::UnicodeSegmentation;
 use zip::{ZipArchive, ZipWriter, write::SimpleFileOptions};
 
-use crate::api::ApiClient;
+use crate::api::{ApiClient, TicketFilter};
+use crate::editor::TextEditor;
+use crate::ui::DEFAULT_DATE_FORMAT;
+
+/// URL scheme used to share tickets, e.g. `curadesk://ticket/42`
+pub const TICKET_LINK_PREFIX: &str = "curadesk://ticket/";
+
+/// Tickets fetched per page in the ticket list
+const TICKET_PAGE_SIZE: i64 = 50;
+
+/// Comments fetched per request in the detail view
+const COMMENT_PAGE_SIZE: i64 = 50;
+
+/// Applies the result of a finished API request to the app on the UI thread
+type Completion = Box<dyn FnOnce(&mut App) + Send>;
+
+pub fn ticket_link(id: i64) -> String {
+    format!("{}{}", TICKET_LINK_PREFIX, id)
+}
+
+/// Plain-text summary of a ticket for pasting into chat.
+fn ticket_summary(detail: &TicketDetail, date_format: &str) -> String {
+    let ticket = &detail.ticket;
+    let title = match &ticket.reference {
+        Some(reference) => format!("{} (#{})", reference, ticket.id),
+        None => format!("#{}", ticket.id),
+    };
+    // Only the latest page of comments is loaded; more may exist
+    let more = if detail.has_more_comments { "+" } else { "" };
+    format!(
+        "Ticket {}\nState: {}\nCreated: {}\nLast activity: {}\nComments: {}{}\nLink: {}\n\n{}",
+        title,
+        ticket.state,
+        crate::ui::format_timestamp(ticket.created_at, date_format),
+        crate::ui::format_timestamp(ticket.updated_at, date_format),
+        detail.comments.len(),
+        more,
+        ticket_link(ticket.id),
+        ticket.description
+    )
+}
+
+/// Parses a ticket reference as pasted by a user: `curadesk://ticket/42`, `#42` or `42`.
+pub fn parse_ticket_link(input: &str) -> Option<i64> {
+    let input = input.trim();
+    let id = match input.strip_prefix(TICKET_LINK_PREFIX) {
+        Some(rest) => rest.trim_end_matches('/'),
+        None => input.strip_prefix('#').unwrap_or(input),
+    };
+    id.parse().ok().filter(|id| *id > 0)
+}
+
+/// Builds an in-memory ZIP archive containing a single file.
+pub fn zip_single_file(name: &str, content: &[u8]) -> Result<Vec<u8>> {
+    let mut buffer = Cursor::new(Vec::new());
+    {
+        let mut zip = ZipWriter::new(&mut buffer);
+        let options = SimpleFileOptions::default();
+        zip.start_file(name, options)?;
+        zip.write_all(content)?;
+        zip.finish()?;
+    }
+    Ok(buffer.into_inner())
+}
+
+/// A ZIP file from disk, attached to a new ticket
+#[derive(Debug, Clone)]
+pub struct AttachedZip {
+    pub file_name: String,
+    pub data: Vec<u8>,
+}
+
+/// Reads `path` and checks that it is a ZIP archive. A leading `~/` stands
+/// for the home directory.
+fn read_zip_file(path: &str) -> Result<AttachedZip> {
+    let path = match (path.strip_prefix("~/"), BaseDirs::new()) {
+        (Some(rest), Some(dirs)) => dirs.home_dir().join(rest),
+        _ => PathBuf::from(path),
+    };
+    let data =
+        std::fs::read(&path).with_context(|| format!("Kann {} nicht lesen", path.display()))?;
+    ZipArchive::new(Cursor::new(&data))
+        .with_context(|| format!("{} ist keine ZIP-Datei", path.display()))?;
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "report.zip".to_string());
+    Ok(AttachedZip { file_name, data })
+}
+
+/// Encodes raw RGBA pixels (as delivered by the clipboard) as PNG.
+fn encode_png(width: u32, height: u32, rgba: &[u8]) -> Result<Vec<u8>> {
+    let mut png = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(rgba)?;
+    }
+    Ok(png)
+}
+
+/// Removes the last user-perceived character, so e.g. a flag emoji or an accented
+/// letter made of several code points disappears with one backspace.
+pub fn pop_grapheme(input: &mut String) {
+    if let Some((index, _)) = input.grapheme_indices(true).next_back() {
+        input.truncate(index);
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum View {
@@ -12,15 +130,238 @@ pub enum View {
     TicketDetail,
     ZipViewer,
     FileContent,
+    HexDump,
     AddComment,
     CreateTicket,
+    /// Path prompt on top of `CreateTicket` for attaching a ZIP file
+    AttachFile,
+    /// Tag prompt on top of `TicketDetail`
+    AddTag,
+    Confirm,
+    /// Key bindings of `help_return_view`, drawn on top of it
+    Help,
+}
+
+/// An action that only runs after the user confirmed it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PendingAction {
+    UpdateState(TicketState),
+    DeleteComment(i64),
+    DeleteTicket(i64),
+}
+
+impl PendingAction {
+    /// Deletes are permanent and need a typed confirmation word; everything
+    /// else is answered with a single `y`/`n`.
+    pub fn needs_typed_confirmation(&self) -> bool {
+        matches!(self, Self::DeleteComment(_) | Self::DeleteTicket(_))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Confirmation {
+    pub message: String,
+    pub action: PendingAction,
+    pub input: String,
+    /// View to return to when the prompt is answered or cancelled
+    pub return_view: View,
+}
+
+/// Words accepted to confirm a destructive action
+const CONFIRM_WORDS: [&str; 2] = ["ja", "yes"];
+
+pub fn is_confirmed(input: &str) -> bool {
+    let input = input.trim();
+    CONFIRM_WORDS.iter().any(|w| input.eq_ignore_ascii_case(w))
+}
+
+/// Keeps the tickets assigned to `me`. If none of them carries an assignee
+/// (the server may not support assignment) or `me` is unknown, every ticket is
+/// kept and the returned notice says why.
+pub fn filter_assigned(
+    tickets: Vec<Ticket>,
+    me: Option<i64>,
+) -> (Vec<Ticket>, Option<&'static str>) {
+    let Some(me) = me else {
+        return (
+            tickets,
+            Some(
+                "Assigned to me: user unknown (identity /whoami unreachable), showing all tickets",
+            ),
+        );
+    };
+    if tickets.iter().all(|t| t.assignee_id.is_none()) {
+        return (
+            tickets,
+            Some("Assigned to me: tickets carry no assignee, showing all tickets"),
+        );
+    }
+    let mine = tickets
+        .into_iter()
+        .filter(|t| t.assignee_id == Some(me))
+        .collect();
+    (mine, None)
+}
+
+/// Replaces the ticket list with a freshly fetched one. Returns the index that
+/// keeps the previously selected ticket selected (by id) and the ids that were
+/// not in the old list.
+pub fn merge_ticket_list(old: &[Ticket], selected: usize, new: &[Ticket]) -> (usize, HashSet<i64>) {
+    let selected_id = old.get(selected).map(|t| t.id);
+    let index = selected_id
+        .and_then(|id| new.iter().position(|t| t.id == id))
+        .unwrap_or_else(|| selected.min(new.len().saturating_sub(1)));
+
+    let known: HashSet<i64> = old.iter().map(|t| t.id).collect();
+    let arrived = new
+        .iter()
+        .map(|t| t.id)
+        .filter(|id| !known.contains(id))
+        .collect();
+
+    (index, arrived)
+}
+
+/// Raw bytes of one archive entry, without any text decoding
+fn read_zip_entry(data: &[u8], name: &str) -> Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+    let mut file = archive.by_name(name)?;
+    let mut content = Vec::new();
+    file.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+/// Creates `path`, or `name-1.ext`, `name-2.ext`, ... if it already exists
+fn write_new_file(path: &Path, data: &[u8]) -> io::Result<PathBuf> {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut candidate = path.to_path_buf();
+    let mut suffix = 0;
+    loop {
+        // create_new so an existing file is never overwritten, even in a race
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(mut file) => {
+                file.write_all(data)?;
+                return Ok(candidate);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                suffix += 1;
+                candidate = path.with_file_name(format!("{}-{}{}", stem, suffix, ext));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Bytes read from the start of each ZIP entry to guess its kind
+const SNIFF_BYTES: u64 = 512;
+
+/// What a ZIP entry looks like, guessed from its first bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    Text,
+    Image,
+    Binary,
+}
+
+/// Image magic numbers first, then text if the bytes are UTF-8 without NULs.
+/// The sample may end in the middle of a character, which still counts as text.
+fn sniff_entry_kind(head: &[u8]) -> EntryKind {
+    const IMAGE_MAGIC: &[&[u8]] = &[
+        b"\x89PNG\r\n\x1a\n",
+        b"\xff\xd8\xff",
+        b"GIF87a",
+        b"GIF89a",
+        b"BM",
+        b"\x00\x00\x01\x00",
+    ];
+    let is_webp = head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP";
+    if is_webp || IMAGE_MAGIC.iter().any(|magic| head.starts_with(magic)) {
+        return EntryKind::Image;
+    }
+
+    let utf8 = match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    if utf8 && !head.contains(&0) {
+        EntryKind::Text
+    } else {
+        EntryKind::Binary
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ZipEntry {
+    /// Name within the current directory, `..` for the parent
     pub name: String,
+    /// Full path inside the archive, directories end with `/`
+    pub path: String,
     pub size: u64,
     pub is_dir: bool,
+    /// `None` for directories
+    pub kind: Option<EntryKind>,
+}
+
+/// Parent of an archive directory path, `a/b/` -> `a/`, `a/` -> ``
+fn zip_parent_dir(dir: &str) -> String {
+    let trimmed = dir.trim_end_matches('/');
+    match trimmed.rfind('/') {
+        Some(pos) => trimmed[..=pos].to_string(),
+        None => String::new(),
+    }
+}
+
+/// Direct children of `dir`: `..` first (below the root), then directories, then files.
+/// Directories only implied by file paths are listed as well.
+fn list_zip_dir(all: &[ZipEntry], dir: &str) -> Vec<ZipEntry> {
+    let mut dirs = BTreeMap::new();
+    let mut files = Vec::new();
+
+    for entry in all {
+        let Some(rest) = entry.path.strip_prefix(dir) else {
+            continue;
+        };
+        match rest.split_once('/') {
+            Some((child, _)) if !child.is_empty() => {
+                dirs.entry(child.to_string()).or_insert_with(|| ZipEntry {
+                    name: format!("{}/", child),
+                    path: format!("{}{}/", dir, child),
+                    size: 0,
+                    is_dir: true,
+                    kind: None,
+                });
+            }
+            Some(_) => {}
+            None if !rest.is_empty() => files.push(ZipEntry {
+                name: rest.to_string(),
+                ..entry.clone()
+            }),
+            None => {}
+        }
+    }
+
+    let mut listing = Vec::new();
+    if !dir.is_empty() {
+        listing.push(ZipEntry {
+            name: "..".to_string(),
+            path: zip_parent_dir(dir),
+            size: 0,
+            is_dir: true,
+            kind: None,
+        });
+    }
+    listing.extend(dirs.into_values());
+    listing.extend(files);
+    listing
 }
 
 pub struct App {
@@ -31,181 +372,1130 @@ pub struct App {
     // Ticket Liste
     pub tickets: Vec<Ticket>,
     pub selected_ticket: usize,
+    /// Position of the loaded page within all (filtered) tickets
+    pub ticket_offset: i64,
+    pub ticket_total: i64,
+    pub ticket_filter: TicketFilter,
+    /// Show only tickets assigned to `current_user_id`, filtered client-side
+    pub assigned_only: bool,
+    /// The agent's user id from the identity server, once `/whoami` answered
+    pub current_user_id: Option<i64>,
+
+    // Auto-refresh of the ticket list
+    pub auto_refresh: bool,
+    pub refresh_interval: Duration,
+    last_refresh: Instant,
+    /// An auto-refresh is in flight, so no second one is started
+    refreshing: bool,
+    /// Tickets that appeared with the last auto-refresh, highlighted in the list
+    pub new_ticket_ids: HashSet<i64>,
 
     // Ticket Detail
     pub current_ticket: Option<TicketDetail>,
     pub detail_scroll: usize,
+    /// Older comments are being fetched after scrolling past the first loaded one
+    pub loading_older_comments: bool,
 
     // ZIP Viewer
     pub zip_data: Option<Vec<u8>>,
+    /// Every entry of the archive, sorted by path
+    zip_all_entries: Vec<ZipEntry>,
+    /// Directory shown in the viewer, `` for the root, else ending with `/`
+    pub zip_cwd: String,
+    /// Listing of `zip_cwd`
     pub zip_entries: Vec<ZipEntry>,
     pub selected_zip_entry: usize,
 
     // File Content Viewer
     pub file_content: Option<String>,
+    /// Raw bytes of the opened entry, shown by the hex view
+    pub file_bytes: Vec<u8>,
+    /// Syntax highlighted `file_content`, `None` to render it plain
+    pub file_lines: Option<Vec<Line<'static>>>,
     pub file_name: String,
     pub content_scroll: usize,
+    /// Query for `/` search in the file content, kept for `n`/`N`
+    pub content_search: String,
+    /// True while the search query is being typed
+    pub content_search_active: bool,
 
     // Comment Input
-    pub comment_input: String,
+    pub comment_input: TextEditor,
+    /// Post the comment as an internal note the customer doesn't see
+    pub comment_internal: bool,
+    /// Comment whose text `comment_input` replaces, instead of posting a new one
+    pub editing_comment: Option<i64>,
 
     // Create Ticket Input
     pub new_ticket_description: String,
+    /// PNG pasted from the clipboard, attached instead of the generated report
+    pub new_ticket_screenshot: Option<Vec<u8>>,
+    /// ZIP file from disk, uploaded as is under its own name
+    pub new_ticket_zip: Option<AttachedZip>,
+    pub attach_path_input: String,
+
+    // Tag Input
+    pub tag_input: String,
 
     // Status/Error Message
     pub status_message: Option<String>,
+
+    /// View the help overlay was opened from and returns to
+    pub help_return_view: Option<View>,
+
+    // API requests running on worker threads
+    /// True while any request is in flight; the status bar shows a spinner
+    pub loading: bool,
+    requests_in_flight: usize,
+    completion_tx: Sender<Completion>,
+    completion_rx: Receiver<Completion>,
+    /// A comment or new ticket is being sent; Enter is ignored until it's done
+    saving: bool,
+
+    // Confirmation prompt for destructive actions
+    pub confirmation: Option<Confirmation>,
+
+    // strftime pattern for timestamps
+    pub date_format: String,
+
+    // Lazily opened so headless sessions never touch the clipboard
+    clipboard: Option<arboard::Clipboard>,
 }
 
 impl App {
     pub fn new(api: ApiClient) -> Self {
+        let (completion_tx, completion_rx) = mpsc::channel();
         Self {
             api,
             running: true,
             view: View::TicketList,
             tickets: Vec::new(),
             selected_ticket: 0,
+            ticket_offset: 0,
+            ticket_total: 0,
+            ticket_filter: TicketFilter::default(),
+            assigned_only: false,
+            current_user_id: None,
+            auto_refresh: false,
+            refresh_interval: Duration::from_secs(30),
+            last_refresh: Instant::now(),
+            refreshing: false,
+            new_ticket_ids: HashSet::new(),
             current_ticket: None,
             detail_scroll: 0,
+            loading_older_comments: false,
             zip_data: None,
+            zip_all_entries: Vec::new(),
+            zip_cwd: String::new(),
             zip_entries: Vec::new(),
             selected_zip_entry: 0,
             file_content: None,
+            file_bytes: Vec::new(),
+            file_lines: None,
             file_name: String::new(),
             content_scroll: 0,
-            comment_input: String::new(),
+            content_search: String::new(),
+            content_search_active: false,
+            comment_input: TextEditor::default(),
+            comment_internal: false,
+            editing_comment: None,
             new_ticket_description: String::new(),
+            new_ticket_screenshot: None,
+            new_ticket_zip: None,
+            attach_path_input: String::new(),
+            tag_input: String::new(),
             status_message: None,
+            help_return_view: None,
+            loading: false,
+            requests_in_flight: 0,
+            completion_tx,
+            completion_rx,
+            saving: false,
+            confirmation: None,
+            date_format: DEFAULT_DATE_FORMAT.to_string(),
+            clipboard: None,
         }
     }
 
-    pub fn load_tickets(&mut self) -> Result<()> {
-        self.tickets = self.api.list_tickets()?;
-        self.selected_ticket = 0;
-        Ok(())
+    /// Runs `call` on a worker thread so the terminal stays responsive; `apply`
+    /// gets its result on the UI thread once `poll_requests` picks it up.
+    fn spawn_request<T, C, A>(&mut self, call: C, apply: A)
+    where
+        T: Send + 'static,
+        C: FnOnce(&ApiClient) -> Result<T> + Send + 'static,
+        A: FnOnce(&mut App, Result<T>) + Send + 'static,
+    {
+        let api = self.api.clone();
+        let tx = self.completion_tx.clone();
+        self.requests_in_flight += 1;
+        self.loading = true;
+        thread::spawn(move || {
+            let result = call(&api);
+            let _ = tx.send(Box::new(move |app: &mut App| apply(app, result)));
+        });
+    }
+
+    /// Called once per loop iteration: applies the results of finished requests.
+    pub fn poll_requests(&mut self) {
+        while let Ok(complete) = self.completion_rx.try_recv() {
+            self.requests_in_flight -= 1;
+            complete(self);
+        }
+        self.loading = self.requests_in_flight > 0;
+    }
+
+    pub fn load_tickets(&mut self) {
+        self.request_tickets(None);
+    }
+
+    /// Reloads the list and confirms it in the status bar once done.
+    pub fn refresh_tickets(&mut self) {
+        self.request_tickets(Some("Tickets refreshed"));
+    }
+
+    fn request_tickets(&mut self, done_message: Option<&'static str>) {
+        let offset = self.ticket_offset;
+        let filter = self.ticket_filter.clone();
+        self.spawn_request(
+            move |api| api.list_tickets(TICKET_PAGE_SIZE, offset, &filter),
+            move |app, page| match page {
+                Ok(page) => {
+                    app.show_tickets(page);
+                    // A plain reload keeps whatever the status bar says, e.g.
+                    // the hints shown after creating a ticket, and a notice
+                    // from the list itself wins over the confirmation
+                    if let Some(message) = done_message
+                        && app.status_message.is_none()
+                    {
+                        app.status_message = Some(message.to_string());
+                    }
+                }
+                Err(e) => app.status_message = Some(format!("Could not load tickets: {}", e)),
+            },
+        );
     }
 
-    pub fn load_ticket_detail(&mut self, id: i64) -> Result<()> {
-        self.current_ticket = Some(self.api.get_ticket(id)?);
+    fn show_tickets(&mut self, page: PaginatedTickets) {
+        self.tickets = if self.assigned_only {
+            let (tickets, notice) = filter_assigned(page.tickets, self.current_user_id);
+            if let Some(notice) = notice {
+                self.status_message = Some(notice.to_string());
+            }
+            tickets
+        } else {
+            page.tickets
+        };
+        self.ticket_total = page.total;
+        // Keep an open ticket selected, so going back lands on it
+        self.selected_ticket = self
+            .current_ticket
+            .as_ref()
+            .and_then(|detail| self.tickets.iter().position(|t| t.id == detail.ticket.id))
+            .unwrap_or(0);
+        self.new_ticket_ids.clear();
+        self.last_refresh = Instant::now();
+    }
+
+    /// Loads the next (`delta` 1) or previous (`delta` -1) page of tickets.
+    pub fn change_ticket_page(&mut self, delta: i64) {
+        let offset = self.ticket_offset + delta * TICKET_PAGE_SIZE;
+        if offset < 0 || offset >= self.ticket_total {
+            return;
+        }
+        self.ticket_offset = offset;
+        self.load_tickets();
+    }
+
+    /// Steps the state filter through all states and back to "all".
+    pub fn cycle_state_filter(&mut self) {
+        let state = match self.ticket_filter.state {
+            None => Some(TicketState::New),
+            Some(TicketState::New) => Some(TicketState::InProgress),
+            Some(TicketState::InProgress) => Some(TicketState::Done),
+            Some(TicketState::Done) => Some(TicketState::Closed),
+            Some(TicketState::Closed) => Some(TicketState::Reopened),
+            Some(TicketState::Reopened) => None,
+        };
+        self.set_state_filter(state);
+    }
+
+    /// Shows only tickets in `state`, or all of them again if that filter is
+    /// already active.
+    pub fn toggle_state_filter(&mut self, state: TicketState) {
+        let state = (self.ticket_filter.state != Some(state)).then_some(state);
+        self.set_state_filter(state);
+    }
+
+    pub fn set_state_filter(&mut self, state: Option<TicketState>) {
+        self.ticket_filter.state = state;
+        self.ticket_offset = 0;
+        self.load_tickets();
+    }
+
+    /// Shows only tickets assigned to the logged-in agent, or all of them again.
+    pub fn toggle_assigned_filter(&mut self) {
+        self.assigned_only = !self.assigned_only;
+        self.ticket_offset = 0;
+        self.load_tickets();
+    }
+
+    /// Asks the identity server who owns the API key, for "assigned to me".
+    /// A failure only matters once that filter is used, which then says so.
+    pub fn resolve_current_user(&mut self, identity_url: String) {
+        self.spawn_request(
+            move |api| api.whoami(&identity_url),
+            |app, id| {
+                if let Ok(id) = id {
+                    app.current_user_id = Some(id);
+                    if app.assigned_only {
+                        app.load_tickets();
+                    }
+                }
+            },
+        );
+    }
+
+    /// Restricts the list to the selected ticket's user, or lifts that filter.
+    pub fn toggle_user_filter(&mut self) {
+        self.ticket_filter.user_id = match self.ticket_filter.user_id {
+            Some(_) => None,
+            None => match self.tickets.get(self.selected_ticket) {
+                Some(ticket) => Some(ticket.user_id),
+                None => return,
+            },
+        };
+        self.ticket_offset = 0;
+        self.load_tickets();
+    }
+
+    /// Switches the list between newest tickets and latest activity first.
+    pub fn toggle_activity_sort(&mut self) {
+        self.ticket_filter.by_activity = !self.ticket_filter.by_activity;
+        self.ticket_offset = 0;
+        self.load_tickets();
+    }
+
+    pub fn toggle_auto_refresh(&mut self) {
+        self.auto_refresh = !self.auto_refresh;
+        self.last_refresh = Instant::now();
+        self.status_message = Some(if self.auto_refresh {
+            format!(
+                "Auto-refresh on (every {}s)",
+                self.refresh_interval.as_secs()
+            )
+        } else {
+            "Auto-refresh off".to_string()
+        });
+    }
+
+    /// Called once per loop iteration: starts a background fetch when the
+    /// interval has elapsed; the result is merged into the list. Paused while
+    /// the agent is typing so the screen does not change under them.
+    pub fn tick_auto_refresh(&mut self) {
+        let typing = matches!(
+            self.view,
+            View::AddComment | View::CreateTicket | View::AttachFile | View::AddTag | View::Confirm
+        );
+        if self.refreshing
+            || !self.auto_refresh
+            || typing
+            || self.last_refresh.elapsed() < self.refresh_interval
+        {
+            return;
+        }
+
+        let offset = self.ticket_offset;
+        let filter = self.ticket_filter.clone();
+        self.refreshing = true;
+        self.last_refresh = Instant::now();
+        self.spawn_request(
+            move |api| api.list_tickets(TICKET_PAGE_SIZE, offset, &filter),
+            |app, page| {
+                app.refreshing = false;
+                match page {
+                    Ok(page) => {
+                        app.ticket_total = page.total;
+                        app.apply_refreshed_tickets(page.tickets);
+                    }
+                    Err(e) => app.status_message = Some(format!("Auto-refresh failed: {}", e)),
+                }
+            },
+        );
+    }
+
+    fn apply_refreshed_tickets(&mut self, tickets: Vec<Ticket>) {
+        let tickets = if self.assigned_only {
+            filter_assigned(tickets, self.current_user_id).0
+        } else {
+            tickets
+        };
+        let (index, arrived) = merge_ticket_list(&self.tickets, self.selected_ticket, &tickets);
+        self.tickets = tickets;
+        self.selected_ticket = index;
+        if !arrived.is_empty() {
+            self.status_message = Some(format!("{} new ticket(s)", arrived.len()));
+            self.new_ticket_ids = arrived;
+        }
+    }
+
+    pub fn load_ticket_detail(&mut self, id: i64) {
+        self.spawn_request(
+            move |api| api.get_ticket(id, COMMENT_PAGE_SIZE),
+            |app, detail| match detail {
+                Ok(detail) => app.show_ticket_detail(detail),
+                Err(e) => app.status_message = Some(format!("Could not load ticket: {}", e)),
+            },
+        );
+    }
+
+    fn show_ticket_detail(&mut self, detail: TicketDetail) {
+        self.new_ticket_ids.remove(&detail.ticket.id);
+        self.current_ticket = Some(detail);
         self.detail_scroll = 0;
+        self.loading_older_comments = false;
         self.view = View::TicketDetail;
-        Ok(())
     }
 
-    pub fn load_zip(&mut self) -> Result<()> {
-        if let Some(detail) = &self.current_ticket {
-            let data = self.api.download_zip(detail.ticket.id)?;
-            self.zip_entries = Self::parse_zip_entries(&data)?;
-            self.zip_data = Some(data);
-            self.selected_zip_entry = 0;
-            self.view = View::ZipViewer;
+    /// Shows the ticket reloaded after a change was saved, with `message` as
+    /// the status.
+    fn show_reloaded_ticket(&mut self, detail: Result<TicketDetail>, message: &str) {
+        match detail {
+            Ok(detail) => {
+                self.show_ticket_detail(detail);
+                self.status_message = Some(message.to_string());
+            }
+            Err(e) => {
+                self.status_message = Some(format!("{} - reload failed: {}", message, e));
+            }
         }
+    }
+
+    /// Prepends the previous page of comments, keeping the selection just
+    /// above the comment that was at the top before.
+    fn load_older_comments(&mut self) {
+        let Some(detail) = &self.current_ticket else {
+            return;
+        };
+        let Some(oldest) = detail.comments.first() else {
+            return;
+        };
+        let (ticket_id, before) = (detail.ticket.id, oldest.id);
+
+        self.loading_older_comments = true;
+        self.spawn_request(
+            move |api| api.list_comments(ticket_id, before, COMMENT_PAGE_SIZE),
+            move |app, page| app.prepend_older_comments(ticket_id, before, page),
+        );
+    }
+
+    /// Puts a page of older comments in front of the ones shown, `before`
+    /// being the id of the oldest comment when the page was requested.
+    fn prepend_older_comments(&mut self, ticket_id: i64, before: i64, page: Result<CommentPage>) {
+        self.loading_older_comments = false;
+        let page = match page {
+            Ok(page) => page,
+            Err(e) => {
+                self.status_message = Some(format!("Could not load older comments: {}", e));
+                return;
+            }
+        };
+        // Dropped if the ticket was left or reloaded in the meantime
+        let Some(detail) = self.current_ticket.as_mut().filter(|d| {
+            d.ticket.id == ticket_id && d.comments.first().map(|c| c.id) == Some(before)
+        }) else {
+            return;
+        };
+        let loaded = page.comments.len();
+        detail.has_more_comments = page.has_more;
+        detail.comments.splice(0..0, page.comments);
+        self.detail_scroll += loaded.saturating_sub(1);
+    }
+
+    /// Opens the ticket `delta` positions away from the current one in list order.
+    /// Stops at the ends of the list instead of wrapping around.
+    pub fn open_adjacent_ticket(&mut self, delta: i32) {
+        let Some(current_id) = self.current_ticket.as_ref().map(|t| t.ticket.id) else {
+            return;
+        };
+        let current = self
+            .tickets
+            .iter()
+            .position(|t| t.id == current_id)
+            .unwrap_or(self.selected_ticket);
+
+        match Self::adjacent_index(current, delta, self.tickets.len()) {
+            Some(index) => {
+                let id = self.tickets[index].id;
+                self.selected_ticket = index;
+                self.load_ticket_detail(id);
+            }
+            None if delta < 0 => self.status_message = Some("Already at first ticket".to_string()),
+            None => self.status_message = Some("Already at last ticket".to_string()),
+        }
+    }
+
+    fn adjacent_index(current: usize, delta: i32, len: usize) -> Option<usize> {
+        let target = current as i64 + delta as i64;
+        if target < 0 || target >= len as i64 {
+            None
+        } else {
+            Some(target as usize)
+        }
+    }
+
+    /// Opens a ticket by id, selecting it in the list if it's on the loaded page.
+    pub fn open_ticket(&mut self, id: i64) {
+        if let Some(index) = self.tickets.iter().position(|t| t.id == id) {
+            self.selected_ticket = index;
+        }
+        self.load_ticket_detail(id);
+    }
+
+    /// Copies a `curadesk://` link for the open ticket, or the selected one in the list.
+    pub fn copy_ticket_link(&mut self) {
+        let id = match self.view {
+            View::TicketList => self.tickets.get(self.selected_ticket).map(|t| t.id),
+            _ => self.current_ticket.as_ref().map(|t| t.ticket.id),
+        };
+        let Some(id) = id else {
+            return;
+        };
+
+        let link = ticket_link(id);
+        self.status_message = Some(match self.copy_to_clipboard(link.clone()) {
+            Ok(()) => format!("Copied {}", link),
+            Err(e) => format!("Clipboard error: {}", e),
+        });
+    }
+
+    pub fn copy_ticket_summary(&mut self) {
+        let Some(detail) = &self.current_ticket else {
+            return;
+        };
+
+        let summary = ticket_summary(detail, &self.date_format);
+        self.status_message = Some(match self.copy_to_clipboard(summary) {
+            Ok(()) => "Copied ticket summary".to_string(),
+            Err(e) => format!("Clipboard error: {}", e),
+        });
+    }
+
+    fn clipboard(&mut self) -> Result<&mut arboard::Clipboard> {
+        if self.clipboard.is_none() {
+            self.clipboard = Some(arboard::Clipboard::new()?);
+        }
+        Ok(self
+            .clipboard
+            .as_mut()
+            .expect("clipboard initialized above"))
+    }
+
+    fn copy_to_clipboard(&mut self, text: String) -> Result<()> {
+        self.clipboard()?.set_text(text)?;
         Ok(())
     }
 
+    /// Takes an image from the clipboard as the attachment for the new ticket.
+    pub fn paste_screenshot(&mut self) {
+        let image = match self.clipboard().and_then(|c| Ok(c.get_image()?)) {
+            Ok(image) => image,
+            Err(_) => {
+                self.status_message = Some("No image in clipboard".to_string());
+                return;
+            }
+        };
+
+        self.attach_screenshot(image.width as u32, image.height as u32, &image.bytes);
+    }
+
+    /// Attaches raw RGBA pixels as a PNG screenshot, replacing a chosen ZIP file.
+    fn attach_screenshot(&mut self, width: u32, height: u32, rgba: &[u8]) {
+        match encode_png(width, height, rgba) {
+            Ok(png) => {
+                self.status_message = Some(format!("Screenshot attached ({}x{})", width, height));
+                self.new_ticket_screenshot = Some(png);
+                self.new_ticket_zip = None;
+            }
+            Err(e) => self.status_message = Some(format!("Could not encode screenshot: {}", e)),
+        }
+    }
+
+    /// Attaches the ZIP file at the typed path to the new ticket. On errors
+    /// the prompt stays open so the path can be corrected.
+    pub fn attach_file(&mut self) {
+        match read_zip_file(self.attach_path_input.trim()) {
+            Ok(zip) => {
+                self.status_message = Some(format!(
+                    "{} attached {}",
+                    zip.file_name,
+                    crate::ui::format_size(zip.data.len() as u64)
+                ));
+                self.new_ticket_zip = Some(zip);
+                self.new_ticket_screenshot = None;
+                self.attach_path_input.clear();
+                self.view = View::CreateTicket;
+            }
+            Err(e) => self.status_message = Some(format!("{:#}", e)),
+        }
+    }
+
+    pub fn load_zip(&mut self) {
+        let Some(ticket_id) = self.current_ticket.as_ref().map(|t| t.ticket.id) else {
+            return;
+        };
+        self.spawn_request(
+            move |api| {
+                let data = api.download_zip(ticket_id)?;
+                let entries = Self::parse_zip_entries(&data)?;
+                Ok((data, entries))
+            },
+            move |app, zip| {
+                // Not opened if the agent left the ticket during the download
+                let still_open = app.view == View::TicketDetail
+                    && app.current_ticket.as_ref().map(|t| t.ticket.id) == Some(ticket_id);
+                if !still_open {
+                    return;
+                }
+                match zip {
+                    Ok((data, entries)) => {
+                        app.zip_all_entries = entries;
+                        app.zip_data = Some(data);
+                        app.change_zip_dir(String::new());
+                        app.view = View::ZipViewer;
+                    }
+                    Err(e) => app.status_message = Some(format!("Could not open ZIP: {}", e)),
+                }
+            },
+        );
+    }
+
+    /// Shows `dir` in the ZIP viewer. When moving up, the directory just left
+    /// stays selected.
+    fn change_zip_dir(&mut self, dir: String) {
+        let previous = std::mem::replace(&mut self.zip_cwd, dir);
+        self.zip_entries = list_zip_dir(&self.zip_all_entries, &self.zip_cwd);
+        self.selected_zip_entry = self
+            .zip_entries
+            .iter()
+            .position(|e| e.name != ".." && e.path == previous)
+            .unwrap_or(0);
+    }
+
     fn parse_zip_entries(data: &[u8]) -> Result<Vec<ZipEntry>> {
         let cursor = Cursor::new(data);
         let mut archive = ZipArchive::new(cursor)?;
         let mut entries: Vec<ZipEntry> = (0..archive.len())
             .filter_map(|i| {
-                let file = archive.by_index(i).ok()?;
+                let mut file = archive.by_index(i).ok()?;
+                let name = file.name().to_string();
+                let size = file.size();
+                let is_dir = file.is_dir();
+                // Only the first bytes are decompressed, not the whole entry
+                let kind = (!is_dir).then(|| {
+                    let mut head = Vec::new();
+                    match (&mut file).take(SNIFF_BYTES).read_to_end(&mut head) {
+                        Ok(_) => sniff_entry_kind(&head),
+                        Err(_) => EntryKind::Binary,
+                    }
+                });
                 Some(ZipEntry {
-                    name: file.name().to_string(),
-                    size: file.size(),
-                    is_dir: file.is_dir(),
+                    name: name.clone(),
+                    path: name,
+                    size,
+                    is_dir,
+                    kind,
                 })
             })
             .collect();
-        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
         Ok(entries)
     }
 
     pub fn open_zip_file(&mut self) -> Result<()> {
-        if let Some(data) = &self.zip_data {
-            if let Some(entry) = self.zip_entries.get(self.selected_zip_entry) {
-                if entry.is_dir {
-                    return Ok(());
+        if let Some(data) = &self.zip_data
+            && let Some(entry) = self.zip_entries.get(self.selected_zip_entry)
+        {
+            if entry.is_dir {
+                let dir = entry.path.clone();
+                self.change_zip_dir(dir);
+                return Ok(());
+            }
+
+            let content = read_zip_entry(data, &entry.path)?;
+
+            // Versuche als UTF-8 zu parsen
+            self.file_lines = None;
+            self.file_content = Some(match std::str::from_utf8(&content) {
+                Ok(text) => {
+                    #[cfg(feature = "highlight")]
+                    {
+                        self.file_lines = crate::highlight::highlight(&entry.path, text);
+                    }
+                    text.to_string()
                 }
+                Err(_) => format!("[Binärdatei: {} Bytes - x: Hex-Ansicht]", content.len()),
+            });
+            self.file_bytes = content;
+            self.file_name = entry.path.clone();
+            self.content_scroll = 0;
+            self.view = View::FileContent;
+        }
+        Ok(())
+    }
+
+    /// Writes the loaded ZIP to the current directory, named after the upload
+    pub fn save_zip(&mut self) {
+        let (Some(data), Some(detail)) = (&self.zip_data, &self.current_ticket) else {
+            return;
+        };
+
+        // Only the final component, so a crafted upload name can't escape the cwd
+        let name = Path::new(&detail.ticket.zip_filename)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| format!("ticket-{}.zip", detail.ticket.id));
+
+        self.status_message = Some(match write_new_file(Path::new(&name), data) {
+            Ok(path) => format!("Saved {}", path.display()),
+            Err(e) => format!("Could not save ZIP: {}", e),
+        });
+    }
+
+    /// Writes the selected ZIP entry verbatim to a per-ticket temp directory
+    pub fn extract_zip_entry(&mut self) {
+        let (Some(data), Some(detail)) = (&self.zip_data, &self.current_ticket) else {
+            return;
+        };
+        let Some(entry) = self.zip_entries.get(self.selected_zip_entry) else {
+            return;
+        };
+        if entry.is_dir {
+            return;
+        }
 
-                let cursor = Cursor::new(data);
-                let mut archive = ZipArchive::new(cursor)?;
-                let mut file = archive.by_name(&entry.name)?;
-
-                let mut content = Vec::new();
-                std::io::Read::read_to_end(&mut file, &mut content)?;
-
-                // Versuche als UTF-8 zu parsen
-                self.file_content = Some(
-                    String::from_utf8(content.clone())
-                        .unwrap_or_else(|_| format!("[Binärdatei: {} Bytes]", content.len())),
-                );
-                self.file_name = entry.name.clone();
-                self.content_scroll = 0;
-                self.view = View::FileContent;
+        let dir = std::env::temp_dir().join(format!("curadesk-ticket-{}", detail.ticket.id));
+        let result = (|| -> Result<PathBuf> {
+            let bytes = read_zip_entry(data, &entry.path)?;
+            // Keep only the file name, not the directories inside the archive
+            let name = Path::new(&entry.path)
+                .file_name()
+                .context("Entry has no file name")?;
+            std::fs::create_dir_all(&dir)?;
+            Ok(write_new_file(&dir.join(name), &bytes)?)
+        })();
+
+        self.status_message = Some(match result {
+            Ok(path) => format!("Extracted to {}", path.display()),
+            Err(e) => format!("Could not extract {}: {}", entry.path, e),
+        });
+    }
+
+    /// Scrolls to the next line containing `content_search`, wrapping around
+    /// at either end. `skip_current` is false for the initial search so a
+    /// match on the top line is found too.
+    pub fn find_in_content(&mut self, forward: bool, skip_current: bool) {
+        if self.content_search.is_empty() {
+            return;
+        }
+        let Some(content) = &self.file_content else {
+            return;
+        };
+
+        let matches: Vec<usize> = content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.contains(&self.content_search))
+            .map(|(i, _)| i)
+            .collect();
+
+        let current = self.content_scroll;
+        let next = if forward {
+            matches
+                .iter()
+                .find(|&&i| {
+                    if skip_current {
+                        i > current
+                    } else {
+                        i >= current
+                    }
+                })
+                .or(matches.first())
+        } else {
+            matches
+                .iter()
+                .rev()
+                .find(|&&i| {
+                    if skip_current {
+                        i < current
+                    } else {
+                        i <= current
+                    }
+                })
+                .or(matches.last())
+        };
+
+        match next {
+            Some(&line) => {
+                let wrapped = if forward {
+                    line < current
+                } else {
+                    line > current
+                };
+                if wrapped {
+                    self.status_message = Some("Search wrapped".to_string());
+                }
+                self.content_scroll = line;
+            }
+            None => {
+                self.status_message = Some(format!("No match for \"{}\"", self.content_search));
             }
         }
-        Ok(())
     }
 
-    pub fn update_ticket_state(&mut self, state: TicketState) -> Result<()> {
+    pub fn update_ticket_state(&mut self, state: TicketState) {
         let Some(ticket_id) = self.current_ticket.as_ref().map(|t| t.ticket.id) else {
-            return Ok(());
+            return;
         };
 
-        self.api.update_state(ticket_id, state.clone())?;
-        // Reload ticket detail
-        self.load_ticket_detail(ticket_id)?;
-        // Update in list too
-        if let Some(t) = self.tickets.iter_mut().find(|t| t.id == ticket_id) {
-            t.state = state;
+        self.spawn_request(
+            move |api| {
+                api.update_state(ticket_id, state)?;
+                Ok(api.get_ticket(ticket_id, COMMENT_PAGE_SIZE))
+            },
+            move |app, detail| match detail {
+                Ok(detail) => {
+                    // Update in list too
+                    if let Some(t) = app.tickets.iter_mut().find(|t| t.id == ticket_id) {
+                        t.state = state;
+                    }
+                    app.show_reloaded_ticket(detail, "Status aktualisiert");
+                }
+                Err(e) => app.status_message = Some(format!("Status nicht geändert: {}", e)),
+            },
+        );
+    }
+
+    /// Changes the ticket state, asking for a typed confirmation first if the
+    /// new state effectively ends work on the ticket.
+    pub fn request_state_change(&mut self, state: TicketState) {
+        if matches!(state, TicketState::Done | TicketState::Closed) {
+            self.request_confirmation(
+                format!("Ticket wirklich auf \"{}\" setzen?", state),
+                PendingAction::UpdateState(state),
+            );
+        } else {
+            self.update_ticket_state(state);
         }
-        self.status_message = Some("Status aktualisiert".to_string());
-        Ok(())
     }
 
-    pub fn submit_comment(&mut self) -> Result<()> {
-        if let Some(detail) = &self.current_ticket {
-            if !self.comment_input.trim().is_empty() {
-                self.api
-                    .add_comment(detail.ticket.id, self.comment_input.clone())?;
-                self.comment_input.clear();
-                // Reload ticket detail
-                self.load_ticket_detail(detail.ticket.id)?;
-                self.status_message = Some("Comment added".to_string());
-            }
+    pub fn request_confirmation(&mut self, message: String, action: PendingAction) {
+        self.confirmation = Some(Confirmation {
+            message,
+            action,
+            input: String::new(),
+            return_view: self.view.clone(),
+        });
+        self.view = View::Confirm;
+    }
+
+    /// Runs the pending action if `confirmed`, otherwise cancels. Either way
+    /// the view the prompt was opened from comes back.
+    pub fn answer_confirmation(&mut self, confirmed: bool) {
+        let Some(confirmation) = self.confirmation.take() else {
+            return;
+        };
+        self.view = confirmation.return_view;
+
+        if !confirmed {
+            self.status_message = Some("Cancelled".to_string());
+            return;
         }
-        self.view = View::TicketDetail;
-        Ok(())
+
+        match confirmation.action {
+            PendingAction::UpdateState(state) => self.update_ticket_state(state),
+            PendingAction::DeleteComment(comment_id) => self.delete_comment(comment_id),
+            PendingAction::DeleteTicket(ticket_id) => self.delete_ticket(ticket_id),
+        }
+    }
+
+    pub fn request_delete_ticket(&mut self) {
+        let Some(ticket) = self.tickets.get(self.selected_ticket) else {
+            return;
+        };
+        let label = ticket
+            .reference
+            .clone()
+            .unwrap_or_else(|| format!("#{}", ticket.id));
+        let message = format!("Ticket {} mit allen Kommentaren endgültig löschen?", label);
+        let ticket_id = ticket.id;
+        self.request_confirmation(message, PendingAction::DeleteTicket(ticket_id));
+    }
+
+    fn delete_ticket(&mut self, ticket_id: i64) {
+        let offset = self.ticket_offset;
+        let filter = self.ticket_filter.clone();
+        self.spawn_request(
+            move |api| {
+                api.delete_ticket(ticket_id)?;
+                Ok(api.list_tickets(TICKET_PAGE_SIZE, offset, &filter))
+            },
+            |app, page| match page {
+                Ok(Ok(page)) => {
+                    let selected = app.selected_ticket;
+                    app.show_tickets(page);
+                    app.selected_ticket = selected.min(app.tickets.len().saturating_sub(1));
+                    app.status_message = Some("Ticket deleted".to_string());
+                }
+                Ok(Err(e)) => {
+                    app.status_message = Some(format!("Ticket deleted - reload failed: {}", e));
+                }
+                Err(e) => app.status_message = Some(format!("Ticket nicht gelöscht: {}", e)),
+            },
+        );
+    }
+
+    fn selected_comment(&self) -> Option<&Comment> {
+        let detail = self.current_ticket.as_ref()?;
+        // Same clamp as the highlight, in case the list shrank after a reload
+        let last = detail.comments.len().checked_sub(1)?;
+        detail.comments.get(self.detail_scroll.min(last))
+    }
+
+    /// Opens the comment input prefilled with the selected comment's text.
+    pub fn start_edit_comment(&mut self) {
+        let Some(comment) = self.selected_comment().cloned() else {
+            return;
+        };
+        self.editing_comment = Some(comment.id);
+        self.comment_input.set_text(comment.text);
+        self.comment_internal = comment.internal;
+        self.view = View::AddComment;
+    }
+
+    pub fn request_delete_comment(&mut self) {
+        let Some(comment) = self.selected_comment() else {
+            return;
+        };
+        let comment_id = comment.id;
+        self.request_confirmation(
+            "Kommentar wirklich löschen?".to_string(),
+            PendingAction::DeleteComment(comment_id),
+        );
+    }
+
+    fn delete_comment(&mut self, comment_id: i64) {
+        let Some(ticket_id) = self.current_ticket.as_ref().map(|t| t.ticket.id) else {
+            return;
+        };
+
+        self.spawn_request(
+            move |api| {
+                api.delete_comment(ticket_id, comment_id)?;
+                Ok(api.get_ticket(ticket_id, COMMENT_PAGE_SIZE))
+            },
+            |app, detail| match detail {
+                Ok(detail) => app.show_reloaded_ticket(detail, "Comment deleted"),
+                Err(e) => app.status_message = Some(format!("Kommentar nicht gelöscht: {}", e)),
+            },
+        );
+    }
+
+    /// Sends the typed comment; the input stays open until the server accepted
+    /// it, so nothing is lost if sending fails.
+    pub fn submit_comment(&mut self) {
+        if self.saving {
+            return;
+        }
+        let Some(ticket_id) = self.current_ticket.as_ref().map(|t| t.ticket.id) else {
+            return;
+        };
+        if self.comment_input.text().trim().is_empty() {
+            self.view = View::TicketDetail;
+            return;
+        }
+
+        let text = self.comment_input.text().to_string();
+        if let Some(comment_id) = self.editing_comment {
+            self.save_comment_edit(ticket_id, comment_id, text);
+            return;
+        }
+
+        let internal = self.comment_internal;
+        self.saving = true;
+        self.spawn_request(
+            move |api| {
+                api.add_comment(ticket_id, text, internal)?;
+                Ok(api.get_ticket(ticket_id, COMMENT_PAGE_SIZE))
+            },
+            move |app, detail| {
+                app.saving = false;
+                match detail {
+                    Ok(detail) => {
+                        app.comment_input.clear();
+                        app.view = View::TicketDetail;
+                        let message = if internal {
+                            "Internal note added"
+                        } else {
+                            "Comment added"
+                        };
+                        app.show_reloaded_ticket(detail, message);
+                    }
+                    Err(e) => {
+                        app.status_message = Some(format!("Kommentar nicht gesendet: {}", e));
+                    }
+                }
+            },
+        );
+    }
+
+    /// Saves an edited comment and refetches only that comment, keeping the
+    /// loaded ticket and the scroll position as they are.
+    fn save_comment_edit(&mut self, ticket_id: i64, comment_id: i64, text: String) {
+        self.saving = true;
+        self.spawn_request(
+            move |api| {
+                api.update_comment(ticket_id, comment_id, text)?;
+                api.get_comment(ticket_id, comment_id)
+            },
+            |app, comment| {
+                app.saving = false;
+                match comment {
+                    Ok(comment) => {
+                        app.comment_input.clear();
+                        app.editing_comment = None;
+                        app.view = View::TicketDetail;
+                        if let Some(old) = app
+                            .current_ticket
+                            .as_mut()
+                            .and_then(|d| d.comments.iter_mut().find(|c| c.id == comment.id))
+                        {
+                            *old = comment;
+                        }
+                        app.status_message = Some("Comment updated".to_string());
+                    }
+                    Err(e) => {
+                        app.status_message = Some(format!("Kommentar nicht geändert: {}", e));
+                    }
+                }
+            },
+        );
+    }
+
+    /// Adds the typed tag to the open ticket. Like comments, the prompt stays
+    /// open until the server accepted the tag.
+    pub fn submit_tag(&mut self) {
+        if self.saving {
+            return;
+        }
+        let Some(ticket_id) = self.current_ticket.as_ref().map(|t| t.ticket.id) else {
+            return;
+        };
+        let tag = self.tag_input.trim().to_string();
+        if tag.is_empty() {
+            self.go_back();
+            return;
+        }
+
+        self.saving = true;
+        self.spawn_request(
+            move |api| {
+                api.add_tag(ticket_id, tag)?;
+                Ok(api.get_ticket(ticket_id, COMMENT_PAGE_SIZE))
+            },
+            |app, detail| {
+                app.saving = false;
+                match detail {
+                    Ok(detail) => {
+                        app.tag_input.clear();
+                        app.view = View::TicketDetail;
+                        app.show_reloaded_ticket(detail, "Tag added");
+                    }
+                    Err(e) => app.status_message = Some(format!("Tag nicht hinzugefügt: {}", e)),
+                }
+            },
+        );
     }
 
-    pub fn submit_new_ticket(&mut self) -> Result<()> {
+    pub fn submit_new_ticket(&mut self) {
+        if self.saving {
+            return;
+        }
         if self.new_ticket_description.trim().is_empty() {
             self.status_message = Some("Description cannot be empty".to_string());
-            return Ok(());
+            return;
         }
 
-        // Create minimal ZIP with the description as a text file
-        let zip_data = Self::create_minimal_zip(&self.new_ticket_description)?;
+        let zip = match self.new_ticket_attachment() {
+            Ok(zip) => zip,
+            Err(e) => {
+                self.status_message = Some(format!("Could not build attachment: {}", e));
+                return;
+            }
+        };
 
-        self.api
-            .create_ticket(self.new_ticket_description.clone(), zip_data)?;
+        let description = self.new_ticket_description.clone();
+        self.saving = true;
+        self.spawn_request(
+            move |api| api.create_ticket(description, zip.file_name, zip.data),
+            |app, ticket| {
+                app.saving = false;
+                match ticket {
+                    Ok(ticket) => app.show_created_ticket(ticket),
+                    Err(e) => app.status_message = Some(format!("Ticket nicht erstellt: {}", e)),
+                }
+            },
+        );
+    }
 
+    fn show_created_ticket(&mut self, ticket: Ticket) {
         self.new_ticket_description.clear();
-        self.load_tickets()?;
-        self.view = View::TicketList;
-        self.status_message = Some("Ticket created".to_string());
-        Ok(())
+        self.new_ticket_screenshot = None;
+        self.new_ticket_zip = None;
+        // Listed right away, so it is selected when going back even if the
+        // reload below is still running
+        let filter = &self.ticket_filter;
+        let listed = self.ticket_offset == 0
+            && filter.state.is_none_or(|state| state == ticket.state)
+            && filter
+                .user_id
+                .is_none_or(|user_id| user_id == ticket.user_id);
+        if listed && !self.tickets.iter().any(|t| t.id == ticket.id) {
+            self.tickets.insert(0, ticket.clone());
+            self.ticket_total += 1;
+        }
+        self.load_tickets();
+        // Jump straight into the new ticket so it can be commented on right away
+        self.open_ticket(ticket.id);
+        self.status_message = Some(format!(
+            "Ticket #{} created - c: Comment, l: Copy link, Esc: Back to list",
+            ticket.id
+        ));
     }
 
-    fn create_minimal_zip(description: &str) -> Result<Vec<u8>> {
-        let mut buffer = Cursor::new(Vec::new());
-        {
-            let mut zip = ZipWriter::new(&mut buffer);
-            let options = SimpleFileOptions::default();
-            zip.start_file("report.txt", options)?;
-            zip.write_all(description.as_bytes())?;
-            zip.finish()?;
+    /// The chosen ZIP file; otherwise the pasted screenshot, or the description
+    /// as a text file, wrapped in a minimal ZIP.
+    fn new_ticket_attachment(&self) -> Result<AttachedZip> {
+        if let Some(zip) = &self.new_ticket_zip {
+            return Ok(zip.clone());
         }
-        Ok(buffer.into_inner())
+        let data = match &self.new_ticket_screenshot {
+            Some(png) => zip_single_file("screenshot.png", png)?,
+            None => zip_single_file("report.txt", self.new_ticket_description.as_bytes())?,
+        };
+        Ok(AttachedZip {
+            file_name: "report.zip".to_string(),
+            data,
+        })
+    }
+
+    /// Switches the opened file between text and hexdump, starting at the top
+    pub fn toggle_hex_view(&mut self) {
+        self.view = match self.view {
+            View::FileContent => View::HexDump,
+            View::HexDump => View::FileContent,
+            _ => return,
+        };
+        self.content_scroll = 0;
     }
 
     pub fn move_selection(&mut self, delta: i32) {
@@ -225,39 +1515,354 @@ impl App {
                 }
             }
             View::TicketDetail => {
-                self.detail_scroll = (self.detail_scroll as i32 + delta).max(0) as usize;
+                let (has_more, last) = self.current_ticket.as_ref().map_or((false, 0), |t| {
+                    (t.has_more_comments, t.comments.len().saturating_sub(1))
+                });
+                if delta < 0 && self.detail_scroll == 0 && has_more && !self.loading_older_comments
+                {
+                    self.load_older_comments();
+                }
+                // Stop at the last comment, which stays the one `e`/`d` act on
+                self.detail_scroll =
+                    ((self.detail_scroll as i32 + delta).max(0) as usize).min(last);
             }
-            View::FileContent => {
+            View::FileContent | View::HexDump => {
                 self.content_scroll = (self.content_scroll as i32 + delta).max(0) as usize;
             }
             _ => {}
         }
     }
 
+    /// True where keys are typed into an input, so `?` can't open the help.
+    pub fn is_typing(&self) -> bool {
+        match self.view {
+            View::AddComment | View::CreateTicket | View::AttachFile | View::AddTag => true,
+            View::FileContent => self.content_search_active,
+            View::Confirm => self
+                .confirmation
+                .as_ref()
+                .is_some_and(|c| c.action.needs_typed_confirmation()),
+            _ => false,
+        }
+    }
+
+    pub fn open_help(&mut self) {
+        if self.view != View::Help {
+            self.help_return_view = Some(self.view.clone());
+            self.view = View::Help;
+        }
+    }
+
+    pub fn close_help(&mut self) {
+        if let Some(view) = self.help_return_view.take() {
+            self.view = view;
+        }
+    }
+
     pub fn go_back(&mut self) {
         match self.view {
             View::TicketDetail => {
                 self.view = View::TicketList;
                 self.current_ticket = None;
             }
+            View::ZipViewer if !self.zip_cwd.is_empty() => {
+                let parent = zip_parent_dir(&self.zip_cwd);
+                self.change_zip_dir(parent);
+            }
             View::ZipViewer => {
                 self.view = View::TicketDetail;
                 self.zip_data = None;
+                self.zip_all_entries.clear();
                 self.zip_entries.clear();
             }
             View::FileContent => {
                 self.view = View::ZipViewer;
                 self.file_content = None;
+                self.file_bytes.clear();
+                self.file_lines = None;
+                self.content_search_active = false;
             }
+            View::HexDump => self.toggle_hex_view(),
             View::AddComment => {
                 self.view = View::TicketDetail;
                 self.comment_input.clear();
+                self.comment_internal = false;
+                self.editing_comment = None;
             }
             View::CreateTicket => {
                 self.view = View::TicketList;
                 self.new_ticket_description.clear();
+                self.new_ticket_screenshot = None;
+                self.new_ticket_zip = None;
+            }
+            View::AttachFile => {
+                self.view = View::CreateTicket;
+                self.attach_path_input.clear();
+            }
+            View::AddTag => {
+                self.view = View::TicketDetail;
+                self.tag_input.clear();
+            }
+            View::Confirm => {
+                if let Some(confirmation) = self.confirmation.take() {
+                    self.view = confirmation.return_view;
+                }
+                self.status_message = Some("Cancelled".to_string());
             }
             _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An app whose requests go nowhere; tests drive its state directly.
+    fn test_app() -> App {
+        App::new(ApiClient::new(
+            "http://127.0.0.1:9".to_string(),
+            "test".to_string(),
+        ))
+    }
+
+    #[test]
+    fn adjacent_index_stays_in_bounds() {
+        assert_eq!(App::adjacent_index(0, 1, 3), Some(1));
+        assert_eq!(App::adjacent_index(2, -1, 3), Some(1));
+        assert_eq!(App::adjacent_index(0, -1, 3), None);
+        assert_eq!(App::adjacent_index(2, 1, 3), None);
+        assert_eq!(App::adjacent_index(0, 1, 0), None);
+        assert_eq!(App::adjacent_index(0, 0, 0), None);
+    }
+
+    #[test]
+    fn ticket_link_round_trips() {
+        for id in [1, 42, i64::MAX] {
+            assert_eq!(parse_ticket_link(&ticket_link(id)), Some(id));
+        }
+    }
+
+    #[test]
+    fn parses_pasted_ticket_references() {
+        assert_eq!(parse_ticket_link("  curadesk://ticket/42/ "), Some(42));
+        assert_eq!(parse_ticket_link("#42"), Some(42));
+        assert_eq!(parse_ticket_link("42"), Some(42));
+        assert_eq!(parse_ticket_link("curadesk://ticket/0"), None);
+        assert_eq!(parse_ticket_link("#-3"), None);
+        assert_eq!(parse_ticket_link("curadesk://ticket/abc"), None);
+        assert_eq!(parse_ticket_link(""), None);
+    }
+
+    fn tickets(ids: &[i64]) -> Vec<Ticket> {
+        ids.iter()
+            .map(|&id| Ticket {
+                id,
+                user_id: 1,
+                created_at: 0,
+                updated_at: 0,
+                description: format!("Ticket {}", id),
+                zip_filename: String::new(),
+                state: TicketState::New,
+                priority: Default::default(),
+                reference: None,
+                assignee_id: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merge_keeps_the_selected_ticket_by_id() {
+        let old = tickets(&[3, 2, 1]);
+        let new = tickets(&[5, 4, 3, 2, 1]);
+
+        let (index, arrived) = merge_ticket_list(&old, 1, &new);
+
+        assert_eq!(new[index].id, 2);
+        assert_eq!(arrived, HashSet::from([5, 4]));
+    }
+
+    #[test]
+    fn merge_clamps_when_the_selected_ticket_is_gone() {
+        let old = tickets(&[3, 2, 1]);
+        let new = tickets(&[3, 2]);
+
+        let (index, arrived) = merge_ticket_list(&old, 2, &new);
+
+        assert_eq!(index, 1);
+        assert!(arrived.is_empty());
+    }
+
+    #[test]
+    fn merge_into_an_empty_list() {
+        let (index, arrived) = merge_ticket_list(&[], 0, &tickets(&[1]));
+        assert_eq!(index, 0);
+        assert_eq!(arrived, HashSet::from([1]));
+
+        let (index, arrived) = merge_ticket_list(&tickets(&[1]), 0, &[]);
+        assert_eq!(index, 0);
+        assert!(arrived.is_empty());
+    }
+
+    #[test]
+    fn assigned_filter_keeps_only_my_tickets() {
+        let mut list = tickets(&[3, 2, 1]);
+        list[0].assignee_id = Some(7);
+        list[1].assignee_id = Some(8);
+
+        let (mine, notice) = filter_assigned(list, Some(7));
+
+        assert_eq!(mine.iter().map(|t| t.id).collect::<Vec<_>>(), [3]);
+        assert!(notice.is_none());
+    }
+
+    #[test]
+    fn assigned_filter_shows_everything_without_assignees() {
+        let (all, notice) = filter_assigned(tickets(&[3, 2, 1]), Some(7));
+        assert_eq!(all.len(), 3);
+        assert!(notice.unwrap().contains("no assignee"));
+
+        let (all, notice) = filter_assigned(tickets(&[3, 2, 1]), None);
+        assert_eq!(all.len(), 3);
+        assert!(notice.unwrap().contains("user unknown"));
+    }
+
+    #[test]
+    fn pasted_screenshot_is_uploaded_as_a_single_entry_zip() {
+        let mut app = test_app();
+        app.new_ticket_description = "Button is cut off".to_string();
+        app.attach_screenshot(2, 1, &[255, 0, 0, 255, 0, 0, 255, 255]);
+        let png = app.new_ticket_screenshot.clone().unwrap();
+
+        let zip = app.new_ticket_attachment().unwrap();
+
+        let mut archive = ZipArchive::new(Cursor::new(zip.data)).unwrap();
+        assert_eq!(archive.len(), 1);
+        let mut entry = archive.by_index(0).unwrap();
+        assert_eq!(entry.name(), "screenshot.png");
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content).unwrap();
+        assert_eq!(content, png);
+        assert!(content.starts_with(b"\x89PNG"));
+    }
+
+    fn comments(ids: std::ops::RangeInclusive<i64>) -> Vec<Comment> {
+        ids.map(|id| Comment {
+            id,
+            ticket_id: 1,
+            user_id: 1,
+            created_at: id,
+            text: format!("Comment {}", id),
+            attachment: None,
+            internal: false,
+            edited_at: None,
+        })
+        .collect()
+    }
+
+    #[test]
+    fn older_comments_are_prepended_without_moving_the_view() {
+        let mut app = test_app();
+        app.current_ticket = Some(TicketDetail {
+            ticket: tickets(&[1]).remove(0),
+            comments: comments(4..=6),
+            has_more_comments: true,
+            tags: Vec::new(),
+        });
+        app.loading_older_comments = true;
+
+        app.prepend_older_comments(
+            1,
+            4,
+            Ok(CommentPage {
+                comments: comments(1..=3),
+                has_more: false,
+            }),
+        );
+
+        let detail = app.current_ticket.as_ref().unwrap();
+        let ids: Vec<i64> = detail.comments.iter().map(|c| c.id).collect();
+        assert_eq!(ids, [1, 2, 3, 4, 5, 6]);
+        assert!(!detail.has_more_comments);
+        assert!(!app.loading_older_comments);
+        // The selection moves one up from the previous top comment, not to
+        // the start of the loaded page
+        assert_eq!(app.selected_comment().unwrap().id, 3);
+        assert_eq!(detail.comments[app.detail_scroll + 1].id, 4);
+    }
+
+    #[test]
+    fn older_comments_for_a_reloaded_ticket_are_dropped() {
+        let mut app = test_app();
+        app.current_ticket = Some(TicketDetail {
+            ticket: tickets(&[1]).remove(0),
+            comments: comments(5..=6),
+            has_more_comments: true,
+            tags: Vec::new(),
+        });
+
+        let page = CommentPage {
+            comments: comments(1..=3),
+            has_more: false,
+        };
+        app.prepend_older_comments(1, 4, Ok(page));
+
+        assert_eq!(app.current_ticket.as_ref().unwrap().comments.len(), 2);
+        assert_eq!(app.detail_scroll, 0);
+    }
+
+    #[test]
+    fn typed_confirmation_accepts_only_the_confirm_words() {
+        assert!(is_confirmed("ja"));
+        assert!(is_confirmed(" YES "));
+        for wrong in ["", "y", "j", "jaa", "no", "yes please"] {
+            assert!(!is_confirmed(wrong), "{:?} confirmed", wrong);
+        }
+    }
+
+    #[test]
+    fn delete_runs_only_after_confirmation() {
+        let mut app = test_app();
+        app.tickets = tickets(&[2, 1]);
+
+        app.request_delete_ticket();
+        let confirmation = app.confirmation.as_ref().unwrap();
+        assert_eq!(confirmation.action, PendingAction::DeleteTicket(2));
+        assert!(confirmation.action.needs_typed_confirmation());
+        assert_eq!(app.view, View::Confirm);
+        assert!(!app.loading, "nothing is sent before the answer");
+
+        // A wrong word cancels and leaves the ticket alone
+        app.answer_confirmation(is_confirmed("y"));
+        assert_eq!(app.view, View::TicketList);
+        assert!(app.confirmation.is_none());
+        assert_eq!(app.status_message.as_deref(), Some("Cancelled"));
+        assert!(!app.loading);
+
+        app.request_delete_ticket();
+        app.answer_confirmation(is_confirmed("ja"));
+        assert_eq!(app.view, View::TicketList);
+        assert!(app.loading, "the delete request is on its way");
+    }
+
+    #[test]
+    fn created_ticket_is_confirmed_and_listed() {
+        let mut app = test_app();
+        app.tickets = tickets(&[2, 1]);
+        app.ticket_total = 2;
+        app.selected_ticket = 1;
+        app.new_ticket_description = "Printer on fire".to_string();
+
+        app.show_created_ticket(tickets(&[3]).remove(0));
+
+        let status = app.status_message.as_deref().unwrap();
+        assert!(status.starts_with("Ticket #3 created"), "{}", status);
+        assert_eq!(
+            app.tickets.iter().map(|t| t.id).collect::<Vec<_>>(),
+            [3, 2, 1]
+        );
+        assert_eq!(app.selected_ticket, 0);
+        assert_eq!(app.ticket_total, 3);
+        assert!(app.new_ticket_description.is_empty());
+    }
+}
@@ -0,0 +1,115 @@
+//! Multi-line text buffer with a cursor, used for writing comments.
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+#[derive(Debug, Clone, Default)]
+pub struct TextEditor {
+    text: String,
+    /// Byte offset into `text`, always on a grapheme boundary
+    cursor: usize,
+}
+
+impl TextEditor {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replaces the content and puts the cursor at its end.
+    pub fn set_text(&mut self, text: String) {
+        self.cursor = text.len();
+        self.text = text;
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    /// Deletes the grapheme before the cursor; at the start of a line this
+    /// joins it with the previous one.
+    pub fn backspace(&mut self) {
+        if let Some(prev) = self.prev_boundary() {
+            self.text.replace_range(prev..self.cursor, "");
+            self.cursor = prev;
+        }
+    }
+
+    pub fn move_left(&mut self) {
+        if let Some(prev) = self.prev_boundary() {
+            self.cursor = prev;
+        }
+    }
+
+    pub fn move_right(&mut self) {
+        if let Some(next) = self.next_boundary() {
+            self.cursor = next;
+        }
+    }
+
+    pub fn move_line_start(&mut self) {
+        self.cursor = self.text[..self.cursor].rfind('\n').map_or(0, |i| i + 1);
+    }
+
+    pub fn move_line_end(&mut self) {
+        self.cursor += self.text[self.cursor..]
+            .find('\n')
+            .unwrap_or(self.text.len() - self.cursor);
+    }
+
+    fn prev_boundary(&self) -> Option<usize> {
+        self.text[..self.cursor]
+            .grapheme_indices(true)
+            .next_back()
+            .map(|(i, _)| i)
+    }
+
+    fn next_boundary(&self) -> Option<usize> {
+        self.text[self.cursor..]
+            .graphemes(true)
+            .next()
+            .map(|g| self.cursor + g.len())
+    }
+
+    /// Splits the text into rows at most `width` columns wide, breaking long
+    /// lines anywhere. Also returns the cursor position as (column, row).
+    pub fn wrap(&self, width: usize) -> (Vec<String>, (usize, usize)) {
+        let width = width.max(1);
+        let mut rows = Vec::new();
+        let mut cursor = (0, 0);
+        let mut offset = 0;
+
+        for line in self.text.split('\n') {
+            let mut row = String::new();
+            let mut row_width = 0;
+            for (i, grapheme) in line.grapheme_indices(true) {
+                let grapheme_width = grapheme.width();
+                if row_width + grapheme_width > width && !row.is_empty() {
+                    rows.push(std::mem::take(&mut row));
+                    row_width = 0;
+                }
+                if offset + i == self.cursor {
+                    cursor = (row_width, rows.len());
+                }
+                row.push_str(grapheme);
+                row_width += grapheme_width;
+            }
+            if offset + line.len() == self.cursor {
+                // A full row leaves no room for the cursor; it goes below
+                cursor = if row_width >= width {
+                    (0, rows.len() + 1)
+                } else {
+                    (row_width, rows.len())
+                };
+            }
+            rows.push(row);
+            offset += line.len() + 1;
+        }
+        (rows, cursor)
+    }
+}
@@ -0,0 +1,83 @@
+//! Syntax highlighting for the file content viewer, only built with the
+//! `highlight` feature.
+
+use std::path::Path;
+use std::sync::OnceLock;
+
+use ratatui::{
+    style::{Color, Style},
+    text::{Line, Span},
+};
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+/// Larger files are shown plain; highlighting them would stall the UI
+const MAX_HIGHLIGHT_BYTES: usize = 2 * 1024 * 1024;
+
+const THEME: &str = "base16-ocean.dark";
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme() -> &'static Theme {
+    static THEME_SET: OnceLock<ThemeSet> = OnceLock::new();
+    &THEME_SET.get_or_init(ThemeSet::load_defaults).themes[THEME]
+}
+
+/// Highlighted lines for `content`, picked by the extension of `file_name`.
+/// `None` means the file should be rendered plain.
+pub fn highlight(file_name: &str, content: &str) -> Option<Vec<Line<'static>>> {
+    if content.len() > MAX_HIGHLIGHT_BYTES {
+        return None;
+    }
+    let extension = Path::new(file_name).extension()?.to_str()?;
+
+    // syntect ships no grammar for logs, so color them by level instead
+    if extension.eq_ignore_ascii_case("log") {
+        return Some(content.lines().map(highlight_log_line).collect());
+    }
+
+    let syntax = syntaxes().find_syntax_by_extension(extension)?;
+    if syntax.name == "Plain Text" {
+        return None;
+    }
+
+    let mut highlighter = HighlightLines::new(syntax, theme());
+    let mut lines = Vec::new();
+    for line in LinesWithEndings::from(content) {
+        let ranges = highlighter.highlight_line(line, syntaxes()).ok()?;
+        let spans: Vec<Span<'static>> = ranges
+            .into_iter()
+            .map(|(style, text)| (style, text.trim_end_matches(['\n', '\r'])))
+            .filter(|(_, text)| !text.is_empty())
+            .map(|(style, text)| {
+                let fg = style.foreground;
+                Span::styled(
+                    text.to_string(),
+                    Style::default().fg(Color::Rgb(fg.r, fg.g, fg.b)),
+                )
+            })
+            .collect();
+        lines.push(Line::from(spans));
+    }
+    Some(lines)
+}
+
+fn highlight_log_line(line: &str) -> Line<'static> {
+    let color = if line.contains("ERROR") || line.contains("FATAL") {
+        Some(Color::Red)
+    } else if line.contains("WARN") {
+        Some(Color::Yellow)
+    } else if line.contains("DEBUG") || line.contains("TRACE") {
+        Some(Color::DarkGray)
+    } else {
+        None
+    };
+
+    let style = color.map_or_else(Style::default, |c| Style::default().fg(c));
+    Line::styled(line.to_string(), style)
+}
@@ -1,34 +1,114 @@
 mod api;
 mod app;
+mod editor;
+#[cfg(feature = "highlight")]
+mod highlight;
 mod ui;
 
 use std::io;
+use std::path::PathBuf;
 use std::time::Duration;
 
 use anyhow::{Context, Result};
+use clap::Parser;
 use crossterm::{
-    event::{self, Event, KeyCode, KeyEventKind},
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
     execute,
     terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
 };
+use directories::ProjectDirs;
 use ratatui::prelude::*;
+use serde::Deserialize;
 use support_common::TicketState;
 
 use api::ApiClient;
 use app::{App, View};
 
+#[derive(Parser)]
+#[command(name = "support-cli")]
+#[command(about = "Terminal UI for Curadesk support staff")]
+struct Args {
+    /// strftime pattern for timestamps (local time), e.g. "%d.%m.%Y %H:%M"
+    #[arg(long, env = "SUPPORT_DATE_FORMAT", default_value = ui::DEFAULT_DATE_FORMAT)]
+    date_format: String,
+
+    /// Open a ticket directly, by id or link (e.g. curadesk://ticket/42)
+    #[arg(long)]
+    open: Option<String>,
+
+    /// Seconds between ticket list refreshes when auto-refresh (`a`) is on
+    #[arg(long, default_value = "30")]
+    refresh_secs: u64,
+}
+
+/// Optional settings from `~/.config/curadesk/cli.toml`; env vars take precedence
+#[derive(Deserialize, Default)]
+struct CliConfig {
+    base_url: Option<String>,
+    api_key: Option<String>,
+    identity_url: Option<String>,
+}
+
+fn config_path() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "curadesk").map(|dirs| dirs.config_dir().join("cli.toml"))
+}
+
+fn load_config() -> Result<CliConfig> {
+    let Some(path) = config_path() else {
+        return Ok(CliConfig::default());
+    };
+    match std::fs::read_to_string(&path) {
+        Ok(text) => toml::from_str(&text)
+            .with_context(|| format!("Ungültige Konfigurationsdatei {}", path.display())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(CliConfig::default()),
+        Err(e) => Err(e).with_context(|| format!("Kann {} nicht lesen", path.display())),
+    }
+}
+
 fn main() -> Result<()> {
-    // Config laden
-    let api_key =
-        std::env::var("SUPPORT_API_KEY").context("SUPPORT_API_KEY Umgebungsvariable fehlt")?;
-    let base_url =
-        std::env::var("SUPPORT_URL").unwrap_or_else(|_| "http://localhost:3000".to_string());
+    let args = Args::parse();
+    ui::validate_date_format(&args.date_format)?;
+    let open_id = args
+        .open
+        .as_deref()
+        .map(|link| {
+            app::parse_ticket_link(link).context(format!("Ungültiger Ticket-Link: {}", link))
+        })
+        .transpose()?;
+
+    // Config laden: Umgebungsvariablen vor Konfigurationsdatei
+    let config = load_config()?;
+    let api_key = std::env::var("SUPPORT_API_KEY")
+        .ok()
+        .or(config.api_key)
+        .with_context(|| {
+            format!(
+                "Kein API-Key: SUPPORT_API_KEY setzen (hat Vorrang) oder api_key in {} eintragen",
+                config_path()
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_else(|| "~/.config/curadesk/cli.toml".to_string())
+            )
+        })?;
+    let base_url = std::env::var("SUPPORT_URL")
+        .ok()
+        .or(config.base_url)
+        .unwrap_or_else(|| "http://localhost:3000".to_string());
+    let identity_url = std::env::var("SUPPORT_IDENTITY_URL")
+        .ok()
+        .or(config.identity_url)
+        .unwrap_or_else(|| "http://localhost:3001".to_string());
 
     let api = ApiClient::new(base_url, api_key);
     let mut app = App::new(api);
+    app.date_format = args.date_format;
+    app.refresh_interval = Duration::from_secs(args.refresh_secs.max(1));
 
     // Initial laden
-    app.load_tickets()?;
+    app.load_tickets();
+    app.resolve_current_user(identity_url);
+    if let Some(id) = open_id {
+        app.open_ticket(id);
+    }
 
     // Terminal setup
     enable_raw_mode()?;
@@ -50,76 +130,124 @@ fn main() -> Result<()> {
 
 fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> Result<()> {
     while app.running {
+        app.poll_requests();
+        app.tick_auto_refresh();
         terminal.draw(|f| ui::draw(f, app))?;
 
-        if event::poll(Duration::from_millis(250))? {
-            if let Event::Key(key) = event::read()? {
-                if key.kind != KeyEventKind::Press {
-                    continue;
-                }
-
-                // Clear status message on any key
-                app.status_message = None;
-
-                match app.view {
-                    View::TicketList => handle_ticket_list_keys(app, key.code)?,
-                    View::TicketDetail => handle_ticket_detail_keys(app, key.code)?,
-                    View::ZipViewer => handle_zip_viewer_keys(app, key.code)?,
-                    View::FileContent => handle_file_content_keys(app, key.code),
-                    View::AddComment => handle_add_comment_keys(app, key.code)?,
-                    View::CreateTicket => handle_create_ticket_keys(app, key.code)?,
-                }
+        // Redraw more often while a request runs so the spinner turns
+        let timeout = if app.loading { 100 } else { 250 };
+        if event::poll(Duration::from_millis(timeout))?
+            && let Event::Key(key) = event::read()?
+        {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            // Clear status message on any key
+            app.status_message = None;
+
+            let help_key =
+                key.code == KeyCode::F(1) || (key.code == KeyCode::Char('?') && !app.is_typing());
+            if help_key && app.view != View::Help {
+                app.open_help();
+                continue;
+            }
+
+            match app.view {
+                View::TicketList => handle_ticket_list_keys(app, key.code),
+                View::TicketDetail => handle_ticket_detail_keys(app, key.code),
+                View::ZipViewer => handle_zip_viewer_keys(app, key.code)?,
+                View::FileContent => handle_file_content_keys(app, key.code),
+                View::HexDump => handle_hex_view_keys(app, key.code),
+                View::AddComment => handle_add_comment_keys(app, key),
+                View::CreateTicket => handle_create_ticket_keys(app, key),
+                View::AttachFile => handle_attach_file_keys(app, key.code),
+                View::AddTag => handle_add_tag_keys(app, key.code),
+                View::Confirm => handle_confirm_keys(app, key.code),
+                View::Help => app.close_help(),
             }
         }
     }
     Ok(())
 }
 
-fn handle_ticket_list_keys(app: &mut App, code: KeyCode) -> Result<()> {
+fn handle_ticket_list_keys(app: &mut App, code: KeyCode) {
     match code {
         KeyCode::Char('q') => app.running = false,
-        KeyCode::Char('r') => {
-            app.load_tickets()?;
-            app.status_message = Some("Tickets refreshed".to_string());
-        }
+        KeyCode::Char('r') => app.refresh_tickets(),
         KeyCode::Char('n') => {
             app.view = View::CreateTicket;
         }
+        KeyCode::Char('l') => app.copy_ticket_link(),
+        KeyCode::Char('a') => app.toggle_auto_refresh(),
+        KeyCode::Char('f') => app.cycle_state_filter(),
+        KeyCode::Char('1') => app.toggle_state_filter(TicketState::New),
+        KeyCode::Char('2') => app.toggle_state_filter(TicketState::InProgress),
+        KeyCode::Char('3') => app.toggle_state_filter(TicketState::Done),
+        KeyCode::Char('4') => app.toggle_state_filter(TicketState::Closed),
+        KeyCode::Char('5') => app.toggle_state_filter(TicketState::Reopened),
+        KeyCode::Char('0') => app.set_state_filter(None),
+        KeyCode::Char('u') => app.toggle_user_filter(),
+        KeyCode::Char('m') => app.toggle_assigned_filter(),
+        KeyCode::Char('s') => app.toggle_activity_sort(),
+        KeyCode::Char('d') => app.request_delete_ticket(),
+        KeyCode::PageDown => app.change_ticket_page(1),
+        KeyCode::PageUp => app.change_ticket_page(-1),
         KeyCode::Up | KeyCode::Char('k') => app.move_selection(-1),
         KeyCode::Down | KeyCode::Char('j') => app.move_selection(1),
         KeyCode::Enter => {
             if let Some(ticket) = app.tickets.get(app.selected_ticket) {
-                app.load_ticket_detail(ticket.id)?;
+                app.load_ticket_detail(ticket.id);
             }
         }
         _ => {}
     }
-    Ok(())
 }
 
-fn handle_ticket_detail_keys(app: &mut App, code: KeyCode) -> Result<()> {
+fn handle_ticket_detail_keys(app: &mut App, code: KeyCode) {
     match code {
         KeyCode::Esc => app.go_back(),
         KeyCode::Up | KeyCode::Char('k') => app.move_selection(-1),
         KeyCode::Down | KeyCode::Char('j') => app.move_selection(1),
+        KeyCode::Char('n') | KeyCode::Char(']') => {
+            app.open_adjacent_ticket(1);
+        }
+        KeyCode::Char('p') | KeyCode::Char('[') => {
+            app.open_adjacent_ticket(-1);
+        }
+        KeyCode::Char('l') => app.copy_ticket_link(),
+        KeyCode::Char('y') => app.copy_ticket_summary(),
         KeyCode::Char('z') => {
-            app.load_zip()?;
+            app.load_zip();
         }
         KeyCode::Char('c') => {
+            app.comment_internal = false;
             app.view = View::AddComment;
         }
+        KeyCode::Char('i') => {
+            app.comment_internal = true;
+            app.view = View::AddComment;
+        }
+        KeyCode::Char('e') => app.start_edit_comment(),
+        KeyCode::Char('d') => app.request_delete_comment(),
+        KeyCode::Char('t') => app.view = View::AddTag,
         KeyCode::Char('1') => {
-            app.update_ticket_state(TicketState::New)?;
+            app.update_ticket_state(TicketState::New);
         }
         KeyCode::Char('2') => {
-            app.update_ticket_state(TicketState::InProgress)?;
+            app.update_ticket_state(TicketState::InProgress);
         }
         KeyCode::Char('3') => {
-            app.update_ticket_state(TicketState::Done)?;
+            app.request_state_change(TicketState::Done);
+        }
+        KeyCode::Char('4') => {
+            app.request_state_change(TicketState::Closed);
+        }
+        KeyCode::Char('5') => {
+            app.update_ticket_state(TicketState::Reopened);
         }
         _ => {}
     }
-    Ok(())
 }
 
 fn handle_zip_viewer_keys(app: &mut App, code: KeyCode) -> Result<()> {
@@ -130,52 +258,166 @@ fn handle_zip_viewer_keys(app: &mut App, code: KeyCode) -> Result<()> {
         KeyCode::Enter => {
             app.open_zip_file()?;
         }
+        KeyCode::Char('s') => app.save_zip(),
+        KeyCode::Char('e') => app.extract_zip_entry(),
         _ => {}
     }
     Ok(())
 }
 
 fn handle_file_content_keys(app: &mut App, code: KeyCode) {
+    if app.content_search_active {
+        handle_content_search_keys(app, code);
+        return;
+    }
+
     match code {
         KeyCode::Esc => app.go_back(),
+        KeyCode::Char('/') => {
+            app.content_search.clear();
+            app.content_search_active = true;
+        }
+        KeyCode::Char('n') => app.find_in_content(true, true),
+        KeyCode::Char('N') => app.find_in_content(false, true),
+        KeyCode::Char('x') => app.toggle_hex_view(),
+        KeyCode::Up | KeyCode::Char('k') => app.move_selection(-1),
+        KeyCode::Down | KeyCode::Char('j') => app.move_selection(1),
+        KeyCode::PageUp => app.move_selection(-20),
+        KeyCode::PageDown => app.move_selection(20),
+        KeyCode::Char('e') => app.extract_zip_entry(),
+        _ => {}
+    }
+}
+
+fn handle_hex_view_keys(app: &mut App, code: KeyCode) {
+    match code {
+        KeyCode::Esc | KeyCode::Char('x') => app.go_back(),
         KeyCode::Up | KeyCode::Char('k') => app.move_selection(-1),
         KeyCode::Down | KeyCode::Char('j') => app.move_selection(1),
         KeyCode::PageUp => app.move_selection(-20),
         KeyCode::PageDown => app.move_selection(20),
+        KeyCode::Char('e') => app.extract_zip_entry(),
         _ => {}
     }
 }
 
-fn handle_add_comment_keys(app: &mut App, code: KeyCode) -> Result<()> {
+fn handle_content_search_keys(app: &mut App, code: KeyCode) {
     match code {
+        KeyCode::Esc => {
+            app.content_search_active = false;
+            app.content_search.clear();
+        }
+        KeyCode::Enter => {
+            app.content_search_active = false;
+            app.find_in_content(true, false);
+        }
+        KeyCode::Backspace => {
+            app::pop_grapheme(&mut app.content_search);
+        }
+        KeyCode::Char(c) => {
+            app.content_search.push(c);
+        }
+        _ => {}
+    }
+}
+
+fn handle_add_comment_keys(app: &mut App, key: KeyEvent) {
+    let input = &mut app.comment_input;
+    match key.code {
+        KeyCode::Esc => app.go_back(),
+        KeyCode::Char('s') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            app.submit_comment();
+        }
+        KeyCode::Enter => input.insert('\n'),
+        KeyCode::Backspace => input.backspace(),
+        KeyCode::Left => input.move_left(),
+        KeyCode::Right => input.move_right(),
+        KeyCode::Home => input.move_line_start(),
+        KeyCode::End => input.move_line_end(),
+        KeyCode::Char(c) => input.insert(c),
+        _ => {}
+    }
+}
+
+fn handle_create_ticket_keys(app: &mut App, key: KeyEvent) {
+    match key.code {
         KeyCode::Esc => app.go_back(),
+        KeyCode::Char('v') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            app.paste_screenshot();
+        }
+        KeyCode::Char('o') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            app.view = View::AttachFile;
+        }
         KeyCode::Enter => {
-            app.submit_comment()?;
+            app.submit_new_ticket();
         }
         KeyCode::Backspace => {
-            app.comment_input.pop();
+            app::pop_grapheme(&mut app.new_ticket_description);
         }
         KeyCode::Char(c) => {
-            app.comment_input.push(c);
+            app.new_ticket_description.push(c);
         }
         _ => {}
     }
-    Ok(())
 }
 
-fn handle_create_ticket_keys(app: &mut App, code: KeyCode) -> Result<()> {
+fn handle_attach_file_keys(app: &mut App, code: KeyCode) {
+    match code {
+        KeyCode::Esc => app.go_back(),
+        KeyCode::Enter => app.attach_file(),
+        KeyCode::Backspace => {
+            app::pop_grapheme(&mut app.attach_path_input);
+        }
+        KeyCode::Char(c) => {
+            app.attach_path_input.push(c);
+        }
+        _ => {}
+    }
+}
+
+fn handle_add_tag_keys(app: &mut App, code: KeyCode) {
+    match code {
+        KeyCode::Esc => app.go_back(),
+        KeyCode::Enter => app.submit_tag(),
+        KeyCode::Backspace => {
+            app.tag_input.pop();
+        }
+        KeyCode::Char(c) => {
+            app.tag_input.push(c);
+        }
+        _ => {}
+    }
+}
+
+fn handle_confirm_keys(app: &mut App, code: KeyCode) {
+    let Some(confirmation) = &app.confirmation else {
+        return;
+    };
+    if !confirmation.action.needs_typed_confirmation() {
+        match code {
+            KeyCode::Char('y') | KeyCode::Char('j') => app.answer_confirmation(true),
+            KeyCode::Char('n') | KeyCode::Esc => app.answer_confirmation(false),
+            _ => {}
+        }
+        return;
+    }
+
     match code {
         KeyCode::Esc => app.go_back(),
         KeyCode::Enter => {
-            app.submit_new_ticket()?;
+            let confirmed = app::is_confirmed(&confirmation.input);
+            app.answer_confirmation(confirmed);
         }
         KeyCode::Backspace => {
-            app.new_ticket_description.pop();
+            if let Some(confirmation) = app.confirmation.as_mut() {
+                app::pop_grapheme(&mut confirmation.input);
+            }
         }
         KeyCode::Char(c) => {
-            app.new_ticket_description.push(c);
+            if let Some(confirmation) = app.confirmation.as_mut() {
+                confirmation.input.push(c);
+            }
         }
         _ => {}
     }
-    Ok(())
 }
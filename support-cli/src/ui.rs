@@ -1,65 +1,36 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
 use ratatui::{
     Frame,
-    layout::{Constraint, Direction, Layout, Rect},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap},
 };
+use std::time::{SystemTime, UNIX_EPOCH};
 use support_common::TicketState;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
-use crate::app::{App, View};
+use crate::app::{App, EntryKind, View};
 
-fn format_timestamp(ts: i64) -> String {
-    use std::time::{Duration, UNIX_EPOCH};
-    let datetime = UNIX_EPOCH + Duration::from_secs(ts as u64);
-    let secs = datetime
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
-
-    // Simple formatting (YYYY-MM-DD HH:MM)
-    let days_since_epoch = secs / 86400;
-    let remaining_secs = secs % 86400;
-    let hours = remaining_secs / 3600;
-    let minutes = (remaining_secs % 3600) / 60;
-
-    // Calculate year, month, day from days since epoch
-    let mut year = 1970;
-    let mut days = days_since_epoch;
-
-    loop {
-        let days_in_year = if is_leap_year(year) { 366 } else { 365 };
-        if days < days_in_year {
-            break;
-        }
-        days -= days_in_year;
-        year += 1;
-    }
-
-    let months_days: [u64; 12] = if is_leap_year(year) {
-        [31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31]
-    } else {
-        [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31]
-    };
+/// Default layout for timestamps, e.g. `2024-05-01 13:37`
+pub const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d %H:%M";
 
-    let mut month = 1;
-    for &d in &months_days {
-        if days < d {
-            break;
-        }
-        days -= d;
-        month += 1;
+/// Checks a strftime pattern up front so a typo fails at startup, not mid-render.
+pub fn validate_date_format(format: &str) -> anyhow::Result<()> {
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        anyhow::bail!("Ungültiges Datumsformat: {}", format);
     }
-    let day = days + 1;
-
-    format!(
-        "{:04}-{:02}-{:02} {:02}:{:02}",
-        year, month, day, hours, minutes
-    )
+    Ok(())
 }
 
-fn is_leap_year(year: u64) -> bool {
-    (year % 4 == 0 && year % 100 != 0) || (year % 400 == 0)
+/// Formats a Unix timestamp in the local timezone of the machine running the TUI.
+pub fn format_timestamp(ts: i64, format: &str) -> String {
+    match DateTime::from_timestamp(ts, 0) {
+        Some(datetime) => datetime.with_timezone(&Local).format(format).to_string(),
+        None => ts.to_string(),
+    }
 }
 
 fn state_color(state: &TicketState) -> Color {
@@ -67,32 +38,336 @@ fn state_color(state: &TicketState) -> Color {
         TicketState::New => Color::Yellow,
         TicketState::InProgress => Color::Cyan,
         TicketState::Done => Color::Green,
+        TicketState::Closed => Color::DarkGray,
+        TicketState::Reopened => Color::Magenta,
     }
 }
 
+/// Picks a chip color from the tag's name, so a tag looks the same on every ticket.
+fn tag_color(tag: &str) -> Color {
+    const COLORS: [Color; 6] = [
+        Color::Cyan,
+        Color::Green,
+        Color::Yellow,
+        Color::Magenta,
+        Color::LightBlue,
+        Color::LightRed,
+    ];
+    let hash = tag
+        .bytes()
+        .fold(0usize, |h, b| h.wrapping_mul(31).wrapping_add(b as usize));
+    COLORS[hash % COLORS.len()]
+}
+
 pub fn draw(frame: &mut Frame, app: &App) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([Constraint::Min(0), Constraint::Length(3)])
         .split(frame.area());
 
-    match app.view {
-        View::TicketList => draw_ticket_list(frame, app, chunks[0]),
-        View::TicketDetail => draw_ticket_detail(frame, app, chunks[0]),
-        View::ZipViewer => draw_zip_viewer(frame, app, chunks[0]),
-        View::FileContent => draw_file_content(frame, app, chunks[0]),
-        View::AddComment => draw_add_comment(frame, app, chunks[0]),
-        View::CreateTicket => draw_create_ticket(frame, app, chunks[0]),
+    draw_view(frame, app, &app.view, chunks[0]);
+    draw_status_bar(frame, app, chunks[1]);
+}
+
+fn draw_view(frame: &mut Frame, app: &App, view: &View, area: Rect) {
+    match view {
+        View::TicketList => draw_ticket_list(frame, app, area),
+        View::TicketDetail => draw_ticket_detail(frame, app, area),
+        View::ZipViewer => draw_zip_viewer(frame, app, area),
+        View::FileContent => draw_file_content(frame, app, area),
+        View::HexDump => draw_hex_view(frame, app, area),
+        View::AddComment => draw_add_comment(frame, app, area),
+        View::CreateTicket => draw_create_ticket(frame, app, area),
+        View::AttachFile => {
+            draw_create_ticket(frame, app, area);
+            draw_attach_file(frame, app, area);
+        }
+        View::AddTag => {
+            draw_ticket_detail(frame, app, area);
+            draw_add_tag(frame, app, area);
+        }
+        View::Confirm => {
+            // Keep the screen the prompt was opened from visible underneath
+            if let Some(confirmation) = &app.confirmation {
+                draw_view(frame, app, &confirmation.return_view, area);
+            }
+            draw_confirm(frame, app, area);
+        }
+        View::Help => {
+            if let Some(return_view) = &app.help_return_view {
+                draw_view(frame, app, return_view, area);
+                draw_help(frame, app, return_view, area);
+            }
+        }
     }
+}
 
-    draw_status_bar(frame, app, chunks[1]);
+/// Keys and what they do in `view`, for the help overlay.
+fn key_bindings(app: &App, view: &View) -> &'static [(&'static str, &'static str)] {
+    match view {
+        View::TicketList => &[
+            ("↑/↓, j/k", "Select ticket"),
+            ("PgUp/PgDn", "Previous/next page"),
+            ("Enter", "Open ticket"),
+            ("n", "New ticket"),
+            ("f", "Cycle status filter"),
+            ("1-5", "Only New/In Progress/Done/Closed/Reopened"),
+            ("0", "All statuses"),
+            ("u", "Only the selected ticket's customer"),
+            ("m", "Only tickets assigned to me"),
+            ("s", "Sort by creation or last activity"),
+            ("l", "Copy link"),
+            ("d", "Delete ticket"),
+            ("r", "Refresh"),
+            ("a", "Toggle auto-refresh"),
+            ("q", "Quit"),
+        ],
+        View::TicketDetail => &[
+            ("↑/↓, j/k", "Select comment, older ones load at the top"),
+            ("n/], p/[", "Next/previous ticket"),
+            ("z", "Open ZIP attachment"),
+            ("c", "Comment"),
+            ("i", "Internal note"),
+            ("e", "Edit selected comment"),
+            ("d", "Delete selected comment"),
+            ("t", "Add tag"),
+            ("1, 2, 5", "Set New, In Progress, Reopened"),
+            ("3, 4", "Set Done, Closed (asks first)"),
+            ("l", "Copy link"),
+            ("y", "Copy summary"),
+            ("Esc", "Back to list"),
+        ],
+        View::ZipViewer => &[
+            ("↑/↓, j/k", "Select entry"),
+            ("Enter", "Open file or folder"),
+            ("e", "Extract file"),
+            ("s", "Save ZIP"),
+            ("Esc", "Parent folder, or back to ticket"),
+        ],
+        View::FileContent => &[
+            ("↑/↓, PgUp/PgDn", "Scroll"),
+            ("/", "Search"),
+            ("n/N", "Next/previous match"),
+            ("x", "Hex view"),
+            ("e", "Extract file"),
+            ("Esc", "Back"),
+        ],
+        View::HexDump => &[
+            ("↑/↓, PgUp/PgDn", "Scroll"),
+            ("x, Esc", "Text view"),
+            ("e", "Extract file"),
+        ],
+        View::AddComment => &[
+            ("Enter", "New line"),
+            ("←/→", "Move cursor"),
+            ("Home/End", "Start/end of line"),
+            ("Ctrl-S", "Send"),
+            ("Esc", "Cancel"),
+        ],
+        View::CreateTicket => &[
+            ("Enter", "Create ticket"),
+            ("Ctrl-O", "Attach ZIP file"),
+            ("Ctrl-V", "Paste screenshot"),
+            ("Esc", "Cancel"),
+        ],
+        View::AttachFile => &[("Enter", "Attach file"), ("Esc", "Back")],
+        View::AddTag => &[("Enter", "Add tag"), ("Esc", "Cancel")],
+        View::Confirm
+            if app
+                .confirmation
+                .as_ref()
+                .is_some_and(|c| c.action.needs_typed_confirmation()) =>
+        {
+            &[
+                ("ja, yes", "Type to confirm, then Enter"),
+                ("Esc", "Cancel"),
+            ]
+        }
+        View::Confirm => &[("y", "Confirm"), ("n, Esc", "Cancel")],
+        View::Help => &[],
+    }
+}
+
+fn draw_help(frame: &mut Frame, app: &App, view: &View, area: Rect) {
+    let bindings = key_bindings(app, view);
+    let key_width = bindings
+        .iter()
+        .map(|(key, _)| key.width())
+        .max()
+        .unwrap_or(0);
+    let lines: Vec<Line> = bindings
+        .iter()
+        .map(|(key, action)| {
+            Line::from(vec![
+                Span::styled(
+                    format!(" {}{} ", key, " ".repeat(key_width - key.width())),
+                    Style::default().fg(Color::Yellow),
+                ),
+                Span::raw(*action),
+            ])
+        })
+        .collect();
+
+    let popup = centered_rect(64, lines.len() as u16 + 2, area);
+    let help = Paragraph::new(lines).block(
+        Block::default()
+            .title(" Keys - press any key to close ")
+            .borders(Borders::ALL),
+    );
+
+    frame.render_widget(Clear, popup);
+    frame.render_widget(help, popup);
+}
+
+/// Rect of the given size centered in `area`, clamped to fit.
+fn centered_rect(width: u16, height: u16, area: Rect) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+fn draw_confirm(frame: &mut Frame, app: &App, area: Rect) {
+    let Some(confirmation) = &app.confirmation else {
+        return;
+    };
+
+    let popup = centered_rect(60, 7, area);
+    let mut text = vec![Line::from(confirmation.message.as_str())];
+    if confirmation.action.needs_typed_confirmation() {
+        text.extend([
+            Line::from(Span::styled(
+                "Zum Bestätigen \"ja\" oder \"yes\" eingeben:",
+                Style::default().add_modifier(Modifier::DIM),
+            )),
+            Line::from(""),
+            Line::from(Span::styled(
+                format!("> {}", confirmation.input),
+                Style::default().fg(Color::Yellow),
+            )),
+        ]);
+    } else {
+        text.extend([
+            Line::from(""),
+            Line::from(Span::styled(
+                "[y] Ja   [n] Nein",
+                Style::default().fg(Color::Yellow),
+            )),
+        ]);
+    }
+    let prompt = Paragraph::new(text)
+        .block(
+            Block::default()
+                .title(" Bestätigung ")
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(Color::Red)),
+        )
+        .wrap(Wrap { trim: false });
+
+    frame.render_widget(Clear, popup);
+    frame.render_widget(prompt, popup);
+}
+
+fn draw_attach_file(frame: &mut Frame, app: &App, area: Rect) {
+    let popup = centered_rect(70, 5, area);
+    let text = vec![
+        Line::from(Span::styled(
+            "Pfad zur ZIP-Datei:",
+            Style::default().add_modifier(Modifier::DIM),
+        )),
+        Line::from(""),
+        Line::from(Span::styled(
+            format!("> {}", app.attach_path_input),
+            Style::default().fg(Color::Yellow),
+        )),
+    ];
+    let prompt = Paragraph::new(text)
+        .block(Block::default().title(" Attach ZIP ").borders(Borders::ALL))
+        .wrap(Wrap { trim: false });
+
+    frame.render_widget(Clear, popup);
+    frame.render_widget(prompt, popup);
+}
+
+fn draw_add_tag(frame: &mut Frame, app: &App, area: Rect) {
+    let popup = centered_rect(40, 5, area);
+    let text = vec![
+        Line::from(Span::styled(
+            "z.B. billing, crash, android",
+            Style::default().add_modifier(Modifier::DIM),
+        )),
+        Line::from(""),
+        Line::from(Span::styled(
+            format!("> {}", app.tag_input),
+            Style::default().fg(Color::Yellow),
+        )),
+    ];
+    let prompt = Paragraph::new(text)
+        .block(Block::default().title(" Add tag ").borders(Borders::ALL))
+        .wrap(Wrap { trim: false });
+
+    frame.render_widget(Clear, popup);
+    frame.render_widget(prompt, popup);
 }
 
 fn draw_ticket_list(frame: &mut Frame, app: &App, area: Rect) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(1), Constraint::Min(0)])
+        .split(area);
+    draw_filter_bar(frame, app, chunks[0]);
+    let area = chunks[1];
+
+    let mut title = if app.assigned_only {
+        " Support Tickets (assigned to me) ".to_string()
+    } else {
+        " Support Tickets ".to_string()
+    };
+    if app.ticket_total as usize > app.tickets.len() {
+        title.push_str(&format!(
+            "{}-{} von {} ",
+            app.ticket_offset + 1,
+            app.ticket_offset + app.tickets.len() as i64,
+            app.ticket_total
+        ));
+    }
+    if app.auto_refresh {
+        title.push_str(&format!(
+            "(auto-refresh {}s) ",
+            app.refresh_interval.as_secs()
+        ));
+    }
+
+    if app.tickets.is_empty() {
+        let hint = Paragraph::new(vec![
+            Line::from(""),
+            Line::from("Keine Tickets"),
+            Line::from(Span::styled(
+                "'n' für neues Ticket, 'r' zum Aktualisieren",
+                Style::default().add_modifier(Modifier::DIM),
+            )),
+        ])
+        .alignment(Alignment::Center)
+        .block(Block::default().title(title).borders(Borders::ALL));
+        frame.render_widget(hint, area);
+        return;
+    }
+
+    let by_activity = app.ticket_filter.by_activity;
     let items: Vec<ListItem> = app
         .tickets
         .iter()
         .map(|t| {
+            // The column shows whichever time the list is sorted by
+            let timestamp = if by_activity {
+                t.updated_at
+            } else {
+                t.created_at
+            };
             let state_span = Span::styled(
                 format!("[{}]", t.state),
                 Style::default().fg(state_color(&t.state)),
@@ -102,20 +377,24 @@ fn draw_ticket_list(frame: &mut Frame, app: &App, area: Rect) {
                 state_span,
                 Span::raw(format!(
                     " {} - {}",
-                    format_timestamp(t.created_at),
+                    format_timestamp(timestamp, &app.date_format),
                     t.description.lines().next().unwrap_or("")
                 )),
             ]);
-            ListItem::new(line)
+            if app.new_ticket_ids.contains(&t.id) {
+                ListItem::new(line).style(
+                    Style::default()
+                        .fg(Color::Green)
+                        .add_modifier(Modifier::BOLD),
+                )
+            } else {
+                ListItem::new(line)
+            }
         })
         .collect();
 
     let list = List::new(items)
-        .block(
-            Block::default()
-                .title(" Support Tickets ")
-                .borders(Borders::ALL),
-        )
+        .block(Block::default().title(title).borders(Borders::ALL))
         .highlight_style(
             Style::default()
                 .bg(Color::DarkGray)
@@ -128,6 +407,53 @@ fn draw_ticket_list(frame: &mut Frame, app: &App, area: Rect) {
     frame.render_stateful_widget(list, area, &mut state);
 }
 
+fn draw_filter_bar(frame: &mut Frame, app: &App, area: Rect) {
+    let filter = &app.ticket_filter;
+    let active = Style::default().fg(Color::Yellow);
+    let state = match filter.state {
+        Some(state) => Span::styled(state.to_string(), active),
+        None => Span::raw("Alle"),
+    };
+    let user = match filter.user_id {
+        Some(id) => Span::styled(format!("User {}", id), active),
+        None => Span::raw("Alle"),
+    };
+    let assigned = if app.assigned_only {
+        Span::styled("Mir", active)
+    } else {
+        Span::raw("Alle")
+    };
+    let sort = if filter.by_activity {
+        Span::styled("Letzte Aktivität", active)
+    } else {
+        Span::raw("Erstellt")
+    };
+
+    let bar = Paragraph::new(Line::from(vec![
+        Span::styled(
+            " Status (f, 1-5, 0): ",
+            Style::default().add_modifier(Modifier::DIM),
+        ),
+        state,
+        Span::styled(
+            "  Kunde (u): ",
+            Style::default().add_modifier(Modifier::DIM),
+        ),
+        user,
+        Span::styled(
+            "  Zugewiesen (m): ",
+            Style::default().add_modifier(Modifier::DIM),
+        ),
+        assigned,
+        Span::styled(
+            "  Sortierung (s): ",
+            Style::default().add_modifier(Modifier::DIM),
+        ),
+        sort,
+    ]));
+    frame.render_widget(bar, area);
+}
+
 fn draw_ticket_detail(frame: &mut Frame, app: &App, area: Rect) {
     let Some(detail) = &app.current_ticket else {
         return;
@@ -139,19 +465,43 @@ fn draw_ticket_detail(frame: &mut Frame, app: &App, area: Rect) {
         .split(area);
 
     // Header info
+    let mut title_spans = vec![
+        Span::styled("Ticket #", Style::default().add_modifier(Modifier::BOLD)),
+        Span::raw(format!("{}", detail.ticket.id)),
+        Span::styled(
+            detail
+                .ticket
+                .reference
+                .as_ref()
+                .map(|r| format!(" ({})", r))
+                .unwrap_or_default(),
+            Style::default().add_modifier(Modifier::DIM),
+        ),
+        Span::raw("  "),
+        Span::styled(
+            format!("[{}]", detail.ticket.state),
+            Style::default().fg(state_color(&detail.ticket.state)),
+        ),
+        Span::raw(format!("  Priorität: {}", detail.ticket.priority)),
+    ];
+    for tag in &detail.tags {
+        title_spans.push(Span::raw(" "));
+        title_spans.push(Span::styled(
+            format!(" {} ", tag),
+            Style::default().fg(Color::Black).bg(tag_color(tag)),
+        ));
+    }
+
     let header_text = vec![
+        Line::from(title_spans),
         Line::from(vec![
-            Span::styled("Ticket #", Style::default().add_modifier(Modifier::BOLD)),
-            Span::raw(format!("{}", detail.ticket.id)),
-            Span::raw("  "),
+            Span::styled("Erstellt: ", Style::default().add_modifier(Modifier::DIM)),
+            Span::raw(format_timestamp(detail.ticket.created_at, &app.date_format)),
             Span::styled(
-                format!("[{}]", detail.ticket.state),
-                Style::default().fg(state_color(&detail.ticket.state)),
+                "  Letzte Aktivität: ",
+                Style::default().add_modifier(Modifier::DIM),
             ),
-        ]),
-        Line::from(vec![
-            Span::styled("Erstellt: ", Style::default().add_modifier(Modifier::DIM)),
-            Span::raw(format_timestamp(detail.ticket.created_at)),
+            Span::raw(format_timestamp(detail.ticket.updated_at, &app.date_format)),
             Span::raw(format!("  (User: {})", detail.ticket.user_id)),
         ]),
         Line::from(vec![
@@ -172,22 +522,60 @@ fn draw_ticket_detail(frame: &mut Frame, app: &App, area: Rect) {
         .comments
         .iter()
         .map(|c| {
-            let header_line = Line::from(vec![
+            let mut header_spans = vec![
                 Span::styled(
                     format!("User {} - ", c.user_id),
                     Style::default().add_modifier(Modifier::DIM),
                 ),
                 Span::styled(
-                    format_timestamp(c.created_at),
+                    format_timestamp(c.created_at, &app.date_format),
                     Style::default().add_modifier(Modifier::DIM),
                 ),
-            ]);
-            let text_line = Line::from(c.text.as_str());
-            ListItem::new(vec![header_line, text_line, Line::from("")])
+                Span::styled(
+                    if c.edited_at.is_some() {
+                        " (bearbeitet)"
+                    } else {
+                        ""
+                    },
+                    Style::default().add_modifier(Modifier::DIM),
+                ),
+                Span::styled(
+                    c.attachment
+                        .as_ref()
+                        .map(|name| format!("  [Anhang: {}]", name))
+                        .unwrap_or_default(),
+                    Style::default().fg(Color::Cyan),
+                ),
+            ];
+            // Internal notes stand out so they are not mistaken for replies the customer saw
+            let text_style = if c.internal {
+                header_spans.insert(
+                    0,
+                    Span::styled(
+                        "[Intern] ",
+                        Style::default()
+                            .fg(Color::Magenta)
+                            .add_modifier(Modifier::BOLD),
+                    ),
+                );
+                Style::default().fg(Color::Magenta)
+            } else {
+                Style::default()
+            };
+            let mut lines = vec![Line::from(header_spans)];
+            lines.extend(c.text.lines().map(|line| Line::styled(line, text_style)));
+            lines.push(Line::from(""));
+            ListItem::new(lines)
         })
         .collect();
 
-    let comments_title = format!(" Kommentare ({}) ", detail.comments.len());
+    let comments_title = if app.loading_older_comments {
+        " Kommentare - loading older comments… ".to_string()
+    } else if detail.has_more_comments {
+        format!(" Kommentare ({}, ↑ for older) ", detail.comments.len())
+    } else {
+        format!(" Kommentare ({}) ", detail.comments.len())
+    };
     let comments = List::new(comment_items)
         .block(Block::default().title(comments_title).borders(Borders::ALL));
 
@@ -198,22 +586,66 @@ fn draw_ticket_detail(frame: &mut Frame, app: &App, area: Rect) {
     frame.render_stateful_widget(comments, chunks[1], &mut state);
 }
 
+/// Cuts `text` to at most `max_width` terminal columns, ending in `…` if shortened.
+/// Works on grapheme clusters so emoji and CJK characters are never split.
+fn truncate_to_width(text: &str, max_width: usize) -> String {
+    if text.width() <= max_width {
+        return text.to_string();
+    }
+    let mut result = String::new();
+    let mut width = 0;
+    for grapheme in text.graphemes(true) {
+        let w = grapheme.width();
+        if width + w + 1 > max_width {
+            break;
+        }
+        result.push_str(grapheme);
+        width += w;
+    }
+    result.push('…');
+    result
+}
+
+/// Truncates or right-pads `text` to exactly `width` terminal columns.
+fn fit_to_width(text: &str, width: usize) -> String {
+    let mut fitted = truncate_to_width(text, width);
+    let padding = width.saturating_sub(fitted.width());
+    fitted.push_str(&" ".repeat(padding));
+    fitted
+}
+
 fn draw_zip_viewer(frame: &mut Frame, app: &App, area: Rect) {
+    // Borders, highlight symbol, icon + space, gap and a fixed size column
+    const SIZE_COLUMN: usize = 12;
+    let name_width = (area.width as usize).saturating_sub(2 + 2 + 3 + 1 + SIZE_COLUMN);
+
     let items: Vec<ListItem> = app
         .zip_entries
         .iter()
         .map(|e| {
-            let icon = if e.is_dir { "📁" } else { "📄" };
+            let icon = match e.kind {
+                None => "📁",
+                Some(EntryKind::Text) => "📄",
+                Some(EntryKind::Image) => "📷",
+                Some(EntryKind::Binary) => "💾",
+            };
             let size_str = if e.is_dir {
                 String::new()
             } else {
                 format_size(e.size)
             };
-            ListItem::new(Line::from(format!("{} {} {}", icon, e.name, size_str)))
+            ListItem::new(Line::from(format!(
+                "{} {} {:>width$}",
+                icon,
+                fit_to_width(&e.name, name_width),
+                size_str,
+                width = SIZE_COLUMN
+            )))
         })
         .collect();
 
-    let title = format!(" ZIP Inhalt ({} Einträge) ", app.zip_entries.len());
+    let count = app.zip_entries.iter().filter(|e| e.name != "..").count();
+    let title = format!(" ZIP Inhalt /{} ({} Einträge) ", app.zip_cwd, count);
     let list = List::new(items)
         .block(Block::default().title(title).borders(Borders::ALL))
         .highlight_style(
@@ -229,13 +661,24 @@ fn draw_zip_viewer(frame: &mut Frame, app: &App, area: Rect) {
 }
 
 fn draw_file_content(frame: &mut Frame, app: &App, area: Rect) {
-    let content = app.file_content.as_deref().unwrap_or("");
-    let lines: Vec<Line> = content
-        .lines()
-        .skip(app.content_scroll)
-        .take(area.height.saturating_sub(2) as usize)
-        .map(Line::from)
-        .collect();
+    let height = area.height.saturating_sub(2) as usize;
+    let lines: Vec<Line> = match &app.file_lines {
+        Some(highlighted) => highlighted
+            .iter()
+            .skip(app.content_scroll)
+            .take(height)
+            .map(|line| highlight_matches(line.clone(), &app.content_search))
+            .collect(),
+        None => app
+            .file_content
+            .as_deref()
+            .unwrap_or("")
+            .lines()
+            .skip(app.content_scroll)
+            .take(height)
+            .map(|line| highlight_matches(Line::from(line), &app.content_search))
+            .collect(),
+    };
 
     let title = format!(" {} ", app.file_name);
     let paragraph = Paragraph::new(lines)
@@ -244,20 +687,134 @@ fn draw_file_content(frame: &mut Frame, app: &App, area: Rect) {
     frame.render_widget(paragraph, area);
 }
 
+/// Bytes per hexdump row
+const HEX_ROW_BYTES: usize = 16;
+
+fn draw_hex_view(frame: &mut Frame, app: &App, area: Rect) {
+    let lines: Vec<Line> = app
+        .file_bytes
+        .chunks(HEX_ROW_BYTES)
+        .enumerate()
+        .skip(app.content_scroll)
+        .take(area.height.saturating_sub(2) as usize)
+        .map(|(row, bytes)| hexdump_line(row * HEX_ROW_BYTES, bytes))
+        .collect();
+
+    let title = format!(" {} (hex, {} Bytes) ", app.file_name, app.file_bytes.len());
+    let paragraph =
+        Paragraph::new(lines).block(Block::default().title(title).borders(Borders::ALL));
+    frame.render_widget(paragraph, area);
+}
+
+/// `00000010  48 65 6c 6c 6f 20 57 6f  72 6c 64 0a 00 00 00 00  |Hello World.....|`
+fn hexdump_line(offset: usize, bytes: &[u8]) -> Line<'static> {
+    let mut hex = String::with_capacity(HEX_ROW_BYTES * 3 + 1);
+    for i in 0..HEX_ROW_BYTES {
+        if i == HEX_ROW_BYTES / 2 {
+            hex.push(' ');
+        }
+        match bytes.get(i) {
+            Some(b) => hex.push_str(&format!("{:02x} ", b)),
+            None => hex.push_str("   "),
+        }
+    }
+    let ascii: String = bytes
+        .iter()
+        .map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        })
+        .collect();
+
+    Line::from(vec![
+        Span::styled(
+            format!("{:08x}  ", offset),
+            Style::default().fg(Color::DarkGray),
+        ),
+        Span::raw(hex),
+        Span::styled(format!(" |{}|", ascii), Style::default().fg(Color::Cyan)),
+    ])
+}
+
+/// Restyles every occurrence of `query` in `line`, splitting spans where a
+/// match starts or ends so syntax colors around it are kept
+fn highlight_matches<'a>(line: Line<'a>, query: &str) -> Line<'a> {
+    if query.is_empty() {
+        return line;
+    }
+    let text: String = line.spans.iter().map(|s| s.content.as_ref()).collect();
+    let matches: Vec<(usize, usize)> = text
+        .match_indices(query)
+        .map(|(start, m)| (start, start + m.len()))
+        .collect();
+    if matches.is_empty() {
+        return line;
+    }
+
+    let match_style = Style::default().fg(Color::Black).bg(Color::Yellow);
+    let mut spans = Vec::new();
+    let mut span_start = 0;
+    for span in &line.spans {
+        let span_end = span_start + span.content.len();
+        let mut pos = span_start;
+        for &(start, end) in &matches {
+            if end <= pos || start >= span_end {
+                continue;
+            }
+            let (start, end) = (start.max(pos), end.min(span_end));
+            if start > pos {
+                let before = &span.content[pos - span_start..start - span_start];
+                spans.push(Span::styled(before.to_string(), span.style));
+            }
+            let matched = &span.content[start - span_start..end - span_start];
+            spans.push(Span::styled(matched.to_string(), match_style));
+            pos = end;
+        }
+        if pos < span_end {
+            let rest = &span.content[pos - span_start..];
+            spans.push(Span::styled(rest.to_string(), span.style));
+        }
+        span_start = span_end;
+    }
+    Line::from(spans).style(line.style)
+}
+
 fn draw_add_comment(frame: &mut Frame, app: &App, area: Rect) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Length(3), Constraint::Min(0)])
+        .constraints([Constraint::Min(5), Constraint::Length(3)])
         .split(area);
 
-    let input = Paragraph::new(app.comment_input.as_str())
-        .block(
-            Block::default()
-                .title(" Enter comment (Enter = Send, Esc = Cancel) ")
-                .borders(Borders::ALL),
+    let (title, color) = if app.editing_comment.is_some() {
+        (
+            " Edit comment (Ctrl-S = Save, Esc = Cancel) ",
+            Color::Yellow,
+        )
+    } else if app.comment_internal {
+        (
+            " Enter internal note, not visible to the customer (Ctrl-S = Send, Esc = Cancel) ",
+            Color::Magenta,
+        )
+    } else {
+        (
+            " Enter comment (Ctrl-S = Send, Esc = Cancel) ",
+            Color::Yellow,
         )
-        .style(Style::default().fg(Color::Yellow));
+    };
+    let block = Block::default().title(title).borders(Borders::ALL);
+    let inner = block.inner(chunks[0]);
+    let (rows, (column, row)) = app.comment_input.wrap(inner.width as usize);
+    // Scroll just far enough to keep the cursor row in view
+    let scroll = row.saturating_sub(inner.height.saturating_sub(1) as usize);
+    let lines: Vec<Line> = rows.into_iter().skip(scroll).map(Line::from).collect();
+    let input = Paragraph::new(lines)
+        .block(block)
+        .style(Style::default().fg(color));
     frame.render_widget(input, chunks[0]);
+    frame.set_cursor_position((inner.x + column as u16, inner.y + (row - scroll) as u16));
 
     // Show ticket context
     if let Some(detail) = &app.current_ticket {
@@ -287,8 +844,21 @@ fn draw_create_ticket(frame: &mut Frame, app: &App, area: Rect) {
         .wrap(Wrap { trim: false });
     frame.render_widget(input, chunks[0]);
 
+    let attachment = match (&app.new_ticket_zip, &app.new_ticket_screenshot) {
+        (Some(zip), _) => format!(
+            "Attachment: {} {}",
+            zip.file_name,
+            format_size(zip.data.len() as u64)
+        ),
+        (None, Some(png)) => format!(
+            "Attachment: screenshot.png {}",
+            format_size(png.len() as u64)
+        ),
+        (None, None) => "A minimal ZIP file will be created automatically.".to_string(),
+    };
     let help_text = vec![
-        Line::from("A minimal ZIP file will be created automatically."),
+        Line::from(attachment),
+        Line::from("Press Ctrl-O to attach a ZIP file, or Ctrl-V for an image from the clipboard."),
         Line::from("Use this to report bugs from beta testers or for testing."),
     ];
     let help = Paragraph::new(help_text)
@@ -297,21 +867,63 @@ fn draw_create_ticket(frame: &mut Frame, app: &App, area: Rect) {
     frame.render_widget(help, chunks[1]);
 }
 
+const SPINNER_FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// Spinner frame for the current time, advancing every 100ms.
+fn spinner_frame() -> char {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    SPINNER_FRAMES[(millis / 100) as usize % SPINNER_FRAMES.len()]
+}
+
 fn draw_status_bar(frame: &mut Frame, app: &App, area: Rect) {
     let help_text = match app.view {
-        View::TicketList => "↑/↓: Select | Enter: Details | n: New ticket | r: Refresh | q: Quit",
-        View::TicketDetail => "↑/↓: Scroll | z: Open ZIP | c: Comment | 1/2/3: Status | Esc: Back",
-        View::ZipViewer => "↑/↓: Select | Enter: Open | Esc: Back",
-        View::FileContent => "↑/↓: Scroll | Esc: Back",
-        View::AddComment => "Enter: Send | Esc: Cancel",
-        View::CreateTicket => "Enter: Create | Esc: Cancel",
+        View::TicketList => {
+            "↑/↓: Select | PgUp/PgDn: Page | Enter: Details | n: New ticket | f/1-5/u: Filter | s: Sort | l: Copy link | d: Delete | r: Refresh | a: Auto-refresh | ?: Help | q: Quit"
+        }
+        View::TicketDetail => {
+            "↑/↓: Scroll | n/p: Next/Prev | z: Open ZIP | c: Comment | i: Internal note | e/d: Edit/Delete comment | t: Tag | 1-5: Status | l/y: Copy link/summary | ?: Help | Esc: Back"
+        }
+        View::ZipViewer => "↑/↓: Select | Enter: Open | e: Extract file | s: Save ZIP | Esc: Back",
+        View::FileContent if app.content_search_active => "Enter: Search | Esc: Cancel",
+        View::FileContent => {
+            "↑/↓: Scroll | /: Search | n/N: Next/Prev match | x: Hex view | e: Extract file | Esc: Back"
+        }
+        View::HexDump => {
+            "↑/↓: Scroll | PgUp/PgDn: Page | x: Text view | e: Extract file | Esc: Back"
+        }
+        View::AddComment if app.editing_comment.is_some() => {
+            "Ctrl-S: Save | Enter: New line | ←/→: Move cursor | Esc: Cancel"
+        }
+        View::AddComment => "Ctrl-S: Send | Enter: New line | ←/→: Move cursor | Esc: Cancel",
+        View::CreateTicket => {
+            "Enter: Create | Ctrl-O: Attach ZIP | Ctrl-V: Paste screenshot | Esc: Cancel"
+        }
+        View::AttachFile => "Enter: Attach | Esc: Back",
+        View::AddTag => "Enter: Add tag | Esc: Cancel",
+        View::Confirm
+            if app
+                .confirmation
+                .as_ref()
+                .is_some_and(|c| !c.action.needs_typed_confirmation()) =>
+        {
+            "y: Confirm | n/Esc: Cancel"
+        }
+        View::Confirm => "Enter: Confirm | Esc: Cancel",
+        View::Help => "Any key: Close",
     };
 
-    let status = if let Some(msg) = &app.status_message {
+    let mut status = if app.view == View::FileContent && app.content_search_active {
+        format!("/{} | {}", app.content_search, help_text)
+    } else if let Some(msg) = &app.status_message {
         format!("{} | {}", msg, help_text)
     } else {
         help_text.to_string()
     };
+    if app.loading {
+        status = format!("{} Loading… | {}", spinner_frame(), status);
+    }
 
     let bar = Paragraph::new(status)
         .block(Block::default().borders(Borders::ALL))
@@ -319,7 +931,7 @@ fn draw_status_bar(frame: &mut Frame, app: &App, area: Rect) {
     frame.render_widget(bar, area);
 }
 
-fn format_size(bytes: u64) -> String {
+pub fn format_size(bytes: u64) -> String {
     const KB: u64 = 1024;
     const MB: u64 = KB * 1024;
 
@@ -331,3 +943,106 @@ fn format_size(bytes: u64) -> String {
         format!("({} B)", bytes)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDateTime, Offset, TimeZone};
+
+    #[test]
+    fn format_timestamp_matches_the_old_utc_output_shifted_to_local_time() {
+        // Output of the former hand-rolled UTC formatter for these timestamps
+        let cases = [
+            (0, "1970-01-01 00:00"),
+            (951782400, "2000-02-29 00:00"),
+            (1709251199, "2024-02-29 23:59"),
+            (1735689599, "2024-12-31 23:59"),
+            (4107542400, "2100-03-01 00:00"),
+        ];
+        for (ts, old) in cases {
+            let utc = NaiveDateTime::parse_from_str(old, DEFAULT_DATE_FORMAT).unwrap();
+            let local = NaiveDateTime::parse_from_str(
+                &format_timestamp(ts, DEFAULT_DATE_FORMAT),
+                DEFAULT_DATE_FORMAT,
+            )
+            .unwrap();
+            let offset = Local.offset_from_utc_datetime(&utc).fix().local_minus_utc();
+            assert_eq!((local - utc).num_seconds(), offset as i64, "at {}", ts);
+        }
+    }
+
+    #[test]
+    fn format_timestamp_follows_the_configured_pattern() {
+        const GERMAN: &str = "%d.%m.%Y %H:%M";
+        for ts in [0, 951782400, 1709251199] {
+            let iso = format_timestamp(ts, DEFAULT_DATE_FORMAT);
+            let german = format_timestamp(ts, GERMAN);
+            assert_ne!(iso, german);
+
+            // Same local time, only written differently
+            let (date, time) = iso.split_once(' ').unwrap();
+            let [year, month, day] = date.split('-').collect::<Vec<_>>()[..] else {
+                panic!("unexpected date {}", iso);
+            };
+            assert_eq!(german, format!("{}.{}.{} {}", day, month, year, time));
+            assert_eq!(
+                NaiveDateTime::parse_from_str(&iso, DEFAULT_DATE_FORMAT).unwrap(),
+                NaiveDateTime::parse_from_str(&german, GERMAN).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn format_timestamp_falls_back_to_the_raw_value() {
+        assert_eq!(
+            format_timestamp(i64::MAX, DEFAULT_DATE_FORMAT),
+            i64::MAX.to_string()
+        );
+    }
+
+    #[test]
+    fn truncates_wide_characters_without_splitting_them() {
+        assert_eq!(truncate_to_width("日本語テキスト", 5), "日本…");
+        assert_eq!(truncate_to_width("日本語テキスト", 6), "日本…");
+        assert_eq!(truncate_to_width("🎉🎉🎉", 4), "🎉…");
+        assert_eq!(truncate_to_width("🎉🎉🎉", 6), "🎉🎉🎉");
+        assert_eq!(truncate_to_width("abc", 3), "abc");
+    }
+
+    #[test]
+    fn fits_wide_characters_to_exact_width() {
+        for text in ["日本語テキスト", "🎉🎉🎉", "abc", ""] {
+            for width in 1..10 {
+                let fitted = fit_to_width(text, width);
+                assert_eq!(fitted.width(), width, "{:?} at {}", text, width);
+            }
+        }
+        assert_eq!(fit_to_width("日本語テキスト", 6), "日本… ");
+        assert_eq!(fit_to_width("🎉", 4), "🎉  ");
+    }
+
+    fn buffer_text(buffer: &ratatui::buffer::Buffer) -> String {
+        buffer
+            .content()
+            .chunks(buffer.area.width as usize)
+            .map(|row| row.iter().map(|cell| cell.symbol()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn empty_ticket_list_shows_the_hint() {
+        let app = App::new(crate::api::ApiClient::new(
+            "http://127.0.0.1:9".to_string(),
+            "test".to_string(),
+        ));
+        let mut terminal =
+            ratatui::Terminal::new(ratatui::backend::TestBackend::new(80, 20)).unwrap();
+
+        terminal.draw(|frame| draw(frame, &app)).unwrap();
+
+        let text = buffer_text(terminal.backend().buffer());
+        assert!(text.contains("Keine Tickets"), "{}", text);
+        assert!(text.contains("Support Tickets"));
+    }
+}
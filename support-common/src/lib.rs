@@ -1,30 +1,61 @@
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "snake_case")]
 pub enum TicketState {
     New,
     InProgress,
     Done,
+    Closed,
+    Reopened,
 }
 
 impl TicketState {
+    pub const ALL: [TicketState; 5] = [
+        TicketState::New,
+        TicketState::InProgress,
+        TicketState::Done,
+        TicketState::Closed,
+        TicketState::Reopened,
+    ];
+
     pub fn as_str(&self) -> &'static str {
         match self {
             TicketState::New => "new",
             TicketState::InProgress => "in_progress",
             TicketState::Done => "done",
+            TicketState::Closed => "closed",
+            TicketState::Reopened => "reopened",
         }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
         match s {
             "new" => Some(TicketState::New),
             "in_progress" => Some(TicketState::InProgress),
             "done" => Some(TicketState::Done),
+            "closed" => Some(TicketState::Closed),
+            "reopened" => Some(TicketState::Reopened),
             _ => None,
         }
     }
+
+    /// Whether an agent may move a ticket from this state to `next`. Tickets
+    /// are only closed once done, and closed tickets can only be reopened.
+    pub fn can_transition_to(&self, next: TicketState) -> bool {
+        use TicketState::*;
+        *self == next
+            || matches!(
+                (self, next),
+                (New, InProgress | Done)
+                    | (InProgress, New | Done)
+                    | (Done, InProgress | Closed | Reopened)
+                    | (Closed, Reopened)
+                    | (Reopened, InProgress | Done)
+            )
+    }
 }
 
 impl std::fmt::Display for TicketState {
@@ -33,18 +64,145 @@ impl std::fmt::Display for TicketState {
             TicketState::New => write!(f, "Neu"),
             TicketState::InProgress => write!(f, "In Bearbeitung"),
             TicketState::Done => write!(f, "Erledigt"),
+            TicketState::Closed => write!(f, "Geschlossen"),
+            TicketState::Reopened => write!(f, "Wiedereröffnet"),
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Urgent,
+}
+
+impl Priority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+            Priority::Urgent => "urgent",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "low" => Some(Priority::Low),
+            "normal" => Some(Priority::Normal),
+            "high" => Some(Priority::High),
+            "urgent" => Some(Priority::Urgent),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Priority::Low => write!(f, "Niedrig"),
+            Priority::Normal => write!(f, "Normal"),
+            Priority::High => write!(f, "Hoch"),
+            Priority::Urgent => write!(f, "Dringend"),
+        }
+    }
+}
+
+/// A user's role, sent by the identity server as a lowercase string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    Support,
+    Customer,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Support => "support",
+            Role::Customer => "customer",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "admin" => Some(Role::Admin),
+            "support" => Some(Role::Support),
+            "customer" => Some(Role::Customer),
+            _ => None,
+        }
+    }
+}
+
+/// Grants everything the key owner's role allows. Keys created without
+/// explicit scopes, including all keys from before scopes existed, have it.
+pub const FULL_ACCESS_SCOPE: &str = "*";
+
+/// Reading tickets; a key with only this scope is read-only
+pub const SCOPE_TICKETS_READ: &str = "tickets:read";
+/// Opening tickets and commenting on one's own
+pub const SCOPE_TICKETS_WRITE: &str = "tickets:write";
+/// Changing any ticket through the `/admin` routes
+pub const SCOPE_TICKETS_ADMIN: &str = "tickets:admin";
+
+/// Scopes an API key can be restricted to, stored comma-separated with the key.
+pub const SCOPES: &[&str] = &[
+    FULL_ACCESS_SCOPE,
+    SCOPE_TICKETS_READ,
+    SCOPE_TICKETS_WRITE,
+    SCOPE_TICKETS_ADMIN,
+];
+
+/// Splits a stored scope list; a key without any gets full access.
+pub fn parse_scopes(scopes: &str) -> Vec<String> {
+    let scopes: Vec<String> = scopes
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect();
+    if scopes.is_empty() {
+        full_access()
+    } else {
+        scopes
+    }
+}
+
+/// Serde default for scope lists missing from older identity servers.
+pub fn full_access() -> Vec<String> {
+    vec![FULL_ACCESS_SCOPE.to_string()]
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct Ticket {
     pub id: i64,
     pub user_id: i64,
     pub created_at: i64,
+    /// Last state change or comment, `created_at` until then
+    #[serde(default)]
+    pub updated_at: i64,
     pub description: String,
     pub zip_filename: String,
     pub state: TicketState,
+    #[serde(default)]
+    pub priority: Priority,
+    /// Customer-facing reference such as `CD-000042`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    /// Agent the ticket is assigned to. Servers without assignment never send
+    /// it, so clients have to cope with it missing on every ticket.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assignee_id: Option<i64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -56,30 +214,168 @@ pub struct TicketWithZip {
     pub zip_filename: String,
     pub zip_data: Vec<u8>,
     pub state: TicketState,
+    #[serde(default)]
+    pub priority: Priority,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct Comment {
     pub id: i64,
     pub ticket_id: i64,
     pub user_id: i64,
     pub created_at: i64,
     pub text: String,
+    /// File name of the attachment, if the comment has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachment: Option<String>,
+    /// Internal notes are only visible to admins
+    #[serde(default)]
+    pub internal: bool,
+    /// When the text was last changed, if ever
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edited_at: Option<i64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct TicketDetail {
     #[serde(flatten)]
     pub ticket: Ticket,
     pub comments: Vec<Comment>,
+    /// Set when only the latest comments were requested and older ones exist
+    #[serde(default)]
+    pub has_more_comments: bool,
+    /// Agents' labels such as `billing`, sorted; only in the admin view
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+/// One page of the admin ticket list, newest first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PaginatedTickets {
+    pub tickets: Vec<Ticket>,
+    /// Number of tickets across all pages
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// A chronologically ordered slice of a ticket's comments.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CommentPage {
+    pub comments: Vec<Comment>,
+    /// Whether comments older than the first one in this page exist
+    pub has_more: bool,
+}
+
+/// JSON alternative to the multipart ticket upload, for scripts and bots.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CreateTicketRequest {
+    pub description: String,
+    /// Base64-encoded ZIP; an empty archive is stored if omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zip_base64: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zip_filename: Option<String>,
+    #[serde(default)]
+    pub priority: Priority,
+}
+
+/// Body of every support-server error response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ErrorResponse {
+    pub error: String,
+    /// The `X-Request-Id` of the failed request, to quote when reporting it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct CreateCommentRequest {
     pub text: String,
+    /// Post as an internal note; ignored for customer comments
+    #[serde(default)]
+    pub internal: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UpdateCommentRequest {
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct UpdateStateRequest {
     pub state: TicketState,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AddTagRequest {
+    /// Letters, digits, `-` or `_`, at most 32; stored lowercase
+    pub tag: String,
+}
+
+/// Pushed to `/admin/ws` subscribers when tickets change, e.g.
+/// `{"type": "state_changed", "ticket_id": 42, "state": "done"}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TicketEvent {
+    TicketCreated {
+        ticket_id: i64,
+        user_id: i64,
+    },
+    StateChanged {
+        ticket_id: i64,
+        state: TicketState,
+    },
+    CommentAdded {
+        ticket_id: i64,
+        comment_id: i64,
+        internal: bool,
+    },
+    TicketDeleted {
+        ticket_id: i64,
+    },
+}
+
+/// Claims of the short-lived JWT issued by the identity server's `/token` endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenClaims {
+    /// User id
+    pub sub: i64,
+    pub email: String,
+    pub role: String,
+    pub subscription_status: String,
+    /// Scopes of the API key the token was issued for
+    #[serde(default = "full_access")]
+    pub scopes: Vec<String>,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+/// Body of `GET /health` on both servers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct HealthStatus {
+    /// `ok`, or `error` if a dependency is down
+    pub status: String,
+    /// Whether a trivial query against the database succeeds
+    pub db: bool,
+}
+
+impl HealthStatus {
+    pub fn from_db_check(db: bool) -> Self {
+        Self {
+            status: if db { "ok" } else { "error" }.to_string(),
+            db,
+        }
+    }
+}
@@ -1,26 +1,103 @@
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
 use axum::{
     extract::FromRequestParts,
-    http::{StatusCode, request::Parts},
+    http::{header, request::Parts},
 };
+use jsonwebtoken::{DecodingKey, Validation};
+use metrics_exporter_prometheus::PrometheusHandle;
 use serde::{Deserialize, Serialize};
+use support_common::{
+    FULL_ACCESS_SCOPE, Role, SCOPE_TICKETS_READ, TicketEvent, TokenClaims, full_access,
+};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
 
 use crate::db::DbPool;
+use crate::error::ApiError;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Why a key could not be checked. Both become a 503, but a timeout points at
+/// a hanging identity service rather than one that is down or misbehaving.
+#[derive(Debug)]
+pub enum IdentityError {
+    Timeout,
+    Unavailable(String),
+}
+
+impl std::fmt::Display for IdentityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdentityError::Timeout => write!(f, "timed out"),
+            IdentityError::Unavailable(detail) => write!(f, "{}", detail),
+        }
+    }
+}
+
+impl From<reqwest::Error> for IdentityError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            IdentityError::Timeout
+        } else {
+            IdentityError::Unavailable(e.to_string())
+        }
+    }
+}
+
+/// Resolves API keys to users. Implemented by the HTTP client for the identity
+/// service and by a static stub for development and tests.
+pub trait IdentityProvider: Send + Sync {
+    /// `Ok(None)` for an invalid key, `Err` if the provider itself is unreachable.
+    fn validate<'a>(
+        &'a self,
+        api_key: &'a str,
+    ) -> BoxFuture<'a, Result<Option<UserInfo>, IdentityError>>;
+
+    /// Emails the user that support replied to `ticket`. A no-op when
+    /// notifications are not configured.
+    fn notify_ticket_reply<'a>(
+        &'a self,
+        user_id: i64,
+        ticket: &'a str,
+    ) -> BoxFuture<'a, Result<(), String>>;
+}
 
 #[derive(Clone)]
 pub struct IdentityClient {
     client: reqwest::Client,
     base_url: String,
+    /// Admin key for `/notify/*`; without one no notifications are sent
+    notify_key: Option<String>,
 }
 
 impl IdentityClient {
-    pub fn new(base_url: String) -> Self {
-        Self {
-            client: reqwest::Client::new(),
-            base_url,
-        }
+    /// Validates the base URL up front so a typo fails at startup rather than
+    /// on every request. `timeout` bounds both connecting and the whole call,
+    /// so a hanging identity service can't stall every authenticated request.
+    pub fn new(base_url: &str, timeout: Duration) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(timeout)
+            .timeout(timeout)
+            .build()
+            .map_err(|e| format!("Failed to build HTTP client: {}", e))?;
+        Ok(Self {
+            client,
+            base_url: normalize_base_url(base_url)?,
+            notify_key: None,
+        })
     }
 
-    pub async fn validate(&self, api_key: &str) -> Result<Option<UserInfo>, reqwest::Error> {
+    pub fn with_notify_key(mut self, notify_key: Option<String>) -> Self {
+        self.notify_key = notify_key;
+        self
+    }
+
+    async fn validate_key(&self, api_key: &str) -> Result<Option<UserInfo>, reqwest::Error> {
         let response = self
             .client
             .post(format!("{}/validate", self.base_url))
@@ -38,6 +115,153 @@ impl IdentityClient {
             Ok(None)
         }
     }
+
+    async fn send_ticket_reply(&self, user_id: i64, ticket: &str) -> Result<(), String> {
+        let Some(notify_key) = &self.notify_key else {
+            return Ok(());
+        };
+
+        let response = self
+            .client
+            .post(format!("{}/notify/ticket-reply", self.base_url))
+            .header("x-api-key", notify_key)
+            .json(&TicketReplyRequest {
+                user_id,
+                ticket: ticket.to_string(),
+            })
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("{}: {}", status, body));
+        }
+        Ok(())
+    }
+}
+
+/// Requires an absolute http(s) URL and strips trailing slashes, so endpoint
+/// paths can be appended with `format!("{}/validate", ...)`.
+fn normalize_base_url(input: &str) -> Result<String, String> {
+    let url = reqwest::Url::parse(input.trim())
+        .map_err(|e| format!("Invalid identity URL '{}': {}", input, e))?;
+
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!(
+            "Invalid identity URL '{}': must start with http:// or https://",
+            input
+        ));
+    }
+    if url.host_str().is_none() {
+        return Err(format!("Invalid identity URL '{}': missing host", input));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(format!(
+            "Invalid identity URL '{}': must not contain a query or fragment",
+            input
+        ));
+    }
+
+    Ok(url.as_str().trim_end_matches('/').to_string())
+}
+
+impl IdentityProvider for IdentityClient {
+    fn validate<'a>(
+        &'a self,
+        api_key: &'a str,
+    ) -> BoxFuture<'a, Result<Option<UserInfo>, IdentityError>> {
+        Box::pin(async move { Ok(self.validate_key(api_key).await?) })
+    }
+
+    fn notify_ticket_reply<'a>(
+        &'a self,
+        user_id: i64,
+        ticket: &'a str,
+    ) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(self.send_ticket_reply(user_id, ticket))
+    }
+}
+
+/// Fixed API keys mapped to canned users, so the server can run without an
+/// identity service. Matches the users referenced by `--seed`.
+pub struct StaticIdentity {
+    users: Vec<(String, UserInfo)>,
+}
+
+impl StaticIdentity {
+    pub fn dev() -> Self {
+        let user = |key: &str, id, email: &str, role, scopes| {
+            (
+                key.to_string(),
+                UserInfo {
+                    id,
+                    email: email.to_string(),
+                    role,
+                    subscription_status: "active".to_string(),
+                    scopes,
+                },
+            )
+        };
+
+        Self {
+            users: vec![
+                user(
+                    "dev_admin",
+                    100,
+                    "admin@curadesk.local",
+                    Role::Admin,
+                    full_access(),
+                ),
+                user(
+                    "dev_support",
+                    101,
+                    "support@curadesk.local",
+                    Role::Support,
+                    full_access(),
+                ),
+                user(
+                    "dev_customer",
+                    1,
+                    "customer@curadesk.local",
+                    Role::Customer,
+                    full_access(),
+                ),
+                // An admin whose key may only read, for trying out scope checks
+                user(
+                    "dev_readonly",
+                    102,
+                    "readonly@curadesk.local",
+                    Role::Admin,
+                    vec![SCOPE_TICKETS_READ.to_string()],
+                ),
+            ],
+        }
+    }
+}
+
+impl IdentityProvider for StaticIdentity {
+    fn validate<'a>(
+        &'a self,
+        api_key: &'a str,
+    ) -> BoxFuture<'a, Result<Option<UserInfo>, IdentityError>> {
+        let user = self
+            .users
+            .iter()
+            .find(|(key, _)| key == api_key)
+            .map(|(_, user)| user.clone());
+        Box::pin(async move { Ok(user) })
+    }
+
+    fn notify_ticket_reply<'a>(
+        &'a self,
+        user_id: i64,
+        ticket: &'a str,
+    ) -> BoxFuture<'a, Result<(), String>> {
+        info!(user_id, ticket, "[dev] Would notify user about a reply");
+        Box::pin(async { Ok(()) })
+    }
 }
 
 #[derive(Serialize)]
@@ -45,6 +269,12 @@ struct ValidateRequest {
     api_key: String,
 }
 
+#[derive(Serialize)]
+struct TicketReplyRequest {
+    user_id: i64,
+    ticket: String,
+}
+
 #[derive(Deserialize)]
 struct ValidateResponse {
     valid: bool,
@@ -57,28 +287,124 @@ struct ValidateResponse {
 pub struct UserInfo {
     pub id: i64,
     pub email: String,
-    pub role: String,
+    pub role: Role,
     pub subscription_status: String,
+    /// Missing from identity servers that predate scoped keys
+    #[serde(default = "full_access")]
+    pub scopes: Vec<String>,
 }
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: DbPool,
-    pub identity: IdentityClient,
+    pub identity: Arc<dyn IdentityProvider>,
+    /// Shared secret for verifying identity-issued JWTs locally
+    pub jwt_secret: Option<String>,
+    /// Prefix for customer-facing ticket references
+    pub ticket_ref_prefix: String,
+    /// Largest ZIP accepted by `create_ticket`
+    pub max_upload_bytes: usize,
+    /// Reject ticket uploads that are not readable ZIP archives
+    pub validate_zips: bool,
+    /// Restrict comment deletion to the comment's author
+    pub author_only_comment_delete: bool,
+    /// Directory for new ticket ZIPs; `None` keeps them in the database
+    pub storage_dir: Option<PathBuf>,
+    pub metrics: PrometheusHandle,
+    /// Ticket changes for `/admin/ws` subscribers
+    pub events: broadcast::Sender<TicketEvent>,
+}
+
+impl AppState {
+    /// Tells connected WebSocket clients about a change. Nobody listening is fine.
+    pub fn publish(&self, event: TicketEvent) {
+        let _ = self.events.send(event);
+    }
+}
+
+/// Verifies an identity-issued JWT (signature and expiry) without a round-trip.
+fn verify_token(token: &str, secret: &str) -> Option<UserInfo> {
+    let claims = jsonwebtoken::decode::<TokenClaims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::default(),
+    )
+    .ok()?
+    .claims;
+
+    Some(UserInfo {
+        id: claims.sub,
+        email: claims.email,
+        role: Role::from_str(&claims.role)?,
+        subscription_status: claims.subscription_status,
+        scopes: claims.scopes,
+    })
 }
 
 #[derive(Debug, Clone)]
 pub struct UserContext {
     pub user_id: i64,
     pub email: String,
-    pub role: String,
-    pub is_admin: bool,
+    pub role: Role,
+    pub subscription_status: String,
+    /// What the presented key may do, within what the role allows
+    pub scopes: Vec<String>,
+}
+
+/// Forbidden unless `scopes` include `scope` or full access.
+fn check_scope(scopes: &[String], scope: &str) -> Result<(), ApiError> {
+    if scopes.iter().any(|s| s == scope || s == FULL_ACCESS_SCOPE) {
+        Ok(())
+    } else {
+        Err(ApiError::Forbidden(
+            "API key lacks the scope for this action",
+        ))
+    }
+}
+
+impl UserContext {
+    /// Customers with an inactive subscription can still read their tickets
+    /// but not open new ones. Admins and support staff are never blocked.
+    pub fn can_open_tickets(&self) -> bool {
+        match self.role {
+            Role::Admin | Role::Support => true,
+            Role::Customer => self.subscription_status != "inactive",
+        }
+    }
+
+    pub fn require_scope(&self, scope: &str) -> Result<(), ApiError> {
+        check_scope(&self.scopes, scope)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct AdminContext {
     pub user_id: i64,
+    #[allow(dead_code)]
+    pub email: String,
+    pub scopes: Vec<String>,
+}
+
+impl AdminContext {
+    pub fn require_scope(&self, scope: &str) -> Result<(), ApiError> {
+        check_scope(&self.scopes, scope)
+    }
+}
+
+/// Support staff or an admin: enough to read and work on any ticket, but not
+/// to delete, export or download customer archives (those take `AdminContext`).
+#[derive(Debug, Clone)]
+pub struct SupportContext {
+    pub user_id: i64,
+    #[allow(dead_code)]
     pub email: String,
+    pub scopes: Vec<String>,
+}
+
+impl SupportContext {
+    pub fn require_scope(&self, scope: &str) -> Result<(), ApiError> {
+        check_scope(&self.scopes, scope)
+    }
 }
 
 impl<S> FromRequestParts<S> for UserContext
@@ -86,34 +412,55 @@ where
     S: Send + Sync,
     AppState: FromRef<S>,
 {
-    type Rejection = (StatusCode, &'static str);
+    type Rejection = ApiError;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        let api_key = parts
+        let app_state = AppState::from_ref(state);
+
+        let bearer = parts
             .headers
-            .get("x-api-key")
+            .get(header::AUTHORIZATION)
             .and_then(|v| v.to_str().ok())
-            .ok_or((StatusCode::UNAUTHORIZED, "Missing X-API-Key header"))?;
+            .and_then(|v| v.strip_prefix("Bearer "));
 
-        let app_state = AppState::from_ref(state);
+        let user = match (bearer, &app_state.jwt_secret) {
+            (Some(token), Some(secret)) => verify_token(token, secret)
+                .ok_or(ApiError::Unauthorized("Invalid or expired token"))?,
+            (Some(_), None) => {
+                return Err(ApiError::Unauthorized("Bearer tokens are not accepted"));
+            }
+            (None, _) => {
+                let api_key = parts
+                    .headers
+                    .get("x-api-key")
+                    .and_then(|v| v.to_str().ok())
+                    .ok_or(ApiError::Unauthorized("Missing X-API-Key header"))?;
 
-        let user = app_state
-            .identity
-            .validate(api_key)
-            .await
-            .map_err(|_| {
-                (
-                    StatusCode::SERVICE_UNAVAILABLE,
-                    "Identity service unavailable",
-                )
-            })?
-            .ok_or((StatusCode::UNAUTHORIZED, "Invalid API key"))?;
+                app_state
+                    .identity
+                    .validate(api_key)
+                    .await
+                    .map_err(|e| {
+                        warn!("Could not validate API key: {}", e);
+                        match e {
+                            IdentityError::Timeout => {
+                                ApiError::Unavailable("Identity service timed out")
+                            }
+                            IdentityError::Unavailable(_) => {
+                                ApiError::Unavailable("Identity service unavailable")
+                            }
+                        }
+                    })?
+                    .ok_or(ApiError::Unauthorized("Invalid API key"))?
+            }
+        };
 
         Ok(UserContext {
             user_id: user.id,
             email: user.email,
-            role: user.role.clone(),
-            is_admin: user.role == "admin",
+            role: user.role,
+            subscription_status: user.subscription_status,
+            scopes: user.scopes,
         })
     }
 }
@@ -123,18 +470,39 @@ where
     S: Send + Sync,
     AppState: FromRef<S>,
 {
-    type Rejection = (StatusCode, &'static str);
+    type Rejection = ApiError;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         let user = UserContext::from_request_parts(parts, state).await?;
 
-        if user.is_admin {
-            Ok(AdminContext {
+        match user.role {
+            Role::Admin => Ok(AdminContext {
                 user_id: user.user_id,
                 email: user.email,
-            })
-        } else {
-            Err((StatusCode::FORBIDDEN, "Admin access required"))
+                scopes: user.scopes,
+            }),
+            Role::Support | Role::Customer => Err(ApiError::Forbidden("Admin access required")),
+        }
+    }
+}
+
+impl<S> FromRequestParts<S> for SupportContext
+where
+    S: Send + Sync,
+    AppState: FromRef<S>,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user = UserContext::from_request_parts(parts, state).await?;
+
+        match user.role {
+            Role::Admin | Role::Support => Ok(SupportContext {
+                user_id: user.user_id,
+                email: user.email,
+                scopes: user.scopes,
+            }),
+            Role::Customer => Err(ApiError::Forbidden("Support access required")),
         }
     }
 }
@@ -154,3 +522,93 @@ impl FromRef<AppState> for DbPool {
         input.db.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_base_url_strips_trailing_slashes() {
+        assert_eq!(
+            normalize_base_url("https://id.example.com/").unwrap(),
+            "https://id.example.com"
+        );
+        assert_eq!(
+            normalize_base_url(" http://localhost:3000/api// ").unwrap(),
+            "http://localhost:3000/api"
+        );
+    }
+
+    #[test]
+    fn normalize_base_url_keeps_well_formed_urls() {
+        assert_eq!(
+            normalize_base_url("https://id.example.com/v1").unwrap(),
+            "https://id.example.com/v1"
+        );
+    }
+
+    #[test]
+    fn normalize_base_url_requires_a_scheme() {
+        assert!(normalize_base_url("id.example.com").is_err());
+        let error = normalize_base_url("localhost:3000").unwrap_err();
+        assert!(error.contains("must start with http:// or https://"));
+        assert!(normalize_base_url("ftp://id.example.com").is_err());
+        assert!(normalize_base_url("https://id.example.com/?x=1").is_err());
+    }
+
+    #[test]
+    fn only_inactive_customers_cannot_open_tickets() {
+        for role in [Role::Admin, Role::Support, Role::Customer] {
+            for status in ["trial", "active", "inactive"] {
+                let user = UserContext {
+                    user_id: 1,
+                    email: "user@example.com".to_string(),
+                    role,
+                    subscription_status: status.to_string(),
+                    scopes: full_access(),
+                };
+                let expected = !(role == Role::Customer && status == "inactive");
+                assert_eq!(user.can_open_tickets(), expected, "{:?} {}", role, status);
+            }
+        }
+    }
+
+    fn token(exp_offset: i64, secret: &str) -> String {
+        let now = chrono::Utc::now().timestamp();
+        let claims = TokenClaims {
+            sub: 7,
+            email: "customer@example.com".to_string(),
+            role: "customer".to_string(),
+            subscription_status: "active".to_string(),
+            scopes: vec![SCOPE_TICKETS_READ.to_string()],
+            iat: now,
+            exp: now + exp_offset,
+        };
+        jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn verify_token_accepts_a_fresh_token() {
+        let user = verify_token(&token(900, "secret"), "secret").unwrap();
+        assert_eq!(user.id, 7);
+        assert_eq!(user.email, "customer@example.com");
+        assert_eq!(user.role, Role::Customer);
+        assert_eq!(user.scopes, vec![SCOPE_TICKETS_READ.to_string()]);
+    }
+
+    #[test]
+    fn verify_token_rejects_an_expired_token() {
+        // Well past the default leeway of a minute
+        assert!(verify_token(&token(-3600, "secret"), "secret").is_none());
+    }
+
+    #[test]
+    fn verify_token_rejects_the_wrong_secret() {
+        assert!(verify_token(&token(900, "other"), "secret").is_none());
+    }
+}
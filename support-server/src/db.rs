@@ -1,11 +1,15 @@
-use rusqlite::{Connection, Result};
-use std::sync::{Arc, Mutex};
+use r2d2::{ManageConnection, Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, ErrorCode, OpenFlags, Result};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-pub type DbPool = Arc<Mutex<Connection>>;
+use crate::migrations;
+
+pub type DbPool = Pool<SqliteConnectionManager>;
+pub type DbConn = PooledConnection<SqliteConnectionManager>;
 
 // Minimal valid empty ZIP file (22 bytes)
-const EMPTY_ZIP: &[u8] = &[
+pub const EMPTY_ZIP: &[u8] = &[
     0x50, 0x4B, 0x05, 0x06, // End of central directory signature
     0x00, 0x00, // Number of this disk
     0x00, 0x00, // Disk where central directory starts
@@ -16,43 +20,63 @@ const EMPTY_ZIP: &[u8] = &[
     0x00, 0x00, // Comment length
 ];
 
-pub fn init_db(path: &str, encryption_key: &str) -> Result<DbPool> {
-    let conn = Connection::open(path)?;
-
-    // Set SQLCipher encryption key
-    conn.execute_batch(&format!("PRAGMA key = '{}';", encryption_key))?;
-
-    conn.execute_batch(
-        "
-        CREATE TABLE IF NOT EXISTS tickets (
-            id INTEGER PRIMARY KEY,
-            user_id INTEGER NOT NULL,
-            created_at INTEGER NOT NULL,
-            description TEXT NOT NULL,
-            zip_data BLOB NOT NULL,
-            zip_filename TEXT NOT NULL,
-            state TEXT NOT NULL DEFAULT 'new'
-        );
-
-        CREATE TABLE IF NOT EXISTS comments (
-            id INTEGER PRIMARY KEY,
-            ticket_id INTEGER NOT NULL,
-            user_id INTEGER NOT NULL,
-            created_at INTEGER NOT NULL,
-            text TEXT NOT NULL,
-            FOREIGN KEY (ticket_id) REFERENCES tickets(id)
-        );
-
-        CREATE INDEX IF NOT EXISTS idx_tickets_user_id ON tickets(user_id);
-        CREATE INDEX IF NOT EXISTS idx_comments_ticket_id ON comments(ticket_id);
-        ",
-    )?;
+/// Opens (or creates) the database; pass `:memory:` as path for a throwaway DB.
+pub fn init_db(path: &str, encryption_key: &str) -> std::result::Result<DbPool, String> {
+    let manager = if path == ":memory:" {
+        SqliteConnectionManager::memory()
+    } else {
+        SqliteConnectionManager::file(path)
+    };
+    // SQLCipher needs the key on every connection before its first read
+    let key = encryption_key.to_string();
+    let manager =
+        manager.with_init(move |conn| conn.execute_batch(&format!("PRAGMA key = '{}';", key)));
+
+    // Migrate outside the pool first: with a wrong key the pool would keep
+    // retrying the failing connection until its timeout instead of erroring
+    let mut conn = manager.connect().map_err(|e| e.to_string())?;
+    migrations::migrate(&mut conn).map_err(|e| e.to_string())?;
 
-    Ok(Arc::new(Mutex::new(conn)))
+    Pool::new(manager).map_err(|e| e.to_string())
 }
 
-pub fn seed_db(pool: &DbPool) -> Result<()> {
-    let conn = pool.lock().unwrap();
+/// Customer-facing reference for a ticket, e.g. `CD-000042`.
+pub fn ticket_reference(prefix: &str, id: i64) -> String {
+    format!("{}-{:06}", prefix, id)
+}
+
+/// Read up to `len` bytes of a ticket's ZIP starting at `offset`, via incremental
+/// blob I/O so the whole attachment never has to sit in memory.
+pub fn read_zip_chunk(
+    conn: &Connection,
+    ticket_id: i64,
+    offset: usize,
+    len: usize,
+) -> Result<Vec<u8>> {
+    let blob = conn.blob_open(rusqlite::MAIN_DB, "tickets", "zip_data", ticket_id, true)?;
+    let len = len.min(blob.len().saturating_sub(offset));
+    let mut buf = vec![0; len];
+    blob.read_at_exact(&mut buf, offset)?;
+    Ok(buf)
+}
+
+/// Gives tickets created before references existed (or by `--seed`) one.
+pub fn assign_missing_references(conn: &Connection, prefix: &str) -> Result<()> {
+    let ids = conn
+        .prepare("SELECT id FROM tickets WHERE reference IS NULL")?
+        .query_map([], |row| row.get::<_, i64>(0))?
+        .collect::<Result<Vec<_>>>()?;
+
+    for id in ids {
+        conn.execute(
+            "UPDATE tickets SET reference = ? WHERE id = ?",
+            rusqlite::params![ticket_reference(prefix, id), id],
+        )?;
+    }
+    Ok(())
+}
+
+pub fn seed_db(conn: &Connection) -> Result<()> {
     let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
@@ -107,3 +131,136 @@ pub fn seed_db(pool: &DbPool) -> Result<()> {
     println!("Note: API keys are now managed via identity-server");
     Ok(())
 }
+
+/// Opens an existing database read-only and checks that the key decrypts it and
+/// that its pages are intact. Returns the row count of every table on success.
+pub fn verify_db(
+    path: &str,
+    encryption_key: &str,
+) -> std::result::Result<Vec<(String, i64)>, String> {
+    if !std::path::Path::new(path).exists() {
+        return Err(format!("Database file {} does not exist", path));
+    }
+
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| e.to_string())?;
+    conn.execute_batch(&format!("PRAGMA key = '{}';", encryption_key))
+        .map_err(|e| e.to_string())?;
+
+    // The first read fails with SQLITE_NOTADB if the key is wrong or the file is garbage
+    let tables: Vec<String> = conn
+        .prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<String>>>()
+        })
+        .map_err(|e| match e.sqlite_error_code() {
+            Some(ErrorCode::NotADatabase) => "Cannot decrypt database — wrong key?".to_string(),
+            _ => e.to_string(),
+        })?;
+
+    let cipher_errors: Vec<String> = conn
+        .prepare("PRAGMA cipher_integrity_check")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<String>>>()
+        })
+        .map_err(|e| e.to_string())?;
+    if !cipher_errors.is_empty() {
+        return Err(format!(
+            "Cipher integrity check failed:\n{}",
+            cipher_errors.join("\n")
+        ));
+    }
+
+    let integrity: String = conn
+        .query_row("PRAGMA integrity_check", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    if integrity != "ok" {
+        return Err(format!("Integrity check failed: {}", integrity));
+    }
+
+    tables
+        .into_iter()
+        .map(|table| {
+            let count: i64 = conn
+                .query_row(&format!("SELECT COUNT(*) FROM \"{}\"", table), [], |row| {
+                    row.get(0)
+                })
+                .map_err(|e| e.to_string())?;
+            Ok((table, count))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a migrated database file and returns its directory and path
+    fn fresh_db() -> (tempfile::TempDir, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.db").to_str().unwrap().to_string();
+        drop(init_db(&path, "test").unwrap());
+        (dir, path)
+    }
+
+    #[test]
+    fn verifies_a_good_file() {
+        let (_dir, path) = fresh_db();
+
+        let counts = verify_db(&path, "test").unwrap();
+
+        assert!(counts.contains(&("tickets".to_string(), 0)));
+    }
+
+    #[test]
+    fn rejects_a_truncated_file() {
+        let (_dir, path) = fresh_db();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        let len = file.metadata().unwrap().len();
+        file.set_len(len / 2).unwrap();
+
+        assert!(verify_db(&path, "test").is_err());
+    }
+
+    #[test]
+    fn rejects_a_wrong_key() {
+        let (_dir, path) = fresh_db();
+
+        let error = verify_db(&path, "other").unwrap_err();
+
+        assert_eq!(error, "Cannot decrypt database — wrong key?");
+    }
+
+    #[test]
+    fn pooled_connections_read_at_the_same_time() {
+        let (_dir, path) = fresh_db();
+        let pool = init_db(&path, "test").unwrap();
+        // Both threads hold a connection before either one queries
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(2));
+
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let pool = pool.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    let conn = pool.get().unwrap();
+                    conn.execute_batch("BEGIN").unwrap();
+                    barrier.wait();
+                    let count: i64 = conn
+                        .query_row("SELECT COUNT(*) FROM tickets", [], |row| row.get(0))
+                        .unwrap();
+                    // Still inside the read transaction when the other one queries
+                    barrier.wait();
+                    conn.execute_batch("COMMIT").unwrap();
+                    count
+                })
+            })
+            .collect();
+
+        for reader in readers {
+            assert_eq!(reader.join().unwrap(), 0);
+        }
+    }
+}
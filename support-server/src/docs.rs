@@ -0,0 +1,101 @@
+//! OpenAPI spec at `/openapi.json` and Swagger UI at `/docs`, only built with
+//! the `docs` feature.
+
+use axum::Router;
+use support_common::Priority;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi, ToSchema};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::auth::AppState;
+use crate::handlers::{admin, health, user};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Curadesk Support API",
+        description = "Tickets, comments and attachments. Authenticate with an `X-API-Key` \
+                       header or, if the server has a JWT secret, a Bearer token."
+    ),
+    paths(
+        health::health,
+        user::create_ticket,
+        user::create_ticket_json,
+        user::list_tickets,
+        user::get_ticket,
+        user::add_comment,
+        admin::list_all_tickets,
+        admin::export_tickets_csv,
+        admin::search_tickets,
+        admin::get_ticket,
+        admin::delete_ticket,
+        admin::update_state,
+        admin::add_tag,
+        admin::remove_tag,
+        admin::add_comment,
+        admin::list_comments,
+        admin::get_comment,
+        admin::add_comment_with_attachment,
+        admin::update_comment,
+        admin::delete_comment,
+        admin::download_comment_attachment,
+        admin::download_zip,
+    ),
+    modifiers(&SecuritySchemes),
+    security(("api_key" = []), ("bearer" = [])),
+    tags(
+        (name = "tickets", description = "A customer's own tickets"),
+        (name = "admin", description = "All tickets, for support staff and admins; deleting, CSV export and report archives are admin-only"),
+    )
+)]
+struct ApiDoc;
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("x-api-key"))),
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+    }
+}
+
+/// Multipart body of `POST /tickets`
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct TicketUpload {
+    description: String,
+    /// The report archive; its file name is kept as `zip_filename`
+    #[schema(value_type = String, format = Binary)]
+    zip: Vec<u8>,
+    priority: Option<Priority>,
+}
+
+/// Multipart body of `POST /admin/tickets/{id}/comments/attachment`
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct CommentUpload {
+    text: String,
+    /// At most 256 KiB
+    #[schema(value_type = String, format = Binary)]
+    file: Vec<u8>,
+    /// `true` posts an internal note
+    internal: Option<bool>,
+}
+
+pub fn router() -> Router<AppState> {
+    SwaggerUi::new("/docs")
+        .url("/openapi.json", ApiDoc::openapi())
+        .into()
+}
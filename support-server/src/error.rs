@@ -0,0 +1,87 @@
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::multipart::MultipartError;
+use support_common::ErrorResponse;
+use tracing::error;
+
+use crate::request_id;
+
+/// Error returned by handlers and extractors, sent as `{"error": "..."}` with
+/// the request's `request_id`.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized(&'static str),
+    Forbidden(&'static str),
+    NotFound(&'static str),
+    Conflict(String),
+    PaymentRequired(&'static str),
+    PayloadTooLarge(String),
+    Unavailable(&'static str),
+    /// The detail is logged but never sent, as it may contain SQL or file paths
+    Internal(String),
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PaymentRequired(_) => StatusCode::PAYMENT_REQUIRED,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let error = match self {
+            ApiError::BadRequest(msg)
+            | ApiError::Conflict(msg)
+            | ApiError::PayloadTooLarge(msg) => msg,
+            ApiError::Unauthorized(msg)
+            | ApiError::Forbidden(msg)
+            | ApiError::NotFound(msg)
+            | ApiError::PaymentRequired(msg)
+            | ApiError::Unavailable(msg) => msg.to_string(),
+            ApiError::Internal(detail) => {
+                error!("Internal error: {}", detail);
+                "Internal server error".to_string()
+            }
+        };
+        let request_id = request_id::current();
+        (status, Json(ErrorResponse { error, request_id })).into_response()
+    }
+}
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(e: rusqlite::Error) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for ApiError {
+    fn from(e: std::io::Error) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
+
+/// Keeps 413 for bodies over the limit; anything else is a malformed upload.
+impl From<MultipartError> for ApiError {
+    fn from(e: MultipartError) -> Self {
+        if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            ApiError::PayloadTooLarge(e.body_text())
+        } else {
+            ApiError::BadRequest(e.body_text())
+        }
+    }
+}
@@ -1,131 +1,724 @@
 use axum::{
     Json,
     body::Body,
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::{StatusCode, header},
     response::Response,
 };
+use axum_extra::extract::Multipart;
+use futures_util::{StreamExt, stream};
+use rusqlite::{Connection, params_from_iter, types::Value};
+use serde::Deserialize;
+use std::io;
 use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncReadExt;
+use tracing::{Instrument, error, warn};
 
-use crate::auth::{AdminContext, AppState};
+use crate::auth::{AdminContext, AppState, SupportContext};
+use crate::db;
+use crate::error::ApiError;
+use crate::handlers::{
+    TICKET_COLUMNS, db_conn, load_comment, load_comments, load_tags, resolve_ticket_id,
+    ticket_from_row,
+};
+use crate::storage;
+#[cfg(feature = "docs")]
+use support_common::ErrorResponse;
 use support_common::{
-    Comment, CreateCommentRequest, Ticket, TicketDetail, TicketState, UpdateStateRequest,
+    AddTagRequest, Comment, CommentPage, CreateCommentRequest, PaginatedTickets,
+    SCOPE_TICKETS_ADMIN, Ticket, TicketDetail, TicketEvent, TicketState, UpdateCommentRequest,
+    UpdateStateRequest,
 };
 
+/// Largest comment page a client may request at once
+const MAX_COMMENT_PAGE: i64 = 200;
+
+/// Comment attachments are meant for a config snippet or short log, not a full report
+const MAX_COMMENT_ATTACHMENT_BYTES: usize = 256 * 1024;
+
+/// Largest ticket page a client may request at once
+const MAX_TICKET_PAGE: i64 = 200;
+
+/// Tickets read per query while streaming the CSV export
+const CSV_EXPORT_BATCH: i64 = 500;
+
+/// Bytes read from the database per step when streaming a ticket's ZIP
+const ZIP_STREAM_CHUNK: usize = 256 * 1024;
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "docs", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct ListQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    /// Only tickets in this state, e.g. `new`
+    pub state: Option<String>,
+    /// Only tickets filed by this user
+    pub user_id: Option<i64>,
+    /// `created` (default) or `updated` for the latest activity first
+    pub sort: Option<String>,
+}
+
+/// The list filters, without paging; the export always covers every match
+#[derive(Deserialize)]
+#[cfg_attr(feature = "docs", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct ExportQuery {
+    /// Only tickets in this state, e.g. `new`
+    pub state: Option<String>,
+    /// Only tickets filed by this user
+    pub user_id: Option<i64>,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "docs", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct SearchQuery {
+    pub q: String,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "docs", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct DetailQuery {
+    /// Only include the newest N comments
+    pub comments_limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "docs", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct CommentsQuery {
+    /// Only return comments with an id lower than this one
+    pub before: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        get,
+        path = "/admin/tickets",
+        tag = "admin",
+        params(ListQuery),
+        responses(
+            (status = 200, body = PaginatedTickets),
+            (status = 400, description = "Unknown state", body = ErrorResponse),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 403, description = "Neither support staff nor an admin", body = ErrorResponse),
+        )
+    )
+)]
 pub async fn list_all_tickets(
     State(state): State<AppState>,
-    _admin: AdminContext,
-) -> Result<Json<Vec<Ticket>>, (StatusCode, String)> {
-    let conn = state.db.lock().unwrap();
-    let mut stmt = conn
-        .prepare("SELECT id, user_id, created_at, description, zip_filename, state FROM tickets ORDER BY created_at DESC")
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    _staff: SupportContext,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<PaginatedTickets>, ApiError> {
+    let limit = query.limit.unwrap_or(50).clamp(1, MAX_TICKET_PAGE);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    let (conditions, mut params) = ticket_filter(query.state.as_deref(), query.user_id)?;
+    let where_clause = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    };
+
+    let order_by = match query.sort.as_deref() {
+        None | Some("created") => "created_at DESC",
+        Some("updated") => "updated_at DESC, created_at DESC",
+        Some(other) => {
+            return Err(ApiError::BadRequest(format!(
+                "Invalid sort '{}', expected created or updated",
+                other
+            )));
+        }
+    };
+
+    let conn = db_conn(&state.db)?;
+
+    let total: i64 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM tickets {}", where_clause),
+        params_from_iter(&params),
+        |row| row.get(0),
+    )?;
 
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM tickets {} ORDER BY {} LIMIT ? OFFSET ?",
+        TICKET_COLUMNS, where_clause, order_by
+    ))?;
+
+    params.push(Value::Integer(limit));
+    params.push(Value::Integer(offset));
     let tickets = stmt
-        .query_map([], |row| {
-            let state_str: String = row.get(5)?;
-            Ok(Ticket {
-                id: row.get(0)?,
-                user_id: row.get(1)?,
-                created_at: row.get(2)?,
-                description: row.get(3)?,
-                zip_filename: row.get(4)?,
-                state: TicketState::from_str(&state_str).unwrap_or(TicketState::New),
-            })
+        .query_map(params_from_iter(&params), ticket_from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Json(PaginatedTickets {
+        tickets,
+        total,
+        limit,
+        offset,
+    }))
+}
+
+/// SQL conditions and their parameters for the ticket list filters.
+fn ticket_filter(
+    state: Option<&str>,
+    user_id: Option<i64>,
+) -> Result<(Vec<&'static str>, Vec<Value>), ApiError> {
+    let mut conditions = Vec::new();
+    let mut params = Vec::new();
+    if let Some(state_str) = state {
+        let ticket_state = TicketState::from_str(state_str)
+            .ok_or_else(|| ApiError::BadRequest(format!("Invalid state '{}'", state_str)))?;
+        conditions.push("state = ?");
+        params.push(Value::Text(ticket_state.as_str().to_string()));
+    }
+    if let Some(user_id) = user_id {
+        conditions.push("user_id = ?");
+        params.push(Value::Integer(user_id));
+    }
+    Ok((conditions, params))
+}
+
+/// All tickets matching the list filters as CSV, oldest first. Rows are read
+/// and sent in batches, so the export never sits in memory as a whole.
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        get,
+        path = "/admin/tickets/export.csv",
+        tag = "admin",
+        params(ExportQuery),
+        responses(
+            (status = 200, description = "`id,user_id,created_at,state,priority,description`", content_type = "text/csv", body = String),
+            (status = 400, description = "Unknown state", body = ErrorResponse),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 403, description = "Not an admin", body = ErrorResponse),
+        )
+    )
+)]
+pub async fn export_tickets_csv(
+    State(state): State<AppState>,
+    _admin: AdminContext,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, ApiError> {
+    let (mut conditions, params) = ticket_filter(query.state.as_deref(), query.user_id)?;
+    conditions.push("id > ?");
+    let sql = format!(
+        "SELECT id, user_id, created_at, state, priority, description FROM tickets
+         WHERE {} ORDER BY id LIMIT {}",
+        conditions.join(" AND "),
+        CSV_EXPORT_BATCH
+    );
+
+    let header = csv_rows(|writer| {
+        writer.write_record([
+            "id",
+            "user_id",
+            "created_at",
+            "state",
+            "priority",
+            "description",
+        ])
+    })?;
+
+    // Keyset pagination on the id, continuing after the last row sent
+    let db = state.db.clone();
+    let rows = stream::try_unfold(Some(0i64), move |last_id| {
+        let db = db.clone();
+        let sql = sql.clone();
+        let mut params = params.clone();
+        async move {
+            let Some(last_id) = last_id else {
+                return Ok(None);
+            };
+            params.push(Value::Integer(last_id));
+            let batch = tokio::task::spawn_blocking(move || csv_batch(&db, &sql, &params))
+                .await
+                .map_err(io::Error::other)?
+                .map_err(|e| {
+                    error!("Failed to export tickets: {}", e);
+                    io::Error::other(e)
+                })?;
+            Ok::<_, io::Error>(Some(match batch {
+                Some((chunk, next_id)) => (chunk, Some(next_id)),
+                None => (Vec::new(), None),
+            }))
+        }
+    });
+    let body = stream::once(async move { Ok::<_, io::Error>(header) }).chain(rows);
+
+    let filename = format!("tickets-{}.csv", chrono::Local::now().format("%Y-%m-%d"));
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/csv; charset=utf-8")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        )
+        .body(Body::from_stream(body))
+        .unwrap())
+}
+
+/// One batch of CSV rows and the id of its last ticket, or `None` when done.
+fn csv_batch(
+    db: &db::DbPool,
+    sql: &str,
+    params: &[Value],
+) -> Result<Option<(Vec<u8>, i64)>, String> {
+    let conn = db.get().map_err(|e| e.to_string())?;
+    let mut stmt = conn.prepare(sql).map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params_from_iter(params), |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, String>(5)?,
+            ))
         })
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| e.to_string())?;
+
+    let Some(last_id) = rows.last().map(|row| row.0) else {
+        return Ok(None);
+    };
+    let chunk = csv_rows(|writer| {
+        for (id, user_id, created_at, state, priority, description) in &rows {
+            writer.write_record([
+                id.to_string(),
+                user_id.to_string(),
+                created_at.to_string(),
+                state.clone(),
+                priority.clone(),
+                description.clone(),
+            ])?;
+        }
+        Ok(())
+    })
+    .map_err(|e| e.to_string())?;
+    Ok(Some((chunk, last_id)))
+}
+
+/// Runs `write` against a CSV writer and returns what it wrote. The writer
+/// quotes fields containing commas, quotes or newlines.
+fn csv_rows(
+    write: impl FnOnce(&mut csv::Writer<Vec<u8>>) -> csv::Result<()>,
+) -> io::Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    write(&mut writer).map_err(io::Error::other)?;
+    writer
+        .into_inner()
+        .map_err(|e| io::Error::other(e.to_string()))
+}
+
+/// Turns free text into an FTS5 query matching all words, quoting each so
+/// characters like `-` or `:` are not read as query syntax.
+fn fts_query(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Tickets whose description or any comment matches `q`, best match first.
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        get,
+        path = "/admin/tickets/search",
+        tag = "admin",
+        params(SearchQuery),
+        responses(
+            (status = 200, body = PaginatedTickets),
+            (status = 400, description = "Empty query", body = ErrorResponse),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 403, description = "Neither support staff nor an admin", body = ErrorResponse),
+        )
+    )
+)]
+pub async fn search_tickets(
+    State(state): State<AppState>,
+    _staff: SupportContext,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<PaginatedTickets>, ApiError> {
+    if query.q.trim().is_empty() {
+        return Err(ApiError::BadRequest(
+            "Search query must not be empty".into(),
+        ));
+    }
+    let limit = query.limit.unwrap_or(50).clamp(1, MAX_TICKET_PAGE);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let fts = fts_query(&query.q);
+
+    // Best (lowest) bm25 rank per ticket across its description and comments
+    let matches = "
+        SELECT ticket_id, MIN(score) AS score FROM (
+            SELECT rowid AS ticket_id, rank AS score FROM tickets_fts WHERE tickets_fts MATCH ?1
+            UNION ALL
+            SELECT c.ticket_id, comments_fts.rank FROM comments_fts
+            JOIN comments c ON c.id = comments_fts.rowid
+            WHERE comments_fts MATCH ?1
+        ) GROUP BY ticket_id";
+
+    let conn = db_conn(&state.db)?;
+
+    let total: i64 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM ({})", matches),
+        [&fts],
+        |row| row.get(0),
+    )?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM tickets JOIN ({}) m ON m.ticket_id = tickets.id
+             ORDER BY m.score, created_at DESC LIMIT ?2 OFFSET ?3",
+        TICKET_COLUMNS, matches
+    ))?;
+
+    let tickets = stmt
+        .query_map(rusqlite::params![fts, limit, offset], ticket_from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
 
-    Ok(Json(tickets))
+    Ok(Json(PaginatedTickets {
+        tickets,
+        total,
+        limit,
+        offset,
+    }))
 }
 
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        get,
+        path = "/admin/tickets/{id}",
+        tag = "admin",
+        params(("id" = String, Path, description = "Ticket id or reference, e.g. `CD-000042`"), DetailQuery),
+        responses(
+            (status = 200, body = TicketDetail),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 403, description = "Neither support staff nor an admin", body = ErrorResponse),
+            (status = 404, description = "Ticket not found", body = ErrorResponse),
+        )
+    )
+)]
 pub async fn get_ticket(
     State(state): State<AppState>,
-    _admin: AdminContext,
-    Path(ticket_id): Path<i64>,
-) -> Result<Json<TicketDetail>, (StatusCode, String)> {
-    let conn = state.db.lock().unwrap();
+    _staff: SupportContext,
+    Path(id_or_ref): Path<String>,
+    Query(query): Query<DetailQuery>,
+) -> Result<Json<TicketDetail>, ApiError> {
+    let conn = db_conn(&state.db)?;
+
+    let ticket_id =
+        resolve_ticket_id(&conn, &id_or_ref).ok_or(ApiError::NotFound("Ticket not found"))?;
 
     let ticket: Ticket = conn
         .query_row(
-            "SELECT id, user_id, created_at, description, zip_filename, state FROM tickets WHERE id = ?",
+            &format!("SELECT {} FROM tickets WHERE id = ?", TICKET_COLUMNS),
             [ticket_id],
-            |row| {
-                let state_str: String = row.get(5)?;
-                Ok(Ticket {
-                    id: row.get(0)?,
-                    user_id: row.get(1)?,
-                    created_at: row.get(2)?,
-                    description: row.get(3)?,
-                    zip_filename: row.get(4)?,
-                    state: TicketState::from_str(&state_str).unwrap_or(TicketState::New),
-                })
-            },
-        )
-        .map_err(|_| (StatusCode::NOT_FOUND, "Ticket not found".into()))?;
-
-    let mut stmt = conn
-        .prepare("SELECT id, ticket_id, user_id, created_at, text FROM comments WHERE ticket_id = ? ORDER BY created_at ASC")
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-
-    let comments = stmt
-        .query_map([ticket_id], |row| {
-            Ok(Comment {
-                id: row.get(0)?,
-                ticket_id: row.get(1)?,
-                user_id: row.get(2)?,
-                created_at: row.get(3)?,
-                text: row.get(4)?,
-            })
+            ticket_from_row,
+        )
+        .map_err(|_| ApiError::NotFound("Ticket not found"))?;
+
+    let limit = query.comments_limit.map(|l| l.clamp(1, MAX_COMMENT_PAGE));
+    let (comments, has_more_comments) = load_comments(&conn, ticket_id, None, limit, true)?;
+    let tags = load_tags(&conn, ticket_id)?;
+
+    Ok(Json(TicketDetail {
+        ticket,
+        comments,
+        has_more_comments,
+        tags,
+    }))
+}
+
+/// Pages backwards through a ticket's comments, e.g. when the CLI scrolls up.
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        get,
+        path = "/admin/tickets/{id}/comments",
+        tag = "admin",
+        params(("id" = i64, Path, description = "Ticket id"), CommentsQuery),
+        responses(
+            (status = 200, body = CommentPage),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 403, description = "Neither support staff nor an admin", body = ErrorResponse),
+            (status = 404, description = "Ticket not found", body = ErrorResponse),
+        )
+    )
+)]
+pub async fn list_comments(
+    State(state): State<AppState>,
+    _staff: SupportContext,
+    Path(ticket_id): Path<i64>,
+    Query(query): Query<CommentsQuery>,
+) -> Result<Json<CommentPage>, ApiError> {
+    let conn = db_conn(&state.db)?;
+
+    let exists: bool = conn
+        .query_row("SELECT 1 FROM tickets WHERE id = ?", [ticket_id], |_| {
+            Ok(true)
         })
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .unwrap_or(false);
+
+    if !exists {
+        return Err(ApiError::NotFound("Ticket not found"));
+    }
 
-    Ok(Json(TicketDetail { ticket, comments }))
+    let limit = query.limit.unwrap_or(50).clamp(1, MAX_COMMENT_PAGE);
+    let (comments, has_more) = load_comments(&conn, ticket_id, query.before, Some(limit), true)?;
+
+    Ok(Json(CommentPage { comments, has_more }))
 }
 
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        put,
+        path = "/admin/tickets/{id}/state",
+        tag = "admin",
+        params(("id" = i64, Path, description = "Ticket id")),
+        request_body = UpdateStateRequest,
+        responses(
+            (status = 204, description = "State changed"),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 403, description = "Neither support staff nor an admin, or the key lacks the tickets:admin scope", body = ErrorResponse),
+            (status = 404, description = "Ticket not found", body = ErrorResponse),
+            (status = 409, description = "Transition not allowed", body = ErrorResponse),
+        )
+    )
+)]
 pub async fn update_state(
     State(state): State<AppState>,
-    _admin: AdminContext,
+    staff: SupportContext,
     Path(ticket_id): Path<i64>,
     Json(req): Json<UpdateStateRequest>,
-) -> Result<StatusCode, (StatusCode, String)> {
-    let conn = state.db.lock().unwrap();
+) -> Result<StatusCode, ApiError> {
+    staff.require_scope(SCOPE_TICKETS_ADMIN)?;
 
-    let rows = conn
-        .execute(
-            "UPDATE tickets SET state = ? WHERE id = ?",
-            rusqlite::params![req.state.as_str(), ticket_id],
+    let conn = db_conn(&state.db)?;
+
+    let current: String = conn
+        .query_row(
+            "SELECT state FROM tickets WHERE id = ?",
+            [ticket_id],
+            |row| row.get(0),
         )
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .map_err(|_| ApiError::NotFound("Ticket not found"))?;
+    let current = TicketState::from_str(&current).unwrap_or(TicketState::New);
 
-    if rows == 0 {
-        Err((StatusCode::NOT_FOUND, "Ticket not found".into()))
-    } else {
-        Ok(StatusCode::NO_CONTENT)
+    if !current.can_transition_to(req.state) {
+        return Err(ApiError::Conflict(format!(
+            "Cannot change ticket state from {} to {}",
+            current.as_str(),
+            req.state.as_str()
+        )));
     }
+
+    conn.execute(
+        "UPDATE tickets SET state = ? WHERE id = ?",
+        rusqlite::params![req.state.as_str(), ticket_id],
+    )?;
+    state.publish(TicketEvent::StateChanged {
+        ticket_id,
+        state: req.state,
+    });
+
+    Ok(StatusCode::NO_CONTENT)
 }
 
-pub async fn add_comment(
+/// Longest tag accepted by `add_tag`
+const MAX_TAG_LEN: usize = 32;
+
+/// Lowercases a tag and checks it only uses characters that are safe in the
+/// `DELETE .../tags/{tag}` path.
+fn normalize_tag(tag: &str) -> Result<String, ApiError> {
+    let tag = tag.trim().to_lowercase();
+    let valid = !tag.is_empty()
+        && tag.len() <= MAX_TAG_LEN
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(ApiError::BadRequest(format!(
+            "Tags are 1-{} letters, digits, '-' or '_'",
+            MAX_TAG_LEN
+        )));
+    }
+    Ok(tag)
+}
+
+/// Labels a ticket, creating the tag on first use. Adding a tag the ticket
+/// already has changes nothing. Returns the ticket's tags.
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        post,
+        path = "/admin/tickets/{id}/tags",
+        tag = "admin",
+        params(("id" = i64, Path, description = "Ticket id")),
+        request_body = AddTagRequest,
+        responses(
+            (status = 200, body = Vec<String>),
+            (status = 400, description = "Invalid tag", body = ErrorResponse),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 403, description = "Neither support staff nor an admin, or the key lacks the tickets:admin scope", body = ErrorResponse),
+            (status = 404, description = "Ticket not found", body = ErrorResponse),
+        )
+    )
+)]
+pub async fn add_tag(
+    State(state): State<AppState>,
+    staff: SupportContext,
+    Path(ticket_id): Path<i64>,
+    Json(req): Json<AddTagRequest>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    staff.require_scope(SCOPE_TICKETS_ADMIN)?;
+
+    let tag = normalize_tag(&req.tag)?;
+    let mut conn = db_conn(&state.db)?;
+    ticket_owner(&conn, ticket_id)?;
+
+    let tx = conn.transaction()?;
+    tx.execute("INSERT OR IGNORE INTO tags (name) VALUES (?)", [&tag])?;
+    tx.execute(
+        "INSERT OR IGNORE INTO ticket_tags (ticket_id, tag_id)
+         SELECT ?, id FROM tags WHERE name = ?",
+        rusqlite::params![ticket_id, tag],
+    )?;
+    let tags = load_tags(&tx, ticket_id)?;
+    tx.commit()?;
+
+    Ok(Json(tags))
+}
+
+/// Takes a tag off a ticket. The tag itself stays for reuse.
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        delete,
+        path = "/admin/tickets/{id}/tags/{tag}",
+        tag = "admin",
+        params(
+            ("id" = i64, Path, description = "Ticket id"),
+            ("tag" = String, Path, description = "Tag name"),
+        ),
+        responses(
+            (status = 204, description = "Tag removed"),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 403, description = "Neither support staff nor an admin, or the key lacks the tickets:admin scope", body = ErrorResponse),
+            (status = 404, description = "The ticket doesn't have this tag", body = ErrorResponse),
+        )
+    )
+)]
+pub async fn remove_tag(
+    State(state): State<AppState>,
+    staff: SupportContext,
+    Path((ticket_id, tag)): Path<(i64, String)>,
+) -> Result<StatusCode, ApiError> {
+    staff.require_scope(SCOPE_TICKETS_ADMIN)?;
+
+    let conn = db_conn(&state.db)?;
+    let removed = conn.execute(
+        "DELETE FROM ticket_tags
+         WHERE ticket_id = ? AND tag_id = (SELECT id FROM tags WHERE name = ?)",
+        rusqlite::params![ticket_id, tag.to_lowercase()],
+    )?;
+    if removed == 0 {
+        return Err(ApiError::NotFound("Tag not found on this ticket"));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Removes a ticket with all its comments and their attachments, e.g. spam or
+/// test submissions.
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        delete,
+        path = "/admin/tickets/{id}",
+        tag = "admin",
+        params(("id" = i64, Path, description = "Ticket id")),
+        responses(
+            (status = 204, description = "Ticket, comments and attachments deleted"),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 403, description = "Not an admin, or the key lacks the tickets:admin scope", body = ErrorResponse),
+            (status = 404, description = "Ticket not found", body = ErrorResponse),
+        )
+    )
+)]
+pub async fn delete_ticket(
     State(state): State<AppState>,
     admin: AdminContext,
     Path(ticket_id): Path<i64>,
-    Json(req): Json<CreateCommentRequest>,
-) -> Result<Json<Comment>, (StatusCode, String)> {
-    let conn = state.db.lock().unwrap();
+) -> Result<StatusCode, ApiError> {
+    admin.require_scope(SCOPE_TICKETS_ADMIN)?;
 
-    // Check ticket exists
-    let exists: bool = conn
-        .query_row("SELECT 1 FROM tickets WHERE id = ?", [ticket_id], |_| {
-            Ok(true)
-        })
-        .unwrap_or(false);
+    let mut conn = db_conn(&state.db)?;
 
-    if !exists {
-        return Err((StatusCode::NOT_FOUND, "Ticket not found".into()));
+    let zip_path: Option<String> = conn
+        .query_row(
+            "SELECT zip_path FROM tickets WHERE id = ?",
+            [ticket_id],
+            |row| row.get(0),
+        )
+        .map_err(|_| ApiError::NotFound("Ticket not found"))?;
+
+    let tx = conn.transaction()?;
+    tx.execute(
+        "DELETE FROM comment_attachments
+         WHERE comment_id IN (SELECT id FROM comments WHERE ticket_id = ?)",
+        [ticket_id],
+    )?;
+    tx.execute("DELETE FROM comments WHERE ticket_id = ?", [ticket_id])?;
+    tx.execute("DELETE FROM ticket_tags WHERE ticket_id = ?", [ticket_id])?;
+    let deleted = tx.execute("DELETE FROM tickets WHERE id = ?", [ticket_id])?;
+
+    // Dropping the transaction rolls it back, though there was nothing to delete anyway
+    if deleted == 0 {
+        return Err(ApiError::NotFound("Ticket not found"));
+    }
+    tx.commit()?;
+
+    if let Some(path) = zip_path {
+        storage::remove_zip(&path);
     }
+    state.publish(TicketEvent::TicketDeleted { ticket_id });
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        post,
+        path = "/admin/tickets/{id}/comments",
+        tag = "admin",
+        params(("id" = i64, Path, description = "Ticket id")),
+        request_body = CreateCommentRequest,
+        responses(
+            (status = 200, body = Comment),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 403, description = "Neither support staff nor an admin, or the key lacks the tickets:admin scope", body = ErrorResponse),
+            (status = 404, description = "Ticket not found", body = ErrorResponse),
+        )
+    )
+)]
+pub async fn add_comment(
+    State(state): State<AppState>,
+    staff: SupportContext,
+    Path(ticket_id): Path<i64>,
+    Json(req): Json<CreateCommentRequest>,
+) -> Result<Json<Comment>, ApiError> {
+    staff.require_scope(SCOPE_TICKETS_ADMIN)?;
+
+    let conn = db_conn(&state.db)?;
+
+    let (owner_id, ticket_label) = ticket_owner(&conn, ticket_id)?;
 
     let created_at = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -133,46 +726,450 @@ pub async fn add_comment(
         .as_secs() as i64;
 
     conn.execute(
-        "INSERT INTO comments (ticket_id, user_id, created_at, text) VALUES (?, ?, ?, ?)",
-        rusqlite::params![ticket_id, admin.user_id, created_at, req.text],
-    )
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        "INSERT INTO comments (ticket_id, user_id, created_at, text, internal) VALUES (?, ?, ?, ?, ?)",
+        rusqlite::params![ticket_id, staff.user_id, created_at, req.text, req.internal],
+    )?;
 
     let id = conn.last_insert_rowid();
+    state.publish(TicketEvent::CommentAdded {
+        ticket_id,
+        comment_id: id,
+        internal: req.internal,
+    });
+
+    // The customer can't see internal notes, so there is nothing to tell them
+    if owner_id != staff.user_id && !req.internal {
+        notify_owner(&state, owner_id, ticket_label);
+    }
 
     Ok(Json(Comment {
         id,
         ticket_id,
-        user_id: admin.user_id,
+        user_id: staff.user_id,
         created_at,
         text: req.text,
+        attachment: None,
+        internal: req.internal,
+        edited_at: None,
     }))
 }
 
-pub async fn download_zip(
+/// Owner and customer-facing label of a ticket, or 404.
+fn ticket_owner(conn: &Connection, ticket_id: i64) -> Result<(i64, String), ApiError> {
+    conn.query_row(
+        "SELECT user_id, reference FROM tickets WHERE id = ?",
+        [ticket_id],
+        |row| {
+            let reference: Option<String> = row.get(1)?;
+            Ok((
+                row.get(0)?,
+                reference.unwrap_or_else(|| format!("#{}", ticket_id)),
+            ))
+        },
+    )
+    .map_err(|_| ApiError::NotFound("Ticket not found"))
+}
+
+/// Emails the ticket owner about a reply in the background. Failures are only
+/// logged, the comment is saved either way.
+fn notify_owner(state: &AppState, owner_id: i64, ticket: String) {
+    let identity = state.identity.clone();
+    // Runs in the request's span so the failure can be traced back to it
+    let notify = async move {
+        if let Err(e) = identity.notify_ticket_reply(owner_id, &ticket).await {
+            warn!(
+                "Failed to notify user {} about reply on {}: {}",
+                owner_id, ticket, e
+            );
+        }
+    };
+    tokio::spawn(notify.in_current_span());
+}
+
+/// Like `add_comment`, but takes multipart `text` and `file` fields and stores
+/// the file alongside the comment. An optional `internal` field set to `true`
+/// posts it as an internal note.
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        post,
+        path = "/admin/tickets/{id}/comments/attachment",
+        tag = "admin",
+        params(("id" = i64, Path, description = "Ticket id")),
+        request_body(content = crate::docs::CommentUpload, content_type = "multipart/form-data"),
+        responses(
+            (status = 200, body = Comment),
+            (status = 400, description = "Missing text or file", body = ErrorResponse),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 403, description = "Neither support staff nor an admin, or the key lacks the tickets:admin scope", body = ErrorResponse),
+            (status = 404, description = "Ticket not found", body = ErrorResponse),
+            (status = 413, description = "Attachment too large", body = ErrorResponse),
+        )
+    )
+)]
+pub async fn add_comment_with_attachment(
     State(state): State<AppState>,
-    _admin: AdminContext,
+    staff: SupportContext,
     Path(ticket_id): Path<i64>,
-) -> Result<Response, (StatusCode, String)> {
-    let conn = state.db.lock().unwrap();
+    mut multipart: Multipart,
+) -> Result<Json<Comment>, ApiError> {
+    staff.require_scope(SCOPE_TICKETS_ADMIN)?;
+
+    let mut text: Option<String> = None;
+    let mut file: Option<(String, Vec<u8>)> = None;
+    let mut internal = false;
+
+    while let Some(mut field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or("").to_string();
+
+        match name.as_str() {
+            "text" => {
+                text = Some(field.text().await?);
+            }
+            "internal" => {
+                let value = field.text().await?;
+                internal = matches!(value.trim(), "true" | "1");
+            }
+            "file" => {
+                let filename = field.file_name().unwrap_or("attachment").to_string();
+                // Count while reading so an oversized upload is never fully buffered
+                let mut data = Vec::new();
+                while let Some(chunk) = field.chunk().await? {
+                    if data.len() + chunk.len() > MAX_COMMENT_ATTACHMENT_BYTES {
+                        return Err(ApiError::PayloadTooLarge(format!(
+                            "Attachment exceeds {} KiB",
+                            MAX_COMMENT_ATTACHMENT_BYTES / 1024
+                        )));
+                    }
+                    data.extend_from_slice(&chunk);
+                }
+                file = Some((filename, data));
+            }
+            _ => {}
+        }
+    }
+
+    let text = text.ok_or(ApiError::BadRequest("Missing text".into()))?;
+    let (filename, data) = file.ok_or(ApiError::BadRequest("Missing file".into()))?;
+
+    let mut conn = db_conn(&state.db)?;
+
+    let (owner_id, ticket_label) = ticket_owner(&conn, ticket_id)?;
+
+    let created_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO comments (ticket_id, user_id, created_at, text, internal) VALUES (?, ?, ?, ?, ?)",
+        rusqlite::params![ticket_id, staff.user_id, created_at, text, internal],
+    )?;
+    let id = tx.last_insert_rowid();
+    tx.execute(
+        "INSERT INTO comment_attachments (comment_id, filename, data) VALUES (?, ?, ?)",
+        rusqlite::params![id, filename, data],
+    )?;
+    tx.commit()?;
+    state.publish(TicketEvent::CommentAdded {
+        ticket_id,
+        comment_id: id,
+        internal,
+    });
+
+    if owner_id != staff.user_id && !internal {
+        notify_owner(&state, owner_id, ticket_label);
+    }
+
+    Ok(Json(Comment {
+        id,
+        ticket_id,
+        user_id: staff.user_id,
+        created_at,
+        text,
+        attachment: Some(filename),
+        internal,
+        edited_at: None,
+    }))
+}
+
+/// A single comment of the ticket, including internal notes, e.g. to refresh
+/// it after an edit.
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        get,
+        path = "/admin/tickets/{id}/comments/{comment_id}",
+        tag = "admin",
+        params(("id" = i64, Path, description = "Ticket id"), ("comment_id" = i64, Path, description = "Comment id")),
+        responses(
+            (status = 200, description = "The comment", body = Comment),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 403, description = "Neither support staff nor an admin", body = ErrorResponse),
+            (status = 404, description = "No such comment on this ticket", body = ErrorResponse),
+        )
+    )
+)]
+pub async fn get_comment(
+    State(state): State<AppState>,
+    _staff: SupportContext,
+    Path((ticket_id, comment_id)): Path<(i64, i64)>,
+) -> Result<Json<Comment>, ApiError> {
+    let conn = db_conn(&state.db)?;
+
+    load_comment(&conn, ticket_id, comment_id)?
+        .map(Json)
+        .ok_or(ApiError::NotFound("Comment not found"))
+}
+
+/// Fixes a comment's text. Only its author may do this, so nobody's words get
+/// changed under their name.
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        put,
+        path = "/admin/tickets/{id}/comments/{comment_id}",
+        tag = "admin",
+        params(("id" = i64, Path, description = "Ticket id"), ("comment_id" = i64, Path, description = "Comment id")),
+        request_body = UpdateCommentRequest,
+        responses(
+            (status = 204, description = "Text replaced"),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 403, description = "Neither support staff nor an admin, not the author, or the key lacks the tickets:admin scope", body = ErrorResponse),
+            (status = 404, description = "No such comment on this ticket", body = ErrorResponse),
+        )
+    )
+)]
+pub async fn update_comment(
+    State(state): State<AppState>,
+    staff: SupportContext,
+    Path((ticket_id, comment_id)): Path<(i64, i64)>,
+    Json(req): Json<UpdateCommentRequest>,
+) -> Result<StatusCode, ApiError> {
+    staff.require_scope(SCOPE_TICKETS_ADMIN)?;
+
+    let conn = db_conn(&state.db)?;
+
+    if comment_author(&conn, ticket_id, comment_id)? != staff.user_id {
+        return Err(ApiError::Forbidden("Only the author can edit this comment"));
+    }
+
+    let edited_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    conn.execute(
+        "UPDATE comments SET text = ?, edited_at = ? WHERE id = ?",
+        rusqlite::params![req.text, edited_at, comment_id],
+    )?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Deletes a comment and its attachment. Any admin may, unless the server
+/// runs with `--author-only-comment-delete`.
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        delete,
+        path = "/admin/tickets/{id}/comments/{comment_id}",
+        tag = "admin",
+        params(("id" = i64, Path, description = "Ticket id"), ("comment_id" = i64, Path, description = "Comment id")),
+        responses(
+            (status = 204, description = "Comment deleted"),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 403, description = "Not an admin, not the author with --author-only-comment-delete, or the key lacks the tickets:admin scope", body = ErrorResponse),
+            (status = 404, description = "No such comment on this ticket", body = ErrorResponse),
+        )
+    )
+)]
+pub async fn delete_comment(
+    State(state): State<AppState>,
+    admin: AdminContext,
+    Path((ticket_id, comment_id)): Path<(i64, i64)>,
+) -> Result<StatusCode, ApiError> {
+    admin.require_scope(SCOPE_TICKETS_ADMIN)?;
+
+    let mut conn = db_conn(&state.db)?;
+
+    let author_id = comment_author(&conn, ticket_id, comment_id)?;
+    if state.author_only_comment_delete && author_id != admin.user_id {
+        return Err(ApiError::Forbidden(
+            "Only the author can delete this comment",
+        ));
+    }
+
+    let tx = conn.transaction()?;
+    tx.execute(
+        "DELETE FROM comment_attachments WHERE comment_id = ?",
+        [comment_id],
+    )?;
+    tx.execute("DELETE FROM comments WHERE id = ?", [comment_id])?;
+    tx.commit()?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Author of a comment, or 404 if it doesn't exist on the given ticket.
+fn comment_author(conn: &Connection, ticket_id: i64, comment_id: i64) -> Result<i64, ApiError> {
+    conn.query_row(
+        "SELECT user_id FROM comments WHERE id = ? AND ticket_id = ?",
+        [comment_id, ticket_id],
+        |row| row.get(0),
+    )
+    .map_err(|_| ApiError::NotFound("Comment not found"))
+}
 
-    let (zip_data, zip_filename): (Vec<u8>, String) = conn
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        get,
+        path = "/admin/comments/{id}/attachment",
+        tag = "admin",
+        params(("id" = i64, Path, description = "Comment id")),
+        responses(
+            (status = 200, description = "The attached file", content_type = "application/octet-stream", body = Vec<u8>),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 403, description = "Neither support staff nor an admin", body = ErrorResponse),
+            (status = 404, description = "Comment has no attachment", body = ErrorResponse),
+        )
+    )
+)]
+pub async fn download_comment_attachment(
+    State(state): State<AppState>,
+    _staff: SupportContext,
+    Path(comment_id): Path<i64>,
+) -> Result<Response, ApiError> {
+    let conn = db_conn(&state.db)?;
+
+    let (data, filename): (Vec<u8>, String) = conn
         .query_row(
-            "SELECT zip_data, zip_filename FROM tickets WHERE id = ?",
-            [ticket_id],
+            "SELECT data, filename FROM comment_attachments WHERE comment_id = ?",
+            [comment_id],
             |row| Ok((row.get(0)?, row.get(1)?)),
         )
-        .map_err(|_| (StatusCode::NOT_FOUND, "Ticket not found".into()))?;
+        .map_err(|_| ApiError::NotFound("Attachment not found"))?;
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        )
+        .body(Body::from(data))
+        .unwrap();
+
+    Ok(response)
+}
+
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        get,
+        path = "/admin/tickets/{id}/zip",
+        tag = "admin",
+        params(("id" = i64, Path, description = "Ticket id")),
+        responses(
+            (status = 200, description = "The ticket's ZIP", content_type = "application/zip", body = Vec<u8>),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 403, description = "Not an admin", body = ErrorResponse),
+            (status = 404, description = "Ticket not found", body = ErrorResponse),
+        )
+    )
+)]
+pub async fn download_zip(
+    State(state): State<AppState>,
+    _admin: AdminContext,
+    Path(ticket_id): Path<i64>,
+) -> Result<Response, ApiError> {
+    let (zip_size, zip_filename, zip_path): (i64, String, Option<String>) = {
+        let conn = db_conn(&state.db)?;
+        conn.query_row(
+            "SELECT length(zip_data), zip_filename, zip_path FROM tickets WHERE id = ?",
+            [ticket_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .map_err(|_| ApiError::NotFound("Ticket not found"))?
+    };
+
+    let (zip_size, body) = match zip_path {
+        Some(path) => stream_zip_file(ticket_id, &path).await?,
+        None => (
+            zip_size as usize,
+            stream_zip_blob(&state, ticket_id, zip_size as usize),
+        ),
+    };
 
     let response = Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "application/zip")
+        .header(header::CONTENT_LENGTH, zip_size)
         .header(
             header::CONTENT_DISPOSITION,
             format!("attachment; filename=\"{}\"", zip_filename),
         )
-        .body(Body::from(zip_data))
+        .body(body)
         .unwrap();
 
     Ok(response)
 }
+
+/// Streams a ZIP kept under `--storage-dir`, returning its size and the body.
+async fn stream_zip_file(ticket_id: i64, path: &str) -> Result<(usize, Body), ApiError> {
+    let file = tokio::fs::File::open(path).await.map_err(|e| {
+        ApiError::Internal(format!(
+            "Failed to open ZIP of ticket {} at {}: {}",
+            ticket_id, path, e
+        ))
+    })?;
+    let zip_size = file.metadata().await?.len() as usize;
+
+    let chunks = stream::try_unfold(file, |mut file| async move {
+        let mut chunk = vec![0; ZIP_STREAM_CHUNK];
+        let read = file.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok::<_, io::Error>(None);
+        }
+        chunk.truncate(read);
+        Ok(Some((chunk, file)))
+    });
+    Ok((zip_size, Body::from_stream(chunks)))
+}
+
+/// Streams a ZIP held in the `zip_data` blob.
+fn stream_zip_blob(state: &AppState, ticket_id: i64, zip_size: usize) -> Body {
+    // Attachments can be hundreds of MB, so hand them out chunk by chunk
+    let db = state.db.clone();
+    let chunks = stream::try_unfold(0, move |offset| {
+        let db = db.clone();
+        async move {
+            if offset >= zip_size {
+                return Ok(None);
+            }
+            // A connection per chunk, so a slow client doesn't hold one for the whole download
+            let chunk = tokio::task::spawn_blocking(move || {
+                let conn = db.get().map_err(io::Error::other)?;
+                db::read_zip_chunk(&conn, ticket_id, offset, ZIP_STREAM_CHUNK)
+                    .map_err(io::Error::other)
+            })
+            .await
+            .map_err(io::Error::other)?
+            .map_err(|e| {
+                error!("Failed to stream ZIP of ticket {}: {}", ticket_id, e);
+                io::Error::other(e)
+            })?;
+            if chunk.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "ZIP ended early",
+                ));
+            }
+            let next = offset + chunk.len();
+            Ok(Some((chunk, next)))
+        }
+    });
+    Body::from_stream(chunks)
+}
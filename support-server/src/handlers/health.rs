@@ -0,0 +1,33 @@
+use axum::{Json, extract::State, http::StatusCode};
+use support_common::HealthStatus;
+
+use crate::auth::AppState;
+
+/// Liveness/readiness probe; needs no API key. Returns 503 if the database
+/// does not answer a trivial query.
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        get,
+        path = "/health",
+        security(()),
+        responses(
+            (status = 200, description = "Service and database are up", body = HealthStatus),
+            (status = 503, description = "Database unreachable", body = HealthStatus),
+        )
+    )
+)]
+pub async fn health(State(state): State<AppState>) -> (StatusCode, Json<HealthStatus>) {
+    let db = state
+        .db
+        .get()
+        .map(|conn| conn.query_row("SELECT 1", [], |_| Ok(())).is_ok())
+        .unwrap_or(false);
+
+    let status = if db {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(HealthStatus::from_db_check(db)))
+}
@@ -1,2 +1,137 @@
 pub mod admin;
+pub mod health;
 pub mod user;
+pub mod ws;
+
+use rusqlite::{Connection, OptionalExtension, Row};
+use support_common::{Comment, Priority, Ticket, TicketState};
+use tracing::error;
+
+use crate::db::{DbConn, DbPool};
+use crate::error::ApiError;
+
+/// Checks out a pooled connection, or 503 if none frees up in time.
+pub fn db_conn(pool: &DbPool) -> Result<DbConn, ApiError> {
+    pool.get().map_err(|e| {
+        error!("Failed to get database connection: {}", e);
+        ApiError::Unavailable("Database unavailable")
+    })
+}
+
+/// Columns selected for a `Ticket`, in the order `ticket_from_row` expects
+pub const TICKET_COLUMNS: &str =
+    "id, user_id, created_at, description, zip_filename, state, reference, priority, updated_at";
+
+pub fn ticket_from_row(row: &Row) -> rusqlite::Result<Ticket> {
+    let state_str: String = row.get(5)?;
+    Ok(Ticket {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        created_at: row.get(2)?,
+        updated_at: row.get(8)?,
+        description: row.get(3)?,
+        zip_filename: row.get(4)?,
+        state: TicketState::from_str(&state_str).unwrap_or(TicketState::New),
+        reference: row.get(6)?,
+        priority: Priority::from_str(&row.get::<_, String>(7)?).unwrap_or_default(),
+        // Tickets are not assigned to agents yet
+        assignee_id: None,
+    })
+}
+
+/// Accepts either a numeric ticket id or a reference like `CD-000042`.
+pub fn resolve_ticket_id(conn: &Connection, id_or_ref: &str) -> Option<i64> {
+    if let Ok(id) = id_or_ref.parse::<i64>() {
+        return Some(id);
+    }
+    conn.query_row(
+        "SELECT id FROM tickets WHERE reference = ? COLLATE NOCASE",
+        [id_or_ref],
+        |row| row.get(0),
+    )
+    .optional()
+    .ok()
+    .flatten()
+}
+
+/// Comments with their attachment's file name, for `comment_from_row`
+const COMMENT_SELECT: &str =
+    "SELECT c.id, c.ticket_id, c.user_id, c.created_at, c.text, a.filename, c.internal,
+            c.edited_at
+     FROM comments c
+     LEFT JOIN comment_attachments a ON a.comment_id = c.id";
+
+fn comment_from_row(row: &Row) -> rusqlite::Result<Comment> {
+    Ok(Comment {
+        id: row.get(0)?,
+        ticket_id: row.get(1)?,
+        user_id: row.get(2)?,
+        created_at: row.get(3)?,
+        text: row.get(4)?,
+        attachment: row.get(5)?,
+        internal: row.get(6)?,
+        edited_at: row.get(7)?,
+    })
+}
+
+/// One comment, or `None` if it doesn't exist on the given ticket.
+pub fn load_comment(
+    conn: &Connection,
+    ticket_id: i64,
+    comment_id: i64,
+) -> rusqlite::Result<Option<Comment>> {
+    conn.query_row(
+        &format!("{} WHERE c.id = ? AND c.ticket_id = ?", COMMENT_SELECT),
+        [comment_id, ticket_id],
+        comment_from_row,
+    )
+    .optional()
+}
+
+/// Loads a ticket's comments in chronological order. With a `limit` only the
+/// newest `limit` comments (older than `before`, if given) are returned, plus
+/// whether even older ones exist. Internal notes are skipped unless
+/// `include_internal` is set.
+pub fn load_comments(
+    conn: &Connection,
+    ticket_id: i64,
+    before: Option<i64>,
+    limit: Option<i64>,
+    include_internal: bool,
+) -> rusqlite::Result<(Vec<Comment>, bool)> {
+    let mut stmt = conn.prepare(&format!(
+        "{}
+         WHERE c.ticket_id = ?1 AND (?2 IS NULL OR c.id < ?2) AND (?4 OR c.internal = 0)
+         ORDER BY c.id DESC
+         LIMIT ?3",
+        COMMENT_SELECT
+    ))?;
+
+    // Fetch one extra row to find out whether there is an older page
+    let fetch = limit.map_or(-1, |l| l + 1);
+    let mut comments = stmt
+        .query_map(
+            rusqlite::params![ticket_id, before, fetch, include_internal],
+            comment_from_row,
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let has_more = limit.is_some_and(|l| comments.len() as i64 > l);
+    if has_more {
+        comments.pop();
+    }
+    comments.reverse();
+    Ok((comments, has_more))
+}
+
+/// A ticket's tag names in alphabetical order.
+pub fn load_tags(conn: &Connection, ticket_id: i64) -> rusqlite::Result<Vec<String>> {
+    conn.prepare(
+        "SELECT t.name FROM ticket_tags tt
+         JOIN tags t ON t.id = tt.tag_id
+         WHERE tt.ticket_id = ?
+         ORDER BY t.name",
+    )?
+    .query_map([ticket_id], |row| row.get(0))?
+    .collect()
+}
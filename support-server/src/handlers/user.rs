@@ -1,152 +1,373 @@
 use axum::{
     Json,
     extract::{Path, State},
-    http::StatusCode,
 };
 use axum_extra::extract::Multipart;
+use base64::prelude::*;
+use std::io::Cursor;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::auth::{AppState, UserContext};
-use support_common::{Comment, Ticket, TicketDetail, TicketState};
+use crate::db::{EMPTY_ZIP, ticket_reference};
+use crate::error::ApiError;
+use crate::handlers::{TICKET_COLUMNS, db_conn, load_comments, resolve_ticket_id, ticket_from_row};
+use crate::storage;
+#[cfg(feature = "docs")]
+use support_common::ErrorResponse;
+use support_common::{
+    Comment, CreateCommentRequest, CreateTicketRequest, Priority, SCOPE_TICKETS_WRITE, Ticket,
+    TicketDetail, TicketEvent, TicketState,
+};
 
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        post,
+        path = "/tickets",
+        tag = "tickets",
+        request_body(content = crate::docs::TicketUpload, content_type = "multipart/form-data"),
+        responses(
+            (status = 200, body = Ticket),
+            (status = 400, description = "Missing field or not a ZIP archive", body = ErrorResponse),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 402, description = "The customer's subscription is inactive", body = ErrorResponse),
+            (status = 403, description = "The key lacks the tickets:write scope", body = ErrorResponse),
+            (status = 413, description = "ZIP larger than --max-upload-bytes", body = ErrorResponse),
+        )
+    )
+)]
 pub async fn create_ticket(
     State(state): State<AppState>,
     user: UserContext,
     mut multipart: Multipart,
-) -> Result<Json<Ticket>, (StatusCode, String)> {
-    let db = &state.db;
+) -> Result<Json<Ticket>, ApiError> {
+    user.require_scope(SCOPE_TICKETS_WRITE)?;
+
+    require_subscription(&user)?;
+
     let mut description: Option<String> = None;
     let mut zip_data: Option<Vec<u8>> = None;
     let mut zip_filename: Option<String> = None;
+    let mut priority = Priority::Normal;
 
-    while let Some(field) = multipart
-        .next_field()
-        .await
-        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?
-    {
+    while let Some(mut field) = multipart.next_field().await? {
         let name = field.name().unwrap_or("").to_string();
 
         match name.as_str() {
             "description" => {
-                description = Some(
-                    field
-                        .text()
-                        .await
-                        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?,
-                );
+                description = Some(field.text().await?);
+            }
+            "priority" => {
+                let value = field.text().await?;
+                priority = Priority::from_str(value.trim())
+                    .ok_or_else(|| ApiError::BadRequest(format!("Invalid priority '{}'", value)))?;
             }
             "zip" => {
                 zip_filename = field.file_name().map(|s| s.to_string());
-                zip_data = Some(
-                    field
-                        .bytes()
-                        .await
-                        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?
-                        .to_vec(),
-                );
+                // Count while reading so an oversized upload is never fully buffered
+                let mut data = Vec::new();
+                while let Some(chunk) = field.chunk().await? {
+                    if data.len() + chunk.len() > state.max_upload_bytes {
+                        return Err(ApiError::PayloadTooLarge(format!(
+                            "ZIP exceeds {} bytes",
+                            state.max_upload_bytes
+                        )));
+                    }
+                    data.extend_from_slice(&chunk);
+                }
+                zip_data = Some(data);
             }
             _ => {}
         }
     }
 
-    let description = description.ok_or((StatusCode::BAD_REQUEST, "Missing description".into()))?;
-    let zip_data = zip_data.ok_or((StatusCode::BAD_REQUEST, "Missing zip file".into()))?;
+    let description = description.ok_or(ApiError::BadRequest("Missing description".into()))?;
+    let zip_data = zip_data.ok_or(ApiError::BadRequest("Missing zip file".into()))?;
     let zip_filename = zip_filename.unwrap_or_else(|| "upload.zip".to_string());
 
+    insert_ticket(
+        &state,
+        user.user_id,
+        description,
+        zip_data,
+        zip_filename,
+        priority,
+    )
+    .map(Json)
+}
+
+fn require_subscription(user: &UserContext) -> Result<(), ApiError> {
+    if user.can_open_tickets() {
+        Ok(())
+    } else {
+        Err(ApiError::PaymentRequired("Subscription inactive"))
+    }
+}
+
+/// Same as `create_ticket`, for clients that would rather send JSON with a
+/// base64-encoded ZIP than build a multipart body.
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        post,
+        path = "/tickets/json",
+        tag = "tickets",
+        request_body = CreateTicketRequest,
+        responses(
+            (status = 200, body = Ticket),
+            (status = 400, description = "Invalid base64 or not a ZIP archive", body = ErrorResponse),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 402, description = "The customer's subscription is inactive", body = ErrorResponse),
+            (status = 403, description = "The key lacks the tickets:write scope", body = ErrorResponse),
+            (status = 413, description = "ZIP larger than --max-upload-bytes", body = ErrorResponse),
+        )
+    )
+)]
+pub async fn create_ticket_json(
+    State(state): State<AppState>,
+    user: UserContext,
+    Json(req): Json<CreateTicketRequest>,
+) -> Result<Json<Ticket>, ApiError> {
+    user.require_scope(SCOPE_TICKETS_WRITE)?;
+
+    require_subscription(&user)?;
+
+    let zip_data = match &req.zip_base64 {
+        Some(encoded) => BASE64_STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| ApiError::BadRequest(format!("Invalid base64 in zip_base64: {}", e)))?,
+        None => EMPTY_ZIP.to_vec(),
+    };
+    let zip_filename = req.zip_filename.unwrap_or_else(|| "upload.zip".to_string());
+
+    insert_ticket(
+        &state,
+        user.user_id,
+        req.description,
+        zip_data,
+        zip_filename,
+        req.priority,
+    )
+    .map(Json)
+}
+
+/// Validates the upload and stores a new ticket with its reference.
+fn insert_ticket(
+    state: &AppState,
+    user_id: i64,
+    description: String,
+    zip_data: Vec<u8>,
+    zip_filename: String,
+    priority: Priority,
+) -> Result<Ticket, ApiError> {
+    if zip_data.len() > state.max_upload_bytes {
+        return Err(ApiError::PayloadTooLarge(format!(
+            "ZIP exceeds {} bytes",
+            state.max_upload_bytes
+        )));
+    }
+
+    // Catch renamed .rar files and raw logs here rather than in the CLI's ZIP viewer
+    if state.validate_zips
+        && let Err(e) = zip::ZipArchive::new(Cursor::new(&zip_data))
+    {
+        return Err(ApiError::BadRequest(format!(
+            "Uploaded file is not a valid ZIP archive: {}",
+            e
+        )));
+    }
+
     let created_at = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_secs() as i64;
 
-    let conn = db.lock().unwrap();
-    conn.execute(
-        "INSERT INTO tickets (user_id, created_at, description, zip_data, zip_filename, state) VALUES (?, ?, ?, ?, ?, ?)",
-        rusqlite::params![user.user_id, created_at, description, zip_data, zip_filename, "new"],
-    )
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let conn = db_conn(&state.db)?;
+
+    // With a storage dir the row only points at the file and keeps an empty blob
+    let (zip_data, zip_path) = match &state.storage_dir {
+        Some(dir) => {
+            let path = storage::write_zip(dir, &zip_data)?;
+            (Vec::new(), Some(path.to_string_lossy().into_owned()))
+        }
+        None => (zip_data, None),
+    };
+
+    if let Err(e) = conn.execute(
+        "INSERT INTO tickets (user_id, created_at, description, zip_data, zip_path, zip_filename, state, priority) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        rusqlite::params![user_id, created_at, description, zip_data, zip_path, zip_filename, "new", priority.as_str()],
+    ) {
+        if let Some(path) = &zip_path {
+            storage::remove_zip(path);
+        }
+        return Err(e.into());
+    }
 
     let id = conn.last_insert_rowid();
+    let reference = ticket_reference(&state.ticket_ref_prefix, id);
+    conn.execute(
+        "UPDATE tickets SET reference = ? WHERE id = ?",
+        rusqlite::params![reference, id],
+    )?;
 
-    Ok(Json(Ticket {
+    state.publish(TicketEvent::TicketCreated {
+        ticket_id: id,
+        user_id,
+    });
+
+    Ok(Ticket {
         id,
-        user_id: user.user_id,
+        user_id,
         created_at,
+        updated_at: created_at,
         description,
         zip_filename,
         state: TicketState::New,
-    }))
+        priority,
+        reference: Some(reference),
+        assignee_id: None,
+    })
 }
 
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        get,
+        path = "/tickets",
+        tag = "tickets",
+        responses(
+            (status = 200, description = "The caller's tickets, newest first", body = Vec<Ticket>),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        )
+    )
+)]
 pub async fn list_tickets(
     State(state): State<AppState>,
     user: UserContext,
-) -> Result<Json<Vec<Ticket>>, (StatusCode, String)> {
-    let conn = state.db.lock().unwrap();
-    let mut stmt = conn
-        .prepare("SELECT id, user_id, created_at, description, zip_filename, state FROM tickets WHERE user_id = ? ORDER BY created_at DESC")
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+) -> Result<Json<Vec<Ticket>>, ApiError> {
+    let conn = db_conn(&state.db)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM tickets WHERE user_id = ? ORDER BY created_at DESC",
+        TICKET_COLUMNS
+    ))?;
 
     let tickets = stmt
-        .query_map([user.user_id], |row| {
-            let state_str: String = row.get(5)?;
-            Ok(Ticket {
-                id: row.get(0)?,
-                user_id: row.get(1)?,
-                created_at: row.get(2)?,
-                description: row.get(3)?,
-                zip_filename: row.get(4)?,
-                state: TicketState::from_str(&state_str).unwrap_or(TicketState::New),
-            })
-        })
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .query_map([user.user_id], ticket_from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
 
     Ok(Json(tickets))
 }
 
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        get,
+        path = "/tickets/{id}",
+        tag = "tickets",
+        params(("id" = String, Path, description = "Ticket id or reference, e.g. `CD-000042`")),
+        responses(
+            (status = 200, description = "Ticket with its comments, without internal notes", body = TicketDetail),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 404, description = "Ticket not found", body = ErrorResponse),
+        )
+    )
+)]
 pub async fn get_ticket(
     State(state): State<AppState>,
     user: UserContext,
-    Path(ticket_id): Path<i64>,
-) -> Result<Json<TicketDetail>, (StatusCode, String)> {
-    let conn = state.db.lock().unwrap();
+    Path(id_or_ref): Path<String>,
+) -> Result<Json<TicketDetail>, ApiError> {
+    let conn = db_conn(&state.db)?;
+
+    let ticket_id =
+        resolve_ticket_id(&conn, &id_or_ref).ok_or(ApiError::NotFound("Ticket not found"))?;
 
     let ticket: Ticket = conn
         .query_row(
-            "SELECT id, user_id, created_at, description, zip_filename, state FROM tickets WHERE id = ? AND user_id = ?",
+            &format!(
+                "SELECT {} FROM tickets WHERE id = ? AND user_id = ?",
+                TICKET_COLUMNS
+            ),
             [ticket_id, user.user_id],
-            |row| {
-                let state_str: String = row.get(5)?;
-                Ok(Ticket {
-                    id: row.get(0)?,
-                    user_id: row.get(1)?,
-                    created_at: row.get(2)?,
-                    description: row.get(3)?,
-                    zip_filename: row.get(4)?,
-                    state: TicketState::from_str(&state_str).unwrap_or(TicketState::New),
-                })
-            },
+            ticket_from_row,
+        )
+        .map_err(|_| ApiError::NotFound("Ticket not found"))?;
+
+    let (comments, _) = load_comments(&conn, ticket_id, None, None, false)?;
+
+    Ok(Json(TicketDetail {
+        ticket,
+        comments,
+        has_more_comments: false,
+        tags: Vec::new(),
+    }))
+}
+
+#[cfg_attr(
+    feature = "docs",
+    utoipa::path(
+        post,
+        path = "/tickets/{id}/comments",
+        tag = "tickets",
+        params(("id" = i64, Path, description = "Ticket id")),
+        request_body = CreateCommentRequest,
+        responses(
+            (status = 200, body = Comment),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+            (status = 403, description = "Not the caller's ticket, or the key lacks the tickets:write scope", body = ErrorResponse),
+            (status = 404, description = "Ticket not found", body = ErrorResponse),
         )
-        .map_err(|_| (StatusCode::NOT_FOUND, "Ticket not found".into()))?;
-
-    let mut stmt = conn
-        .prepare("SELECT id, ticket_id, user_id, created_at, text FROM comments WHERE ticket_id = ? ORDER BY created_at ASC")
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-
-    let comments = stmt
-        .query_map([ticket_id], |row| {
-            Ok(Comment {
-                id: row.get(0)?,
-                ticket_id: row.get(1)?,
-                user_id: row.get(2)?,
-                created_at: row.get(3)?,
-                text: row.get(4)?,
-            })
-        })
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-
-    Ok(Json(TicketDetail { ticket, comments }))
+    )
+)]
+pub async fn add_comment(
+    State(state): State<AppState>,
+    user: UserContext,
+    Path(ticket_id): Path<i64>,
+    Json(req): Json<CreateCommentRequest>,
+) -> Result<Json<Comment>, ApiError> {
+    user.require_scope(SCOPE_TICKETS_WRITE)?;
+
+    let conn = db_conn(&state.db)?;
+
+    let owner_id: i64 = conn
+        .query_row(
+            "SELECT user_id FROM tickets WHERE id = ?",
+            [ticket_id],
+            |row| row.get(0),
+        )
+        .map_err(|_| ApiError::NotFound("Ticket not found"))?;
+
+    if owner_id != user.user_id {
+        return Err(ApiError::Forbidden(
+            "You can only comment on your own tickets",
+        ));
+    }
+
+    let created_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    conn.execute(
+        "INSERT INTO comments (ticket_id, user_id, created_at, text) VALUES (?, ?, ?, ?)",
+        rusqlite::params![ticket_id, user.user_id, created_at, req.text],
+    )?;
+
+    let id = conn.last_insert_rowid();
+    state.publish(TicketEvent::CommentAdded {
+        ticket_id,
+        comment_id: id,
+        internal: false,
+    });
+
+    Ok(Json(Comment {
+        id,
+        ticket_id,
+        user_id: user.user_id,
+        created_at,
+        text: req.text,
+        attachment: None,
+        internal: false,
+        edited_at: None,
+    }))
 }
@@ -0,0 +1,51 @@
+use axum::{
+    extract::{
+        State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    response::Response,
+};
+use support_common::TicketEvent;
+use tokio::sync::broadcast::{Receiver, error::RecvError};
+use tracing::warn;
+
+use crate::auth::{AdminContext, AppState};
+
+/// Pushes a JSON `TicketEvent` for every ticket change, so dashboards don't
+/// have to poll `/admin/tickets`. Authenticates like any admin route.
+pub async fn ticket_events(
+    State(state): State<AppState>,
+    _admin: AdminContext,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let events = state.events.subscribe();
+    ws.on_upgrade(move |socket| forward_events(socket, events))
+}
+
+async fn forward_events(mut socket: WebSocket, mut events: Receiver<TicketEvent>) {
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    // A slow client misses some events; it can refetch the list
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("WebSocket client fell behind, dropped {} events", missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let json = serde_json::to_string(&event).expect("TicketEvent serializes");
+                if socket.send(Message::Text(json.into())).await.is_err() {
+                    break;
+                }
+            }
+            // Only watched to notice the client going away; its messages are ignored
+            incoming = socket.recv() => {
+                if !matches!(incoming, Some(Ok(_))) {
+                    break;
+                }
+            }
+        }
+    }
+}
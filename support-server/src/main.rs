@@ -1,14 +1,42 @@
 mod auth;
 mod db;
+#[cfg(feature = "docs")]
+mod docs;
+mod error;
 mod handlers;
+mod migrations;
+mod request_id;
+mod storage;
+mod telemetry;
 
 use axum::{
-    Router,
-    routing::{get, post, put},
+    BoxError, Router,
+    error_handling::HandleErrorLayer,
+    extract::DefaultBodyLimit,
+    http::StatusCode,
+    middleware,
+    routing::{delete, get, post, put},
 };
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use tower::ServiceBuilder;
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tracing::Level;
+use tracing_subscriber::EnvFilter;
 
-use auth::{AppState, IdentityClient};
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use auth::{AppState, IdentityClient, IdentityProvider, StaticIdentity};
+
+/// Ticket events kept for WebSocket clients that fall behind
+const EVENT_BUFFER: usize = 256;
+
+/// Allowance for the description and request framing on top of --max-upload-bytes
+const MULTIPART_OVERHEAD_BYTES: usize = 1024 * 1024;
 
 #[derive(Parser)]
 #[command(name = "support-server")]
@@ -30,57 +58,682 @@ struct Args {
     )]
     identity_url: String,
 
+    /// Seconds to wait for the identity service, both to connect and for a
+    /// whole call; slower calls fail with 503
+    #[arg(long, default_value = "5")]
+    identity_timeout_secs: u64,
+
+    /// Shared secret for verifying identity-issued JWTs (or use IDENTITY_JWT_SECRET env var)
+    #[arg(long, env = "IDENTITY_JWT_SECRET")]
+    jwt_secret: Option<String>,
+
     /// Database file path
     #[arg(long, default_value = "support.db")]
     db_path: String,
 
+    /// Use a throwaway in-memory database instead of --db-path
+    #[arg(long)]
+    in_memory: bool,
+
+    /// Accept fixed dev keys (dev_admin, dev_support, dev_customer, dev_readonly) instead of
+    /// calling the identity service
+    #[arg(long)]
+    dev_identity: bool,
+
     /// Port to listen on
     #[arg(long, default_value = "3000")]
     port: u16,
+
+    /// Maximum number of requests handled concurrently; excess requests get 503
+    #[arg(long, default_value = "64")]
+    max_concurrent_requests: usize,
+
+    /// Prefix of customer-facing ticket references, e.g. CD for CD-000042
+    #[arg(long, default_value = "CD")]
+    ticket_ref_prefix: String,
+
+    /// Largest ZIP accepted when creating a ticket; bigger uploads get 413
+    #[arg(long, default_value = "26214400")]
+    max_upload_bytes: usize,
+
+    /// Accept ticket uploads that are not valid ZIP archives
+    #[arg(long)]
+    no_zip_validation: bool,
+
+    /// Admin API key used to have the identity service email customers about
+    /// replies (or use SUPPORT_NOTIFY_API_KEY env var); unset disables notifications
+    #[arg(long, env = "SUPPORT_NOTIFY_API_KEY")]
+    notify_api_key: Option<String>,
+
+    /// Serve /metrics on this port instead of the main one, e.g. to keep it
+    /// off the public interface
+    #[arg(long)]
+    metrics_port: Option<u16>,
+
+    /// Let admins delete only their own comments, not anyone's
+    #[arg(long)]
+    author_only_comment_delete: bool,
+
+    /// Store new ticket ZIPs as files in this directory instead of in the
+    /// database; `migrate-storage` moves the existing ones
+    #[arg(long)]
+    storage_dir: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Verify database integrity and that the encryption key is correct
+    Verify,
+    /// Move ZIPs stored in the database into --storage-dir
+    MigrateStorage,
+}
+
+/// Request logs go to stderr; levels can be tuned with RUST_LOG.
+fn init_logging() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .init();
 }
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
+    init_logging();
 
-    let db = db::init_db(&args.db_path, &args.db_key).expect("Failed to initialize database");
+    match args.command {
+        Some(Command::Verify) => {
+            verify(&args.db_path, &args.db_key);
+            return;
+        }
+        Some(Command::MigrateStorage) => {
+            migrate_storage(&args);
+            return;
+        }
+        None => {}
+    }
+
+    let db_path = if args.in_memory {
+        ":memory:"
+    } else {
+        &args.db_path
+    };
+    let db = db::init_db(db_path, &args.db_key).expect("Failed to initialize database");
+
+    {
+        let conn = db.get().expect("Failed to get database connection");
+        if args.seed {
+            db::seed_db(&conn).expect("Failed to seed database");
+        }
+        db::assign_missing_references(&conn, &args.ticket_ref_prefix)
+            .expect("Failed to assign ticket references");
+    }
+
+    let identity: Arc<dyn IdentityProvider> = if args.dev_identity {
+        Arc::new(StaticIdentity::dev())
+    } else {
+        Arc::new(
+            IdentityClient::new(
+                &args.identity_url,
+                Duration::from_secs(args.identity_timeout_secs),
+            )
+            .expect("Invalid --identity-url")
+            .with_notify_key(args.notify_api_key.clone()),
+        )
+    };
+    let state = AppState {
+        db,
+        identity,
+        jwt_secret: args.jwt_secret.clone(),
+        ticket_ref_prefix: args.ticket_ref_prefix.clone(),
+        max_upload_bytes: args.max_upload_bytes,
+        validate_zips: !args.no_zip_validation,
+        author_only_comment_delete: args.author_only_comment_delete,
+        storage_dir: args.storage_dir.clone(),
+        metrics: telemetry::install(),
+        events: broadcast::channel(EVENT_BUFFER).0,
+    };
+
+    if let Some(port) = args.metrics_port {
+        let metrics_app = Router::new()
+            .route("/metrics", get(telemetry::render))
+            .with_state(state.clone());
+        let addr = format!("0.0.0.0:{}", port);
+        let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+        println!("Metrics on http://{}/metrics", addr);
+        tokio::spawn(async move { axum::serve(listener, metrics_app).await.unwrap() });
+    }
+
+    let app = app(
+        state,
+        args.max_concurrent_requests,
+        args.metrics_port.is_none(),
+    );
 
-    if args.seed {
-        db::seed_db(&db).expect("Failed to seed database");
+    let addr = format!("0.0.0.0:{}", args.port);
+    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    println!("Support server running on http://{}", addr);
+    if args.dev_identity {
+        println!(
+            "Identity service: static dev keys (dev_admin, dev_support, dev_customer, dev_readonly)"
+        );
+    } else {
+        println!("Identity service: {}", args.identity_url);
+    }
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .unwrap();
+    println!("Shut down cleanly");
+}
+
+/// Completes on Ctrl-C or SIGTERM (e.g. `docker stop`), letting `axum::serve`
+/// drain open requests before the process exits.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl-C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
     }
+    println!("Shutting down, waiting for in-flight requests");
+}
 
-    let identity = IdentityClient::new(args.identity_url.clone());
-    let state = AppState { db, identity };
+/// Builds the full router; also usable with `tower::ServiceExt::oneshot` in tests.
+fn app(state: AppState, max_concurrent_requests: usize, serve_metrics: bool) -> Router {
+    // The handlers enforce the ZIP limit themselves; this caps the rest of the
+    // body, allowing for base64 growing the ZIP by a third on the JSON route
+    let upload_limit = DefaultBodyLimit::max(
+        (state.max_upload_bytes / 3 * 4).saturating_add(MULTIPART_OVERHEAD_BYTES),
+    );
 
     let user_routes = Router::new()
-        .route("/tickets", post(handlers::user::create_ticket))
+        .route(
+            "/tickets",
+            post(handlers::user::create_ticket).layer(upload_limit),
+        )
+        .route(
+            "/tickets/json",
+            post(handlers::user::create_ticket_json).layer(upload_limit),
+        )
         .route("/tickets", get(handlers::user::list_tickets))
-        .route("/tickets/{id}", get(handlers::user::get_ticket));
+        .route("/tickets/{id}", get(handlers::user::get_ticket))
+        .route("/tickets/{id}/comments", post(handlers::user::add_comment));
 
-    let admin_routes = Router::new()
+    // Support staff and admins both work tickets (SupportContext)...
+    let support_routes = Router::new()
         .route("/admin/tickets", get(handlers::admin::list_all_tickets))
+        .route(
+            "/admin/tickets/search",
+            get(handlers::admin::search_tickets),
+        )
         .route("/admin/tickets/{id}", get(handlers::admin::get_ticket))
         .route(
             "/admin/tickets/{id}/state",
             put(handlers::admin::update_state),
         )
+        .route("/admin/tickets/{id}/tags", post(handlers::admin::add_tag))
+        .route(
+            "/admin/tickets/{id}/tags/{tag}",
+            delete(handlers::admin::remove_tag),
+        )
         .route(
             "/admin/tickets/{id}/comments",
-            post(handlers::admin::add_comment),
+            post(handlers::admin::add_comment).get(handlers::admin::list_comments),
+        )
+        .route(
+            "/admin/tickets/{id}/comments/attachment",
+            post(handlers::admin::add_comment_with_attachment),
+        )
+        .route(
+            "/admin/tickets/{id}/comments/{comment_id}",
+            get(handlers::admin::get_comment).put(handlers::admin::update_comment),
+        )
+        .route(
+            "/admin/comments/{id}/attachment",
+            get(handlers::admin::download_comment_attachment),
+        );
+
+    // ...but deleting, bulk export and the customers' report archives stay
+    // with admins (AdminContext)
+    let admin_routes = Router::new()
+        .route(
+            "/admin/tickets/export.csv",
+            get(handlers::admin::export_tickets_csv),
+        )
+        .route(
+            "/admin/tickets/{id}",
+            delete(handlers::admin::delete_ticket),
+        )
+        .route(
+            "/admin/tickets/{id}/comments/{comment_id}",
+            delete(handlers::admin::delete_comment),
         )
         .route(
             "/admin/tickets/{id}/zip",
             get(handlers::admin::download_zip),
         );
 
-    let app = Router::new()
+    // Shed requests beyond the limit instead of queueing them on the DB pool.
+    // `Router::layer` wraps every route on its own, so the semaphore is
+    // created here to be shared by all of them.
+    let concurrency_limit = ServiceBuilder::new()
+        .layer(HandleErrorLayer::new(|_: BoxError| async {
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Server busy, try again later",
+            )
+        }))
+        .load_shed()
+        .layer(GlobalConcurrencyLimitLayer::new(max_concurrent_requests));
+
+    let mut router = Router::new()
         .merge(user_routes)
+        .merge(support_routes)
         .merge(admin_routes)
-        .with_state(state);
+        .layer(concurrency_limit)
+        // Outside the limit so shed requests are counted too
+        .layer(middleware::from_fn(telemetry::track_requests))
+        // One span per request, carrying its X-Request-Id
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(request_id::make_span)
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+        // Added after the limit so probes still answer under load
+        .route("/health", get(handlers::health::health))
+        // Long-lived, so it must not hold one of the limited slots
+        .route("/admin/ws", get(handlers::ws::ticket_events));
+    if serve_metrics {
+        router = router.route("/metrics", get(telemetry::render));
+    }
+    #[cfg(feature = "docs")]
+    {
+        router = router.merge(docs::router());
+    }
+    // Outermost, so every response carries an X-Request-Id
+    router
+        .layer(middleware::from_fn(request_id::assign))
+        .with_state(state)
+}
 
-    let addr = format!("0.0.0.0:{}", args.port);
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    println!("Support server running on http://{}", addr);
-    println!("Identity service: {}", args.identity_url);
-    axum::serve(listener, app).await.unwrap();
+fn verify(db_path: &str, db_key: &str) {
+    match db::verify_db(db_path, db_key) {
+        Ok(counts) => {
+            println!("{:<25} Rows", "Table");
+            println!("{}", "-".repeat(35));
+            for (table, count) in counts {
+                println!("{:<25} {}", table, count);
+            }
+            println!("\nDatabase {} OK", db_path);
+        }
+        Err(e) => {
+            eprintln!("Verification of {} failed: {}", db_path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn migrate_storage(args: &Args) {
+    let Some(dir) = &args.storage_dir else {
+        eprintln!("migrate-storage needs --storage-dir");
+        std::process::exit(2);
+    };
+    let db = db::init_db(&args.db_path, &args.db_key).expect("Failed to initialize database");
+    let conn = db.get().expect("Failed to get database connection");
+
+    match storage::migrate_blobs(&conn, dir) {
+        Ok(moved) => println!("Moved {} ZIP(s) to {}", moved, dir.display()),
+        Err(e) => {
+            eprintln!("Storage migration failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{Body, Bytes, to_bytes};
+    use axum::http::{Request, header};
+    use metrics_exporter_prometheus::PrometheusBuilder;
+    use serde_json::{Value, json};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// State on a fresh in-memory database, authenticating with the
+    /// `StaticIdentity` dev keys.
+    fn test_state() -> AppState {
+        AppState {
+            db: db::init_db(":memory:", "test").unwrap(),
+            identity: Arc::new(StaticIdentity::dev()),
+            jwt_secret: None,
+            ticket_ref_prefix: "CD".to_string(),
+            max_upload_bytes: 1024 * 1024,
+            validate_zips: true,
+            author_only_comment_delete: false,
+            storage_dir: None,
+            // Not installed globally, so every test can have its own
+            metrics: PrometheusBuilder::new().build_recorder().handle(),
+            events: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
+
+    fn test_app() -> Router {
+        app(test_state(), 8, false)
+    }
+
+    /// A request body that stays open until the sender is dropped
+    fn held_body() -> (tokio::sync::mpsc::Sender<Bytes>, Body) {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Bytes>(1);
+        let chunks = futures_util::stream::unfold(rx, |mut rx| async move {
+            let chunk = rx.recv().await?;
+            Some((Ok::<_, std::io::Error>(chunk), rx))
+        });
+        (tx, Body::from_stream(chunks))
+    }
+
+    async fn send(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        key: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("x-api-key", key);
+        let request = match body {
+            Some(body) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }
+        .unwrap();
+
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn customer_creates_and_lists_a_ticket() {
+        let app = test_app();
+
+        let (status, ticket) = send(
+            &app,
+            "POST",
+            "/tickets/json",
+            "dev_customer",
+            Some(json!({ "description": "Sync fails" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ticket["description"], "Sync fails");
+        assert_eq!(ticket["reference"], "CD-000001");
+
+        let (status, tickets) = send(&app, "GET", "/tickets", "dev_customer", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(tickets.as_array().unwrap().len(), 1);
+        assert_eq!(tickets[0]["id"], ticket["id"]);
+    }
+
+    async fn create_ticket(app: &Router, description: &str) -> i64 {
+        let (status, ticket) = send(
+            app,
+            "POST",
+            "/tickets/json",
+            "dev_customer",
+            Some(json!({ "description": description })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        ticket["id"].as_i64().unwrap()
+    }
+
+    #[tokio::test]
+    async fn ticket_is_found_by_id_or_reference() {
+        let app = test_app();
+        create_ticket(&app, "Other").await;
+        let id = create_ticket(&app, "Sync fails").await;
+
+        for path in [
+            id.to_string(),
+            "CD-000002".to_string(),
+            "cd-000002".to_string(),
+        ] {
+            let (status, detail) = send(
+                &app,
+                "GET",
+                &format!("/tickets/{}", path),
+                "dev_customer",
+                None,
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK, "{}", path);
+            assert_eq!(detail["id"], id);
+            assert_eq!(detail["description"], "Sync fails");
+        }
+
+        let (status, _) = send(&app, "GET", "/tickets/CD-000099", "dev_customer", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn comment_is_only_found_on_its_own_ticket() {
+        let app = test_app();
+        let first = create_ticket(&app, "First").await;
+        let second = create_ticket(&app, "Second").await;
+
+        let (status, comment) = send(
+            &app,
+            "POST",
+            &format!("/admin/tickets/{}/comments", first),
+            "dev_admin",
+            Some(json!({ "text": "Looking into it" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let comment_id = comment["id"].as_i64().unwrap();
+
+        let (status, body) = send(
+            &app,
+            "GET",
+            &format!("/admin/tickets/{}/comments/{}", first, comment_id),
+            "dev_admin",
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["text"], "Looking into it");
+
+        let (status, body) = send(
+            &app,
+            "GET",
+            &format!("/admin/tickets/{}/comments/{}", second, comment_id),
+            "dev_admin",
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "Comment not found");
+    }
+
+    #[tokio::test]
+    async fn read_only_key_cannot_write() {
+        let app = test_app();
+        let ticket = create_ticket(&app, "Existing").await;
+
+        let writes = [
+            (
+                "POST",
+                "/tickets/json".to_string(),
+                json!({ "description": "New" }),
+            ),
+            (
+                "POST",
+                format!("/tickets/{}/comments", ticket),
+                json!({ "text": "Hello" }),
+            ),
+            (
+                "PUT",
+                format!("/admin/tickets/{}/state", ticket),
+                json!({ "state": "in_progress" }),
+            ),
+        ];
+        for (method, uri, body) in writes {
+            let (status, error) = send(&app, method, &uri, "dev_readonly", Some(body)).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{} {}", method, uri);
+            assert_eq!(error["error"], "API key lacks the scope for this action");
+        }
+
+        // The multipart upload is checked before its body is read
+        let request = Request::post("/tickets")
+            .header("x-api-key", "dev_readonly")
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=X")
+            .body(Body::from(
+                "--X\r\nContent-Disposition: form-data; name=\"description\"\r\n\r\nNew\r\n--X--\r\n",
+            ))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Reading is still allowed
+        let (status, _) = send(
+            &app,
+            "GET",
+            &format!("/admin/tickets/{}", ticket),
+            "dev_readonly",
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    /// Posts a comment with `data` attached as `log.txt` through the multipart route
+    async fn upload_attachment(app: &Router, ticket: i64, data: &[u8]) -> (StatusCode, Value) {
+        let mut body = b"--X\r\nContent-Disposition: form-data; name=\"text\"\r\n\r\nSee log\r\n\
+            --X\r\nContent-Disposition: form-data; name=\"file\"; filename=\"log.txt\"\r\n\r\n"
+            .to_vec();
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n--X--\r\n");
+        let request = Request::post(format!("/admin/tickets/{}/comments/attachment", ticket))
+            .header("x-api-key", "dev_admin")
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=X")
+            .body(Body::from(body))
+            .unwrap();
+
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    #[tokio::test]
+    async fn comment_attachment_round_trips() {
+        let app = test_app();
+        let ticket = create_ticket(&app, "Crash on start").await;
+
+        let (status, comment) = upload_attachment(&app, ticket, b"line 1\nline 2\n").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(comment["attachment"], "log.txt");
+
+        let request = Request::get(format!("/admin/comments/{}/attachment", comment["id"]))
+            .header("x-api-key", "dev_admin")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"log.txt\""
+        );
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&bytes[..], b"line 1\nline 2\n");
+    }
+
+    #[tokio::test]
+    async fn oversized_comment_attachment_is_rejected() {
+        let app = test_app();
+        let ticket = create_ticket(&app, "Crash on start").await;
+
+        let (status, body) = upload_attachment(&app, ticket, &vec![b'x'; 300 * 1024]).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["error"], "Attachment exceeds 256 KiB");
+
+        let (_, detail) = send(
+            &app,
+            "GET",
+            &format!("/admin/tickets/{}", ticket),
+            "dev_admin",
+            None,
+        )
+        .await;
+        assert_eq!(detail["comments"].as_array().unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_concurrency_limit_are_shed() {
+        let app = app(test_state(), 1, false);
+
+        // Holds the only slot while the handler waits for the rest of the upload
+        let (tx, body) = held_body();
+        let request = Request::post("/tickets")
+            .header("x-api-key", "dev_customer")
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=X")
+            .body(body)
+            .unwrap();
+        let held = tokio::spawn(app.clone().oneshot(request));
+        tx.send(Bytes::from_static(b"--X\r\n")).await.unwrap();
+        // Capacity is back once the handler has read the first chunk
+        drop(tx.reserve().await.unwrap());
+
+        let request = Request::get("/tickets")
+            .header("x-api-key", "dev_customer")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        drop(tx);
+        let response = held.await.unwrap().unwrap();
+        assert_ne!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let (status, _) = send(&app, "GET", "/tickets", "dev_customer", None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn unknown_key_is_rejected() {
+        let app = test_app();
+
+        let (status, body) = send(&app, "GET", "/tickets", "nope", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "Invalid API key");
+    }
 }
@@ -0,0 +1,284 @@
+//! Schema migrations, tracked in `PRAGMA user_version`.
+//!
+//! Append new migrations to `MIGRATIONS`; never edit or reorder one that has
+//! shipped. Version N means the first N migrations have been applied.
+
+use rusqlite::{Connection, Result, ffi};
+
+type Migration = fn(&Connection) -> Result<()>;
+
+const MIGRATIONS: &[Migration] = &[
+    initial_schema,
+    add_search_index,
+    add_internal_comments,
+    add_comment_edited_at,
+    add_zip_path,
+    add_tags,
+    add_ticket_updated_at,
+];
+
+/// Brings the database up to the latest schema version. Each migration runs in
+/// its own transaction together with the version bump.
+pub fn migrate(conn: &mut Connection) -> Result<()> {
+    let current: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    let current = current as usize;
+    if current > MIGRATIONS.len() {
+        return Err(rusqlite::Error::SqliteFailure(
+            ffi::Error::new(ffi::SQLITE_ERROR),
+            Some(format!(
+                "Database schema version {} is newer than this build supports ({})",
+                current,
+                MIGRATIONS.len()
+            )),
+        ));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+        let tx = conn.transaction()?;
+        migration(&tx)?;
+        tx.pragma_update(None, "user_version", (index + 1) as i64)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Schema as of the introduction of migrations. Databases created before then
+/// are at version 0 but may already have some or all of this, so it has to be
+/// idempotent; later migrations need not be.
+fn initial_schema(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS tickets (
+            id INTEGER PRIMARY KEY,
+            user_id INTEGER NOT NULL,
+            created_at INTEGER NOT NULL,
+            description TEXT NOT NULL,
+            zip_data BLOB NOT NULL,
+            zip_filename TEXT NOT NULL,
+            state TEXT NOT NULL DEFAULT 'new',
+            reference TEXT,
+            priority TEXT NOT NULL DEFAULT 'normal'
+        );
+
+        CREATE TABLE IF NOT EXISTS comments (
+            id INTEGER PRIMARY KEY,
+            ticket_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            created_at INTEGER NOT NULL,
+            text TEXT NOT NULL,
+            FOREIGN KEY (ticket_id) REFERENCES tickets(id)
+        );
+
+        -- At most one small file per comment
+        CREATE TABLE IF NOT EXISTS comment_attachments (
+            comment_id INTEGER PRIMARY KEY,
+            filename TEXT NOT NULL,
+            data BLOB NOT NULL,
+            FOREIGN KEY (comment_id) REFERENCES comments(id)
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_tickets_user_id ON tickets(user_id);
+        CREATE INDEX IF NOT EXISTS idx_comments_ticket_id ON comments(ticket_id);
+        ",
+    )?;
+
+    // Columns added before migrations existed, missing on the oldest databases
+    add_column_if_missing(conn, "tickets", "reference", "TEXT")?;
+    add_column_if_missing(
+        conn,
+        "tickets",
+        "priority",
+        "TEXT NOT NULL DEFAULT 'normal'",
+    )?;
+    conn.execute_batch(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_tickets_reference ON tickets(reference);",
+    )
+}
+
+/// FTS5 index over ticket descriptions and comment texts for
+/// `/admin/tickets/search`, kept in sync by triggers
+fn add_search_index(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE VIRTUAL TABLE tickets_fts USING fts5(
+            description, content='tickets', content_rowid='id'
+        );
+        CREATE VIRTUAL TABLE comments_fts USING fts5(
+            text, content='comments', content_rowid='id'
+        );
+
+        CREATE TRIGGER tickets_fts_insert AFTER INSERT ON tickets BEGIN
+            INSERT INTO tickets_fts(rowid, description) VALUES (new.id, new.description);
+        END;
+        CREATE TRIGGER tickets_fts_delete AFTER DELETE ON tickets BEGIN
+            INSERT INTO tickets_fts(tickets_fts, rowid, description)
+            VALUES ('delete', old.id, old.description);
+        END;
+        CREATE TRIGGER tickets_fts_update AFTER UPDATE OF description ON tickets BEGIN
+            INSERT INTO tickets_fts(tickets_fts, rowid, description)
+            VALUES ('delete', old.id, old.description);
+            INSERT INTO tickets_fts(rowid, description) VALUES (new.id, new.description);
+        END;
+
+        CREATE TRIGGER comments_fts_insert AFTER INSERT ON comments BEGIN
+            INSERT INTO comments_fts(rowid, text) VALUES (new.id, new.text);
+        END;
+        CREATE TRIGGER comments_fts_delete AFTER DELETE ON comments BEGIN
+            INSERT INTO comments_fts(comments_fts, rowid, text) VALUES ('delete', old.id, old.text);
+        END;
+        CREATE TRIGGER comments_fts_update AFTER UPDATE OF text ON comments BEGIN
+            INSERT INTO comments_fts(comments_fts, rowid, text) VALUES ('delete', old.id, old.text);
+            INSERT INTO comments_fts(rowid, text) VALUES (new.id, new.text);
+        END;
+
+        -- Index what is already there
+        INSERT INTO tickets_fts(tickets_fts) VALUES ('rebuild');
+        INSERT INTO comments_fts(comments_fts) VALUES ('rebuild');
+        ",
+    )
+}
+
+/// Internal notes are comments only admins get to see.
+fn add_internal_comments(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE comments ADD COLUMN internal BOOLEAN NOT NULL DEFAULT 0;")
+}
+
+fn add_comment_edited_at(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE comments ADD COLUMN edited_at INTEGER;")
+}
+
+/// Set for tickets whose ZIP lives under `--storage-dir`; their `zip_data` is
+/// then empty
+fn add_zip_path(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE tickets ADD COLUMN zip_path TEXT;")
+}
+
+/// Free-form labels agents put on tickets; a tag row is shared by all its tickets
+fn add_tags(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE tags (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE
+        );
+
+        CREATE TABLE ticket_tags (
+            ticket_id INTEGER NOT NULL,
+            tag_id INTEGER NOT NULL,
+            PRIMARY KEY (ticket_id, tag_id),
+            FOREIGN KEY (ticket_id) REFERENCES tickets(id),
+            FOREIGN KEY (tag_id) REFERENCES tags(id)
+        );
+
+        CREATE INDEX idx_ticket_tags_tag_id ON ticket_tags(tag_id);
+        ",
+    )
+}
+
+/// Last activity on a ticket, for sorting the admin list. Triggers keep it
+/// current: new tickets start at `created_at`, state changes and new comments
+/// bump it.
+fn add_ticket_updated_at(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        ALTER TABLE tickets ADD COLUMN updated_at INTEGER;
+        UPDATE tickets SET updated_at = created_at;
+        CREATE INDEX idx_tickets_updated_at ON tickets(updated_at);
+
+        CREATE TRIGGER tickets_init_updated_at AFTER INSERT ON tickets
+        WHEN new.updated_at IS NULL BEGIN
+            UPDATE tickets SET updated_at = new.created_at WHERE id = new.id;
+        END;
+        CREATE TRIGGER tickets_state_updated_at AFTER UPDATE OF state ON tickets
+        WHEN new.state IS NOT old.state BEGIN
+            UPDATE tickets SET updated_at = CAST(strftime('%s', 'now') AS INTEGER)
+            WHERE id = new.id;
+        END;
+        CREATE TRIGGER comments_ticket_updated_at AFTER INSERT ON comments BEGIN
+            UPDATE tickets SET updated_at = new.created_at WHERE id = new.ticket_id;
+        END;
+        ",
+    )
+}
+
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<()> {
+    let exists = conn
+        .prepare(&format!(
+            "SELECT 1 FROM pragma_table_info('{}') WHERE name = ?",
+            table
+        ))?
+        .exists([column])?;
+    if !exists {
+        conn.execute_batch(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {};",
+            table, column, definition
+        ))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        conn.prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap()
+    }
+
+    fn user_version(conn: &Connection) -> i64 {
+        conn.pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn empty_database_reaches_latest_schema() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        migrate(&mut conn).unwrap();
+
+        assert_eq!(user_version(&conn), MIGRATIONS.len() as i64);
+        let expected = [
+            ("tickets", "reference"),
+            ("tickets", "priority"),
+            ("tickets", "zip_path"),
+            ("tickets", "updated_at"),
+            ("comments", "internal"),
+            ("comments", "edited_at"),
+            ("comment_attachments", "filename"),
+            ("tags", "name"),
+            ("ticket_tags", "tag_id"),
+        ];
+        for (table, column) in expected {
+            assert!(
+                columns(&conn, table).iter().any(|c| c == column),
+                "{}.{} missing",
+                table,
+                column
+            );
+        }
+
+        // Running again is a no-op
+        migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len() as i64);
+    }
+
+    #[test]
+    fn newer_schema_is_rejected() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() as i64 + 1)
+            .unwrap();
+
+        let error = migrate(&mut conn).unwrap_err();
+        assert!(error.to_string().contains("newer than this build supports"));
+    }
+}
@@ -0,0 +1,71 @@
+//! `X-Request-Id` on every request: taken from the client if it sent a sane
+//! one, otherwise generated. It is echoed in the response header, recorded on
+//! the request's log span and added to error bodies, so a customer can quote it
+//! and we can find the matching log lines.
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use tracing::Span;
+use uuid::Uuid;
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longer client-supplied ids are replaced, so they can't bloat the logs
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+tokio::task_local! {
+    /// Set while a request is handled, for `ApiError` to read
+    static CURRENT: String;
+}
+
+/// The id of the request being handled, stored as a request extension.
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+/// Outermost middleware: assigns the id and echoes it on the response.
+pub async fn assign(mut req: Request, next: Next) -> Response {
+    let id = req
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    req.extensions_mut().insert(RequestId(id.clone()));
+
+    let mut response = CURRENT.scope(id.clone(), next.run(req)).await;
+    let value = HeaderValue::from_str(&id).expect("request id is visible ASCII");
+    response.headers_mut().insert(X_REQUEST_ID, value);
+    response
+}
+
+/// The current request's id, or `None` outside of a request.
+pub fn current() -> Option<String> {
+    CURRENT.try_with(Clone::clone).ok()
+}
+
+/// Span for `TraceLayer`, so every log line of a request carries its id.
+pub fn make_span(req: &Request) -> Span {
+    let id = req
+        .extensions()
+        .get::<RequestId>()
+        .map_or("", |id| id.0.as_str());
+    tracing::info_span!(
+        "request",
+        request_id = %id,
+        method = %req.method(),
+        uri = %req.uri(),
+    )
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
@@ -0,0 +1,71 @@
+//! Ticket ZIPs kept as files under `--storage-dir` instead of inside the
+//! database. Tickets with a `zip_path` are stored this way; all others still
+//! have their ZIP in the `zip_data` blob.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use rusqlite::{Connection, OptionalExtension};
+use tracing::warn;
+use uuid::Uuid;
+
+/// Writes `data` to a new `{uuid}.zip` in `dir` and returns its path.
+pub fn write_zip(dir: &Path, data: &[u8]) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{}.zip", Uuid::new_v4()));
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    Ok(path)
+}
+
+/// Removes a stored ZIP, only logging failures: an orphaned file is better than
+/// failing the request that made it unreferenced.
+pub fn remove_zip(path: &str) {
+    if let Err(e) = fs::remove_file(path) {
+        warn!("Failed to remove stored ZIP {}: {}", path, e);
+    }
+}
+
+/// Moves every ZIP still held as a blob into `dir` and returns how many were
+/// moved. Each ticket is switched over on its own, so an interrupted run can
+/// simply be repeated.
+pub fn migrate_blobs(conn: &Connection, dir: &Path) -> Result<usize, String> {
+    let mut moved = 0;
+    loop {
+        let next: Option<(i64, Vec<u8>)> = conn
+            .query_row(
+                "SELECT id, zip_data FROM tickets WHERE zip_path IS NULL ORDER BY id LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        let Some((id, data)) = next else {
+            break;
+        };
+
+        let path = write_zip(dir, &data)
+            .map_err(|e| format!("Failed to write ZIP of ticket {}: {}", id, e))?;
+        let path = path.to_string_lossy().into_owned();
+
+        if let Err(e) = conn.execute(
+            "UPDATE tickets SET zip_path = ?, zip_data = X'' WHERE id = ?",
+            rusqlite::params![path, id],
+        ) {
+            remove_zip(&path);
+            return Err(e.to_string());
+        }
+        moved += 1;
+    }
+
+    // Emptied blobs only free pages; rewrite the file so it actually shrinks
+    if moved > 0 {
+        conn.execute_batch("VACUUM;").map_err(|e| e.to_string())?;
+    }
+    Ok(moved)
+}
@@ -0,0 +1,92 @@
+//! Prometheus metrics: request counts and latencies per route, plus ticket
+//! counts per state that are refreshed on every scrape.
+
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use metrics::{counter, gauge, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use support_common::TicketState;
+use tracing::warn;
+
+use crate::auth::AppState;
+
+/// Latency histogram buckets in seconds; ZIP uploads land in the upper ones
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Installs the global metrics recorder. Call once at startup.
+pub fn install() -> PrometheusHandle {
+    PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full("http_request_duration_seconds".into()),
+            LATENCY_BUCKETS,
+        )
+        .expect("latency buckets are not empty")
+        .install_recorder()
+        .expect("Failed to install metrics recorder")
+}
+
+/// Counts requests and records their latency, labelled by the matched route
+/// (not the raw path, which would create a series per ticket id).
+pub async fn track_requests(req: Request, next: Next) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = req.method().to_string();
+    let start = Instant::now();
+
+    let response = next.run(req).await;
+
+    counter!(
+        "http_requests_total",
+        "method" => method.clone(),
+        "route" => route.clone(),
+        "status" => response.status().as_u16().to_string(),
+    )
+    .increment(1);
+    histogram!(
+        "http_request_duration_seconds",
+        "method" => method,
+        "route" => route,
+    )
+    .record(start.elapsed().as_secs_f64());
+
+    response
+}
+
+/// `GET /metrics` in the Prometheus text format. If the ticket counts can't be
+/// read, the previous values are reported rather than failing the scrape.
+pub async fn render(State(state): State<AppState>) -> String {
+    match count_tickets_by_state(&state) {
+        Ok(counts) => {
+            for ticket_state in TicketState::ALL {
+                let count = counts
+                    .iter()
+                    .find(|(s, _)| s == ticket_state.as_str())
+                    .map_or(0, |(_, n)| *n);
+                gauge!("support_tickets", "state" => ticket_state.as_str()).set(count as f64);
+            }
+        }
+        Err(e) => warn!("Failed to count tickets for metrics: {}", e),
+    }
+
+    state.metrics.render()
+}
+
+fn count_tickets_by_state(state: &AppState) -> Result<Vec<(String, i64)>, String> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare("SELECT state, COUNT(*) FROM tickets GROUP BY state")
+        .map_err(|e| e.to_string())?;
+    stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .and_then(|rows| rows.collect())
+        .map_err(|e| e.to_string())
+}